@@ -9,11 +9,14 @@ use crate::common::messages as msg;
 use crate::common::types;
 
 use crate::settings::Settings;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use self::image::ImageFormat;
+use std::fs;
 use std::io;
+use std::io::Read;
 use std::net::ToSocketAddrs;
+use std::path::Path;
 use std::sync;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
@@ -22,13 +25,94 @@ use std::thread;
 use std::net::TcpStream;
 use stoppable_thread as st_thread;
 
+/// Event received from a video stream.
+pub enum VideoEvent {
+    Frame(types::VideoFrame),
+    /// Current stream parameters and adjustable controls of a camera.
+    Capabilities(usize, types::CameraCapabilities),
+    /// Camera problem reported by the server, `None` when it's resolved.
+    Status(usize, Option<String>),
+    Snapshot(Result<msg::Snapshot, String>),
+    /// Clip recorded on the server, `None` when the recording is stopped.
+    Recording(usize, Result<Option<String>, String>),
+    /// Autonomous mode driving the machine, `None` when it's stopped.
+    Autopilot(usize, Option<String>),
+}
+
+/// How often a lost video connection is opened again.
+const VIDEO_RECONNECT_INTERVAL_MS: u64 = 1000;
+
+/// Opens the video connection of a camera, again when it's lost.
+struct VideoConnector {
+    addr: std::net::SocketAddr,
+    session_id: String,
+    camera: String,
+    settings: Settings,
+    conn_timeout: u64,
+    read_timeout: u64,
+}
+
+impl VideoConnector {
+    fn open(&self) -> Result<(TcpStream, types::Orientation), io::Error> {
+        let mut stream =
+            TcpStream::connect_timeout(&self.addr, Duration::from_millis(self.conn_timeout))?;
+
+        stream.set_nodelay(true)?;
+        if self.addr.is_ipv4() {
+            stream.set_ttl(5)?;
+        }
+        stream.set_read_timeout(Some(Duration::from_millis(self.read_timeout)))?;
+
+        debug!("Sending open video message for {} camera...", self.camera);
+        let mut video_settings = self.settings.video.clone();
+        video_settings.camera = self.camera.clone();
+        let open_session_msg = &msg::RequestConnection {
+            token: self.settings.connection.token.clone(),
+            session_id: Some(self.session_id.clone()),
+            conn_type: msg::ConnectionType::Video(video_settings),
+        };
+        stream.write_msg(open_session_msg)?;
+
+        debug!("Sended open video message. Waiting for a response...");
+        let open_video_resp = stream.read_msg::<msg::OpenVideoConnection>(&mut vec![])?;
+        if !open_video_resp.ok {
+            let err_msg = open_video_resp.error.unwrap_or("Unknown".to_string());
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Failed to open video stream. {}", err_msg),
+            ))
+        } else {
+            debug!(
+                "Video connection is opened. Orientation: {:?}",
+                open_video_resp.orientation
+            );
+            Ok((stream, open_video_resp.orientation))
+        }
+    }
+
+    /// Open the connection again until it succeeds or `stopped` returns `true`.
+    fn reopen(&self, stopped: &dyn Fn() -> bool) -> Option<(TcpStream, types::Orientation)> {
+        while !stopped() {
+            thread::sleep(Duration::from_millis(VIDEO_RECONNECT_INTERVAL_MS));
+            match self.open() {
+                Ok(res) => return Some(res),
+                Err(e) => warn!("Unable to reopen {} camera connection: {}", self.camera, e),
+            }
+        }
+        None
+    }
+}
+
 pub struct Session {
     main_conn: Option<TcpStream>,
     conn_timeout: u64,
     read_timeout: u64,
     settings: Settings,
 
-    pub video_rx: Arc<Mutex<Option<mpsc::Receiver<types::VideoFrame>>>>,
+    pub loss_policy: Option<types::LossPolicy>,
+    pub video_rx: Arc<Mutex<Option<mpsc::Receiver<VideoEvent>>>>,
+    /// Decoded frames sent to the video receiver and not taken out yet.
+    video_queue: Arc<sync::atomic::AtomicUsize>,
     pub state_conn: Arc<Mutex<Option<TcpStream>>>,
 
     is_connected: sync::Arc<sync::atomic::AtomicBool>,
@@ -39,8 +123,10 @@ impl Session {
     pub fn new(settings: Settings) -> Self {
         Session {
             main_conn: None,
+            loss_policy: None,
             state_conn: Arc::new(Mutex::new(None)),
             video_rx: Arc::new(Mutex::new(None)),
+            video_queue: Arc::new(sync::atomic::AtomicUsize::default()),
             conn_timeout: 1000,
             read_timeout: 1000,
             settings: settings,
@@ -53,8 +139,10 @@ impl Session {
         &mut self,
     ) -> Result<
         (
-            mpsc::Receiver<types::VideoFrame>,
-            mpsc::Sender<types::MachineState>,
+            mpsc::Receiver<VideoEvent>,
+            mpsc::Sender<msg::Control>,
+            mpsc::Receiver<msg::Telemetry>,
+            Vec<String>,
         ),
         io::Error,
     > {
@@ -68,19 +156,48 @@ impl Session {
             info!("Connecting to {:?}...", addr);
 
             match self.open_session(&addr) {
-                Ok((stream, session_id)) => {
+                Ok((stream, session_id, loss_policy)) => {
                     info!("Opened session with ID {} on {}", session_id, addr);
+                    match loss_policy {
+                        Some(policy) => info!("Connection loss policy: {}", policy),
+                        None => warn!("Server did not report a connection loss policy"),
+                    }
+                    self.loss_policy = loss_policy;
+                    self.stream_heartbeat(stream.try_clone()?);
                     self.main_conn = Some(stream);
 
-                    info!("Connecting to the video stream...");
-                    let video_receiver = match self.open_video_connection(&addr, session_id.clone())
-                    {
-                        Ok(stream) => self.stream_video(stream),
-                        Err(e) => {
-                            error!("Unable to open video connection: {}", e);
-                            return Err(e);
+                    info!("Connecting to the video streams...");
+                    let (video_sender, video_receiver): (
+                        mpsc::Sender<VideoEvent>,
+                        mpsc::Receiver<VideoEvent>,
+                    ) = mpsc::channel();
+                    // Streams are indexed by the connected cameras, which are reported
+                    // to the caller.
+                    let mut connected = vec![];
+                    let cameras = self.settings.video.cameras.clone();
+                    for (index, camera) in cameras.iter().enumerate() {
+                        let connector = self.video_connector(&addr, session_id.clone(), camera);
+                        match connector.open() {
+                            Ok((stream, orientation)) => {
+                                self.stream_video(
+                                    stream,
+                                    connected.len(),
+                                    orientation,
+                                    connector,
+                                    video_sender.clone(),
+                                );
+                                connected.push(camera.clone());
+                            }
+                            // Only the main camera is required.
+                            Err(e) if index == 0 => {
+                                error!("Unable to open video connection: {}", e);
+                                return Err(e);
+                            }
+                            Err(e) => {
+                                warn!("Unable to open {} camera connection: {}", camera, e);
+                            }
                         }
-                    };
+                    }
 
                     info!("Connecting to the control stream...");
                     let control_sender =
@@ -92,10 +209,25 @@ impl Session {
                             }
                         };
 
+                    info!("Connecting to the telemetry stream...");
+                    let telemetry_receiver =
+                        match self.open_telemetry_connection(&addr, session_id.clone()) {
+                            Ok(stream) => self.stream_telemetry(stream),
+                            Err(e) => {
+                                error!("Unable to open telemetry connection: {}", e);
+                                return Err(e);
+                            }
+                        };
+
                     self.is_connected
                         .clone()
                         .store(false, sync::atomic::Ordering::Relaxed);
-                    return Ok((video_receiver, control_sender));
+                    return Ok((
+                        video_receiver,
+                        control_sender,
+                        telemetry_receiver,
+                        connected,
+                    ));
                 }
                 Err(e) => {
                     if e.kind() == io::ErrorKind::TimedOut
@@ -119,7 +251,10 @@ impl Session {
         ))
     }
 
-    fn open_session(&self, addr: &std::net::SocketAddr) -> Result<(TcpStream, String), io::Error> {
+    fn open_session(
+        &self,
+        addr: &std::net::SocketAddr,
+    ) -> Result<(TcpStream, String, Option<types::LossPolicy>), io::Error> {
         let mut stream =
             TcpStream::connect_timeout(&addr, Duration::from_millis(self.conn_timeout))?;
         stream.set_nodelay(true)?;
@@ -143,7 +278,7 @@ impl Session {
         debug!("Received open session response.");
         if open_session_resp.ok {
             match open_session_resp.session_id {
-                Some(session_id) => Ok((stream, session_id)),
+                Some(session_id) => Ok((stream, session_id, open_session_resp.loss_policy)),
                 None => Err(io::Error::new(
                     io::ErrorKind::Other,
                     "Response missing session ID",
@@ -158,7 +293,23 @@ impl Session {
         }
     }
 
-    fn open_video_connection(
+    fn video_connector(
+        &self,
+        addr: &std::net::SocketAddr,
+        session_id: String,
+        camera: &String,
+    ) -> VideoConnector {
+        VideoConnector {
+            addr: *addr,
+            session_id: session_id,
+            camera: camera.clone(),
+            settings: self.settings.clone(),
+            conn_timeout: self.conn_timeout,
+            read_timeout: self.read_timeout,
+        }
+    }
+
+    pub fn open_control_connection(
         &self,
         addr: &std::net::SocketAddr,
         session_id: String,
@@ -172,29 +323,29 @@ impl Session {
         }
         stream.set_read_timeout(Some(Duration::from_millis(self.read_timeout)))?;
 
-        debug!("Sending open video message...");
+        debug!("Sending open control message...");
         let open_session_msg = &msg::RequestConnection {
             token: self.settings.connection.token.clone(),
             session_id: Some(session_id),
-            conn_type: msg::ConnectionType::Video(self.settings.video.clone()),
+            conn_type: msg::ConnectionType::Controller(self.settings.controller.clone()),
         };
         stream.write_msg(open_session_msg)?;
 
-        debug!("Sended open video message. Waiting for a response...");
-        let open_video_resp = stream.read_msg::<msg::OpenVideoConnection>(&mut vec![])?;
-        if !open_video_resp.ok {
-            let err_msg = open_video_resp.error.unwrap_or("Unknown".to_string());
+        debug!("Sended open controller message. Waiting for a response...");
+        let open_controller_resp = stream.read_msg::<msg::OpenControllerConnection>(&mut vec![])?;
+        if !open_controller_resp.ok {
+            let err_msg = open_controller_resp.error.unwrap_or("Unknown".to_string());
             Err(io::Error::new(
                 io::ErrorKind::Other,
-                format!("Failed to open video stream. {}", err_msg),
+                format!("Failed to open controller stream. {}", err_msg),
             ))
         } else {
-            debug!("Video connection is opened.");
+            debug!("Controller connection is opened.");
             Ok(stream)
         }
     }
 
-    pub fn open_control_connection(
+    pub fn open_telemetry_connection(
         &self,
         addr: &std::net::SocketAddr,
         session_id: String,
@@ -208,51 +359,89 @@ impl Session {
         }
         stream.set_read_timeout(Some(Duration::from_millis(self.read_timeout)))?;
 
-        debug!("Sending open control message...");
+        debug!("Sending open telemetry message...");
         let open_session_msg = &msg::RequestConnection {
             token: self.settings.connection.token.clone(),
             session_id: Some(session_id),
-            conn_type: msg::ConnectionType::Controller(self.settings.controller.clone()),
+            conn_type: msg::ConnectionType::Telemetry(self.settings.telemetry.clone()),
         };
         stream.write_msg(open_session_msg)?;
 
-        debug!("Sended open controller message. Waiting for a response...");
-        let open_controller_resp = stream.read_msg::<msg::OpenControllerConnection>(&mut vec![])?;
-        if !open_controller_resp.ok {
-            let err_msg = open_controller_resp.error.unwrap_or("Unknown".to_string());
+        debug!("Sended open telemetry message. Waiting for a response...");
+        let open_telemetry_resp = stream.read_msg::<msg::OpenTelemetryConnection>(&mut vec![])?;
+        if !open_telemetry_resp.ok {
+            let err_msg = open_telemetry_resp.error.unwrap_or("Unknown".to_string());
             Err(io::Error::new(
                 io::ErrorKind::Other,
-                format!("Failed to open controller stream. {}", err_msg),
+                format!("Failed to open telemetry stream. {}", err_msg),
             ))
         } else {
-            debug!("Controller connection is opened.");
+            debug!("Telemetry connection is opened.");
             Ok(stream)
         }
     }
 
-    pub fn set_connection(&mut self, rx: Option<mpsc::Receiver<types::VideoFrame>>) {
+    /// Counter of queued video frames. The receiver of frames must decrement it.
+    pub fn video_queue(&self) -> Arc<sync::atomic::AtomicUsize> {
+        self.video_queue.clone()
+    }
+
+    pub fn set_connection(&mut self, rx: Option<mpsc::Receiver<VideoEvent>>) {
         let receiver = self.video_rx.clone();
 
         let mut value = receiver.try_lock().unwrap();
         *value = rx;
     }
 
-    fn stream_video(&mut self, mut stream: TcpStream) -> mpsc::Receiver<types::VideoFrame> {
-        let (video_sender, video_receiver): (
-            mpsc::Sender<types::VideoFrame>,
-            mpsc::Receiver<types::VideoFrame>,
-        ) = mpsc::channel();
-
+    fn stream_video(
+        &mut self,
+        mut stream: TcpStream,
+        camera: usize,
+        mut orientation: types::Orientation,
+        connector: VideoConnector,
+        video_sender: mpsc::Sender<VideoEvent>,
+    ) {
+        let video_queue = self.video_queue.clone();
         let video_thread = st_thread::spawn(move |stopped| {
+            let mut data = vec![];
+            let mut frames_received: u64 = 0;
+            // Error which left the connection unusable.
+            let mut lost: Option<io::Error> = None;
             while !stopped.get() {
-                match stream.read_msg::<msg::VideoFrame>(&mut vec![]) {
-                    Ok(frame) => {
-                        match image::load_from_memory_with_format(&frame.data, ImageFormat::Jpeg) {
+                if let Some(e) = lost.take() {
+                    warn!("Camera {} video is lost: {:?}. Reconnecting...", camera, e);
+                    let status = format!("Camera {} video is lost: {}", camera, e);
+                    let _ = video_sender.send(VideoEvent::Status(camera, Some(status)));
+                    if let Some(res) = connector.reopen(&|| stopped.get()) {
+                        stream = res.0;
+                        orientation = res.1;
+                        frames_received = 0;
+                        let _ = video_sender.send(VideoEvent::Status(camera, None));
+                    }
+                    continue;
+                }
+                match stream.read_msg::<msg::Video>(&mut vec![]) {
+                    Ok(msg::Video::Frame(frame)) => {
+                        // Frame data follows the header outside of the message.
+                        data.resize(frame.size as usize, 0);
+                        if let Err(e) = stream.read_exact(&mut data) {
+                            // The rest of the payload would be read as messages.
+                            lost = Some(e);
+                            continue;
+                        }
+                        frames_received += 1;
+
+                        let decode_start = Instant::now();
+                        match image::load_from_memory_with_format(&data, ImageFormat::Jpeg) {
                             Ok(img) => {
-                                match video_sender.send(types::VideoFrame {
-                                    image: img.rotate90().to_rgb(),
+                                let image = orientation.apply(img).to_rgb();
+                                video_queue.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                                match video_sender.send(VideoEvent::Frame(types::VideoFrame {
+                                    camera: camera,
+                                    image: image,
                                     timestamp_ms: frame.timestamp_ms,
-                                }) {
+                                    annotations: frame.annotations,
+                                })) {
                                     Ok(_) => {}
                                     Err(e) => {
                                         warn!("Failed to process a frame: {:?}", e);
@@ -261,23 +450,100 @@ impl Session {
                             }
                             Err(e) => warn!("Failed to decode a frame: {:?}", e),
                         }
+
+                        let feedback = msg::VideoFeedback {
+                            frames_received: frames_received,
+                            last_timestamp_ms: frame.timestamp_ms,
+                            decode_ms: decode_start.elapsed().as_millis() as u32,
+                            queue_depth: video_queue.load(sync::atomic::Ordering::Relaxed) as u32,
+                        };
+                        if let Err(e) = stream.write_msg(&feedback) {
+                            warn!("Failed to send video feedback: {:?}", e);
+                        }
                     }
-                    Err(e) => {
-                        warn!("Failed to read a frame: {:?}", e);
+                    Ok(msg::Video::Status(status)) => {
+                        match &status {
+                            Some(status) => warn!("{}", status),
+                            None => info!("Camera {} is streaming again", camera),
+                        }
+                        let _ = video_sender.send(VideoEvent::Status(camera, status));
+                    }
+                    Ok(msg::Video::Snapshot(snapshot)) => {
+                        let _ = video_sender.send(VideoEvent::Snapshot(snapshot));
                     }
+                    Ok(msg::Video::Recording(recording)) => {
+                        let _ = video_sender.send(VideoEvent::Recording(camera, recording));
+                    }
+                    Ok(msg::Video::Autopilot(mode)) => {
+                        match &mode {
+                            Some(mode) => info!("Camera {} drives the machine: {}", camera, mode),
+                            None => info!("Camera {} stopped driving the machine", camera),
+                        }
+                        let _ = video_sender.send(VideoEvent::Autopilot(camera, mode));
+                    }
+                    Ok(msg::Video::Capabilities(capabilities)) => {
+                        debug!("Camera {} capabilities: {:?}", camera, capabilities);
+                        let _ = video_sender.send(VideoEvent::Capabilities(camera, capabilities));
+                    }
+                    // Timeouts are retried by `read_msg`, so the stream is closed or
+                    // broken, e.g. when the camera feed of the server stops.
+                    Err(e) => lost = Some(e),
                 }
-                thread::sleep(Duration::from_millis(15));
             }
         });
         self.threads.insert(0, video_thread);
+    }
 
-        return video_receiver;
+    fn stream_telemetry(&mut self, mut stream: TcpStream) -> mpsc::Receiver<msg::Telemetry> {
+        let (telemetry_sender, telemetry_receiver): (
+            mpsc::Sender<msg::Telemetry>,
+            mpsc::Receiver<msg::Telemetry>,
+        ) = mpsc::channel();
+
+        let telemetry_thread = st_thread::spawn(move |stopped| {
+            while !stopped.get() {
+                match stream.read_msg::<msg::Telemetry>(&mut vec![]) {
+                    Ok(telemetry) => match telemetry_sender.send(telemetry) {
+                        Err(e) => {
+                            warn!("Failed to process telemetry: {:?}", e);
+                        }
+                        _ => {}
+                    },
+                    Err(e) => {
+                        warn!("Failed to read telemetry: {:?}", e);
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+            }
+        });
+        self.threads.insert(0, telemetry_thread);
+
+        return telemetry_receiver;
     }
 
-    fn stream_control(&mut self, mut stream: TcpStream) -> mpsc::Sender<types::MachineState> {
+    fn stream_heartbeat(&mut self, mut stream: TcpStream) {
+        let interval = Duration::from_millis(self.settings.heartbeat.interval as u64);
+
+        let heartbeat_thread = st_thread::spawn(move |stopped| {
+            while !stopped.get() {
+                match stream.write_msg(&msg::Heartbeat {
+                    timestamp_ms: chrono::Utc::now().timestamp_millis(),
+                }) {
+                    Err(e) => {
+                        warn!("Failed to send a heartbeat: {:?}", e);
+                    }
+                    _ => {}
+                }
+                thread::sleep(interval);
+            }
+        });
+        self.threads.insert(0, heartbeat_thread);
+    }
+
+    fn stream_control(&mut self, mut stream: TcpStream) -> mpsc::Sender<msg::Control> {
         let (control_sender, control_receiver): (
-            mpsc::Sender<types::MachineState>,
-            mpsc::Receiver<types::MachineState>,
+            mpsc::Sender<msg::Control>,
+            mpsc::Receiver<msg::Control>,
         ) = mpsc::channel();
 
         let controller_thread = st_thread::spawn(move |stopped| {
@@ -313,3 +579,148 @@ impl Session {
         Ok(())
     }
 }
+
+/// Download server journal records of the last `settings.journal.minutes` minutes.
+///
+/// Doesn't require an open session, so it can be used after the connection is lost.
+pub fn download_journal(settings: &Settings) -> Result<Vec<msg::JournalRecord>, io::Error> {
+    debug!("Sending journal request...");
+    let mut stream = request_connection(
+        settings,
+        msg::ConnectionType::Journal(settings.journal.clone()),
+    )?;
+
+    let journal_resp = stream.read_msg::<msg::Journal>(&mut vec![])?;
+    if journal_resp.ok {
+        Ok(journal_resp.records)
+    } else {
+        let err_msg = journal_resp.error.unwrap_or("Unknown".to_string());
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Failed to download journal. {}", err_msg),
+        ))
+    }
+}
+
+/// List video clips recorded on the server. Doesn't require an open session.
+pub fn list_recordings(settings: &Settings) -> Result<Vec<msg::Clip>, io::Error> {
+    let mut stream = request_connection(
+        settings,
+        msg::ConnectionType::Recordings(msg::RecordingsRequest::List),
+    )?;
+
+    let response = stream.read_msg::<msg::Recordings>(&mut vec![])?;
+    if response.ok {
+        Ok(response.clips)
+    } else {
+        let err_msg = response.error.unwrap_or("Unknown".to_string());
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Failed to list recordings. {}", err_msg),
+        ))
+    }
+}
+
+/// Names of the scripts the server can run.
+pub fn list_scripts(settings: &Settings) -> Result<Vec<String>, io::Error> {
+    let mut stream = request_connection(settings, msg::ConnectionType::Scripts)?;
+
+    let response = stream.read_msg::<msg::Scripts>(&mut vec![])?;
+    if response.ok {
+        Ok(response.names)
+    } else {
+        let err_msg = response.error.unwrap_or("Unknown".to_string());
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Failed to list scripts. {}", err_msg),
+        ))
+    }
+}
+
+/// Download a recorded clip into `writer` and return its size.
+pub fn download_recording(
+    settings: &Settings,
+    name: &String,
+    writer: &mut dyn io::Write,
+) -> Result<u64, io::Error> {
+    let mut stream = request_connection(
+        settings,
+        msg::ConnectionType::Recordings(msg::RecordingsRequest::Download(name.clone())),
+    )?;
+
+    let response = stream.read_msg::<msg::RecordingDownload>(&mut vec![])?;
+    if !response.ok {
+        let err_msg = response.error.unwrap_or("Unknown".to_string());
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Failed to download {}. {}", name, err_msg),
+        ));
+    }
+
+    let received = io::copy(&mut stream.take(response.size), writer)?;
+    if received < response.size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Download of {} is interrupted after {} of {} bytes",
+                name, received, response.size
+            ),
+        ));
+    }
+    Ok(received)
+}
+
+/// Download clips recorded on the server into `dir` and return how many were downloaded.
+///
+/// Clips which are already downloaded with the same size are skipped.
+pub fn download_recordings(settings: &Settings, dir: &str) -> Result<usize, io::Error> {
+    fs::create_dir_all(dir)?;
+    let mut count = 0;
+    for clip in list_recordings(settings)? {
+        let path = Path::new(dir).join(&clip.name);
+        if fs::metadata(&path).map_or(false, |m| m.len() == clip.size) {
+            continue;
+        }
+
+        debug!("Downloading {} ({} bytes)...", clip.name, clip.size);
+        let part_path = path.with_extension("part");
+        let mut file = fs::File::create(&part_path)?;
+        if let Err(e) = download_recording(settings, &clip.name, &mut file) {
+            let _ = fs::remove_file(&part_path);
+            return Err(e);
+        }
+        fs::rename(&part_path, &path)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Open a connection which doesn't belong to a session.
+fn request_connection(
+    settings: &Settings,
+    conn_type: msg::ConnectionType,
+) -> Result<TcpStream, io::Error> {
+    let addrs_str = format!(
+        "{}:{}",
+        &settings.connection.host, &settings.connection.port
+    );
+    let addr = match addrs_str.to_socket_addrs()?.next() {
+        Some(res) => res,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Failed to resolve {}", addrs_str),
+            ))
+        }
+    };
+
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::from_millis(1000))?;
+    stream.set_read_timeout(Some(Duration::from_millis(10000)))?;
+
+    stream.write_msg(&msg::RequestConnection {
+        token: settings.connection.token.clone(),
+        session_id: None,
+        conn_type: conn_type,
+    })?;
+    Ok(stream)
+}
@@ -1,12 +1,21 @@
 extern crate find_folder;
+use crate::common::messages as msg;
+use crate::common::types;
 use druid::widget::{ImageData, SvgData};
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::fs;
 use std::io;
+use std::io::Write;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 
+/// JPEG quality of snapshots that have to be re-encoded to turn them upright.
+const SNAPSHOT_QUALITY: u8 = 95;
+/// Directory where clips recorded on the server are downloaded.
+pub const RECORDINGS_DIR: &str = "recordings";
+
 pub fn load_assets_folder(path: &str) -> Result<PathBuf, io::Error> {
     match find_folder::Search::ParentsThenKids(1, 4).for_folder(format!("assets/{}", path).as_str())
     {
@@ -70,3 +79,108 @@ impl FPSCounter {
         self.frames.len() as u8
     }
 }
+
+/// Save a snapshot with its metadata in the current directory and return the image path.
+///
+/// The image is turned upright when the camera orientation requires it.
+pub fn save_snapshot(snapshot: &msg::Snapshot) -> Result<String, io::Error> {
+    let duration =
+        std::time::UNIX_EPOCH + std::time::Duration::from_millis(snapshot.timestamp_ms as u64);
+    let name = format!(
+        "snapshot-{}-{}",
+        snapshot.camera,
+        chrono::DateTime::<chrono::Local>::from(duration).format("%Y%m%d-%H%M%S")
+    );
+    let path = format!("{}.jpg", name);
+
+    if snapshot.orientation == types::Orientation::default() {
+        fs::write(&path, &snapshot.data)?;
+    } else {
+        let img =
+            match image::load_from_memory_with_format(&snapshot.data, image::ImageFormat::Jpeg) {
+                Ok(res) => snapshot.orientation.apply(res).to_rgb(),
+                Err(e) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("Failed to decode snapshot: {}", e),
+                    ))
+                }
+            };
+        let mut file = fs::File::create(&path)?;
+        let encoded = image::jpeg::JPEGEncoder::new_with_quality(&mut file, SNAPSHOT_QUALITY)
+            .encode(&img, img.width(), img.height(), image::ColorType::Rgb8);
+        if let Err(e) = encoded {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Failed to encode snapshot: {}", e),
+            ));
+        }
+    }
+    fs::write(format!("{}.txt", name), snapshot.metadata())?;
+    Ok(path)
+}
+
+/// Load a mission from a TOML file.
+pub fn load_mission(path: &str) -> Result<types::Mission, io::Error> {
+    let text = fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid mission: {}", e),
+        )
+    })
+}
+
+/// Machine state sent at a time since the start of a macro recording.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct MacroStep {
+    pub at_ms: u64,
+    pub state: types::MachineState,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Macro {
+    steps: Vec<MacroStep>,
+}
+
+/// Save a recorded macro into a TOML file.
+pub fn save_macro(path: &str, steps: &[MacroStep]) -> Result<(), io::Error> {
+    let text = toml::to_string(&Macro {
+        steps: steps.to_vec(),
+    })
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}", e)))?;
+    fs::write(path, text)
+}
+
+/// Load a macro from a TOML file.
+pub fn load_macro(path: &str) -> Result<Vec<MacroStep>, io::Error> {
+    let text = fs::read_to_string(path)?;
+    match toml::from_str::<Macro>(&text) {
+        Ok(res) => Ok(res.steps),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid macro: {}", e),
+        )),
+    }
+}
+
+/// Save journal records into a text file in the current directory and return its path.
+pub fn save_journal(records: &Vec<msg::JournalRecord>) -> Result<String, io::Error> {
+    let path = chrono::Local::now()
+        .format("journal-%Y%m%d-%H%M%S.log")
+        .to_string();
+    let mut file = fs::File::create(&path)?;
+
+    for record in records {
+        let duration =
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(record.timestamp_ms as u64);
+        let datetime = chrono::DateTime::<chrono::Local>::from(duration);
+        writeln!(
+            file,
+            "{} {:?}",
+            datetime.format("%Y-%m-%d %H:%M:%S.%3f"),
+            record.event
+        )?;
+    }
+    Ok(path)
+}
@@ -6,6 +6,7 @@ use std::sync;
 use std::thread;
 use std::time;
 
+use crate::common::messages as msg;
 use crate::common::settings;
 use crate::common::types;
 use crate::conn;
@@ -14,10 +15,11 @@ use crate::utils;
 use druid::{
     piet::{FontBuilder, ImageFormat, InterpolationMode, Text, TextLayoutBuilder},
     widget::{
-        prelude::*, Align, Controller, FillStrat, Flex, Label, Split, ViewSwitcher, WidgetExt,
+        prelude::*, Align, Controller, FillStrat, Flex, Label, SizedBox, Split, ViewSwitcher,
+        WidgetExt,
     },
-    AppDelegate, Color, Command, Data, DelegateCtx, Env, ExtEventSink, KeyCode, Lens, Rect,
-    Selector, Target, Widget,
+    AppDelegate, Color, Command, Data, DelegateCtx, Env, ExtEventSink, KeyCode, Lens, MouseButton,
+    Point, Rect, Selector, Target, UnitPoint, Widget,
 };
 use druid_material_icons as icons;
 use stoppable_thread as st_thread;
@@ -32,13 +34,30 @@ pub const CONNECTION_COMMAND: Selector<ConnectionEvent> = Selector::new("connect
 pub const KEYBOARD_COMMAND: Selector<druid::Event> = Selector::new("keyboard.event");
 pub const GAMEPAD_COMMAND: Selector<gilrs::EventType> = Selector::new("gamepad.event");
 pub const VIDEO_SET_FRAME_COMMAND: Selector<types::VideoFrame> = Selector::new("render.event");
-pub const VIDEO_SET_FPS_COMMAND: Selector<u8> = Selector::new("render.set.fps");
+pub const VIDEO_SET_FPS_COMMAND: Selector<(usize, u8)> = Selector::new("render.set.fps");
+pub const TELEMETRY_COMMAND: Selector<msg::Telemetry> = Selector::new("telemetry.event");
+pub const JOURNAL_COMMAND: Selector<String> = Selector::new("journal.status");
+pub const SNAPSHOT_COMMAND: Selector<String> = Selector::new("snapshot.status");
+pub const CAMERA_CAPABILITIES_COMMAND: Selector<(usize, types::CameraCapabilities)> =
+    Selector::new("camera.capabilities");
+pub const VIDEO_SET_REGION_COMMAND: Selector<Option<types::Region>> =
+    Selector::new("render.set.region");
+pub const MOUNT_COMMAND: Selector<types::MountCommand> = Selector::new("mount.move");
+pub const CAMERA_STATUS_COMMAND: Selector<(usize, Option<String>)> = Selector::new("camera.status");
+pub const RECORDING_COMMAND: Selector<(usize, Result<Option<String>, String>)> =
+    Selector::new("recording.status");
+pub const RECORDINGS_COMMAND: Selector<String> = Selector::new("recordings.status");
+pub const AUTOPILOT_COMMAND: Selector<(usize, Option<String>)> = Selector::new("autopilot.status");
+pub const SCRIPTS_COMMAND: Selector<Result<Vec<String>, String>> = Selector::new("scripts.list");
+/// Next state of a replayed macro, `None` when the replay is over.
+pub const MACRO_COMMAND: Selector<Option<types::MachineState>> = Selector::new("macro.step");
 
 pub enum ConnectionEvent {
     InitConnect,
     InitDisconnect,
     Error(String),
-    Connected,
+    /// Names of the cameras streamed in the session.
+    Connected(Vec<String>),
     Disconnected,
 }
 
@@ -50,7 +69,15 @@ pub struct AppState {
     pub light_state: String,
     pub direction_state: String,
     pub connection_status: String,
+    pub gps_status: String,
+    pub mount_status: String,
     pub fps: u8,
+    pub main_camera: usize,
+    pub camera_count: usize,
+    pub camera_name: String,
+    pub camera_status: String,
+    pub camera_panel: bool,
+    pub camera_panel_text: String,
 }
 
 impl AppState {
@@ -62,7 +89,24 @@ impl AppState {
             light_state: "".to_string(),
             direction_state: "".to_string(),
             connection_status: "".to_string(),
+            gps_status: "".to_string(),
+            mount_status: "".to_string(),
             fps: 0,
+            main_camera: 0,
+            camera_count: 0,
+            camera_name: "".to_string(),
+            camera_status: "".to_string(),
+            camera_panel: false,
+            camera_panel_text: "".to_string(),
+        }
+    }
+
+    /// Camera shown as a picture-in-picture.
+    pub fn pip_camera(&self) -> Option<usize> {
+        if self.camera_count > 1 {
+            Some((self.main_camera + 1) % self.camera_count)
+        } else {
+            None
         }
     }
 }
@@ -72,9 +116,40 @@ pub struct Delegate {
     is_connecting: sync::Arc<sync::atomic::AtomicBool>,
     session_thread: Option<st_thread::StoppableHandle<()>>,
     gamepad_thread: Option<st_thread::StoppableHandle<()>>,
-    control_sender: Option<mpsc::Sender<types::MachineState>>,
+    control_sender: Option<mpsc::Sender<msg::Control>>,
     settings: settings::Settings,
+    /// Cameras streamed in the session, a camera index points into it.
+    cameras: Vec<String>,
     machine_state: types::MachineState,
+    /// Last reported capabilities by camera index.
+    capabilities: Vec<Option<types::CameraCapabilities>>,
+    /// Last reported problems by camera index.
+    camera_statuses: Vec<Option<String>>,
+    /// Clips being recorded on the server by camera index.
+    recordings: Vec<Option<String>>,
+    /// Cameras with a timelapse running on the server.
+    timelapses: Vec<String>,
+    /// Motion detection requested by camera index.
+    motion_detection: Vec<Option<types::MotionDetection>>,
+    /// Color blob tracking requested by camera index.
+    blob_tracking: Vec<Option<types::BlobTracking>>,
+    /// Line following requested by camera index.
+    line_following: Vec<Option<types::LineFollowing>>,
+    /// Cameras with marker detection by index.
+    marker_detection: Vec<bool>,
+    /// Last mission progress reported by the server.
+    mission: Option<types::MissionProgress>,
+    /// Scripts listed by the server, started by their number.
+    scripts: Vec<String>,
+    /// Last script state reported by the server.
+    script: Option<types::ScriptRun>,
+    /// Start of a macro recording and the machine states sent since then.
+    macro_recording: Option<(time::Instant, Vec<utils::MacroStep>)>,
+    macro_replay: Option<st_thread::StoppableHandle<()>>,
+    /// Mount angles requested with the gamepad stick.
+    mount_stick: (f32, f32),
+    /// Selected row of the camera settings panel.
+    camera_panel_row: usize,
 }
 
 impl Delegate {
@@ -82,10 +157,26 @@ impl Delegate {
         Delegate {
             sink: sink,
             settings: settings,
+            cameras: vec![],
             session_thread: None,
             gamepad_thread: None,
             control_sender: None,
             machine_state: types::MachineState::new(),
+            capabilities: vec![],
+            camera_statuses: vec![],
+            recordings: vec![],
+            timelapses: vec![],
+            motion_detection: vec![],
+            blob_tracking: vec![],
+            line_following: vec![],
+            marker_detection: vec![],
+            mission: None,
+            scripts: vec![],
+            script: None,
+            macro_recording: None,
+            macro_replay: None,
+            mount_stick: (0.0, 0.0),
+            camera_panel_row: 0,
             is_connecting: sync::Arc::new(sync::atomic::AtomicBool::default()),
         }
     }
@@ -107,19 +198,24 @@ impl Delegate {
 
         let sink = self.sink.clone();
         let settings = self.settings.clone();
+        let camera_count = settings.video.cameras.len();
 
         let (control_sender, control_receiver): (
-            mpsc::Sender<types::MachineState>,
-            mpsc::Receiver<types::MachineState>,
+            mpsc::Sender<msg::Control>,
+            mpsc::Receiver<msg::Control>,
         ) = mpsc::channel();
 
         let session_th = st_thread::spawn(move |stopped| {
             let mut session = conn::Session::new(settings);
 
             match session.connect() {
-                Ok((video_receiver, control_sender)) => {
-                    sink.submit_command(CONNECTION_COMMAND, ConnectionEvent::Connected, None)
-                        .expect("Failed to submit command");
+                Ok((video_receiver, control_sender, telemetry_receiver, cameras)) => {
+                    sink.submit_command(
+                        CONNECTION_COMMAND,
+                        ConnectionEvent::Connected(cameras),
+                        None,
+                    )
+                    .expect("Failed to submit command");
 
                     let control_th = st_thread::spawn(move |control_stopped| {
                         while !control_stopped.get() {
@@ -134,20 +230,68 @@ impl Delegate {
                         }
                     });
 
-                    let mut fps_counter = utils::FPSCounter::new(128);
+                    let video_queue = session.video_queue();
+                    let telemetry_sink = sink.clone();
+                    let mut fps_counters: Vec<utils::FPSCounter> = (0..camera_count)
+                        .map(|_| utils::FPSCounter::new(128))
+                        .collect();
                     let video_th = st_thread::spawn(move |video_stopped| {
                         while !video_stopped.get() {
                             match video_receiver.try_recv() {
-                                Ok(frame) => {
-                                    sink.submit_command(VIDEO_SET_FRAME_COMMAND, frame, None)
+                                Ok(conn::VideoEvent::Capabilities(camera, capabilities)) => {
+                                    sink.submit_command(
+                                        CAMERA_CAPABILITIES_COMMAND,
+                                        (camera, capabilities),
+                                        None,
+                                    )
+                                    .expect("Failed to submit command");
+                                }
+                                Ok(conn::VideoEvent::Snapshot(snapshot)) => {
+                                    let status = match snapshot {
+                                        Ok(snapshot) => match utils::save_snapshot(&snapshot) {
+                                            Ok(path) => format!("Saved snapshot to {}", path),
+                                            Err(e) => format!("Failed to save snapshot: {}", e),
+                                        },
+                                        Err(e) => format!("Failed to take snapshot: {}", e),
+                                    };
+                                    info!("{}", status);
+                                    sink.submit_command(SNAPSHOT_COMMAND, status, None)
                                         .expect("Failed to submit command");
+                                }
+                                Ok(conn::VideoEvent::Autopilot(camera, mode)) => {
+                                    sink.submit_command(AUTOPILOT_COMMAND, (camera, mode), None)
+                                        .expect("Failed to submit command");
+                                }
+                                Ok(conn::VideoEvent::Recording(camera, recording)) => {
                                     sink.submit_command(
-                                        VIDEO_SET_FPS_COMMAND,
-                                        fps_counter.tick(),
+                                        RECORDING_COMMAND,
+                                        (camera, recording),
                                         None,
                                     )
                                     .expect("Failed to submit command");
                                 }
+                                Ok(conn::VideoEvent::Status(camera, status)) => {
+                                    sink.submit_command(
+                                        CAMERA_STATUS_COMMAND,
+                                        (camera, status),
+                                        None,
+                                    )
+                                    .expect("Failed to submit command");
+                                }
+                                Ok(conn::VideoEvent::Frame(frame)) => {
+                                    video_queue.fetch_sub(1, sync::atomic::Ordering::Relaxed);
+                                    let camera = frame.camera;
+                                    sink.submit_command(VIDEO_SET_FRAME_COMMAND, frame, None)
+                                        .expect("Failed to submit command");
+                                    if let Some(fps_counter) = fps_counters.get_mut(camera) {
+                                        sink.submit_command(
+                                            VIDEO_SET_FPS_COMMAND,
+                                            (camera, fps_counter.tick()),
+                                            None,
+                                        )
+                                        .expect("Failed to submit command");
+                                    }
+                                }
                                 Err(_) => {
                                     thread::sleep(time::Duration::from_millis(10));
                                 }
@@ -155,6 +299,21 @@ impl Delegate {
                         }
                     });
 
+                    let telemetry_th = st_thread::spawn(move |telemetry_stopped| {
+                        while !telemetry_stopped.get() {
+                            match telemetry_receiver.try_recv() {
+                                Ok(telemetry) => {
+                                    telemetry_sink
+                                        .submit_command(TELEMETRY_COMMAND, telemetry, None)
+                                        .expect("Failed to submit command");
+                                }
+                                Err(_) => {
+                                    thread::sleep(time::Duration::from_millis(50));
+                                }
+                            };
+                        }
+                    });
+
                     while !stopped.get() {
                         thread::sleep(time::Duration::from_millis(200));
                     }
@@ -162,6 +321,7 @@ impl Delegate {
                     debug!("Stopping session...");
                     control_th.stop();
                     video_th.stop();
+                    telemetry_th.stop();
                     match session.disconnect() {
                         Ok(_) => debug!("Session stopped."),
                         Err(e) => warn!("{}", e),
@@ -259,14 +419,547 @@ impl Delegate {
         }))
     }
 
+    pub fn download_journal(&mut self) {
+        let sink = self.sink.clone();
+        let settings = self.settings.clone();
+
+        thread::spawn(move || {
+            let status = match conn::download_journal(&settings) {
+                Ok(records) => match utils::save_journal(&records) {
+                    Ok(path) => format!("Saved {} records to {}", records.len(), path),
+                    Err(e) => format!("Failed to save journal: {}", e),
+                },
+                Err(e) => format!("{}", e),
+            };
+            info!("{}", status);
+            sink.submit_command(JOURNAL_COMMAND, status, None)
+                .expect("Failed to submit command");
+        });
+    }
+
+    /// Download clips recorded on the server which aren't downloaded yet.
+    pub fn download_recordings(&mut self) {
+        let sink = self.sink.clone();
+        let settings = self.settings.clone();
+
+        thread::spawn(move || {
+            let status = match conn::download_recordings(&settings, utils::RECORDINGS_DIR) {
+                Ok(0) => "No new recordings".to_string(),
+                Ok(count) => format!("Downloaded {} clips to {}", count, utils::RECORDINGS_DIR),
+                Err(e) => format!("{}", e),
+            };
+            info!("{}", status);
+            sink.submit_command(RECORDINGS_COMMAND, status, None)
+                .expect("Failed to submit command");
+        });
+    }
+
+    /// Start or stop recording of the main camera on the server.
+    pub fn toggle_recording(&mut self, data: &AppState) {
+        let command = match self.recordings.get(data.main_camera) {
+            Some(Some(_)) => types::CameraCommand::StopRecording,
+            _ => types::CameraCommand::StartRecording,
+        };
+        self.send_camera_command(data.main_camera, command);
+    }
+
+    /// Start or stop a timelapse of the main camera on the server.
+    pub fn toggle_timelapse(&mut self, data: &mut AppState) {
+        let command = if self.timelapses.contains(&data.camera_name) {
+            data.connection_status = format!("Stopping timelapse of {}...", data.camera_name);
+            types::TimelapseCommand::Stop
+        } else {
+            data.connection_status = format!(
+                "Starting timelapse of {} every {} s...",
+                data.camera_name, self.settings.timelapse.interval_s
+            );
+            types::TimelapseCommand::Start {
+                interval_s: self.settings.timelapse.interval_s,
+                format: self.settings.timelapse.format,
+            }
+        };
+        if let Some(sender) = &self.control_sender {
+            if let Err(e) = sender.send(msg::Control::Timelapse(data.camera_name.clone(), command))
+            {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    /// Turn motion detection of the main camera on or off. With `sentry` the server
+    /// also takes the sentry actions on motion while the machine is parked.
+    pub fn toggle_motion_detection(&mut self, data: &mut AppState, sentry: bool) {
+        if self.motion_detection.len() <= data.main_camera {
+            self.motion_detection.resize(data.main_camera + 1, None);
+        }
+        let detection = match self.motion_detection[data.main_camera] {
+            Some(current) if current.sentry.is_some() == sentry => None,
+            _ => Some(types::MotionDetection {
+                min_score: self.settings.motion.min_score,
+                sentry: if sentry {
+                    Some(self.settings.motion.sentry)
+                } else {
+                    None
+                },
+            }),
+        };
+        self.motion_detection[data.main_camera] = detection;
+        data.connection_status = match (detection, sentry) {
+            (Some(_), true) => format!("Sentry mode of {} is on", data.camera_name),
+            (Some(_), false) => format!("Motion detection of {} is on", data.camera_name),
+            (None, _) => format!("Motion detection of {} is off", data.camera_name),
+        };
+        self.send_camera_command(
+            data.main_camera,
+            types::CameraCommand::SetMotionDetection(detection),
+        );
+        data.camera_status = self.main_camera_status(data);
+    }
+
+    /// Turn tracking of the configured color blob by the main camera on or off. With `follow`
+    /// the server also drives the machine after the blob until any manual input.
+    pub fn toggle_blob_tracking(&mut self, data: &mut AppState, follow: bool) {
+        if self.blob_tracking.len() <= data.main_camera {
+            self.blob_tracking.resize(data.main_camera + 1, None);
+        }
+        let tracking = match self.blob_tracking[data.main_camera] {
+            Some(current) if current.follow == follow => None,
+            _ => Some(types::BlobTracking {
+                color: self.settings.follow.color,
+                follow: follow,
+                target_size: self.settings.follow.target_size,
+            }),
+        };
+        self.blob_tracking[data.main_camera] = tracking;
+        data.connection_status = match (tracking, follow) {
+            (Some(_), true) => format!("Following the blob with {}", data.camera_name),
+            (Some(_), false) => format!("Blob tracking of {} is on", data.camera_name),
+            (None, _) => format!("Blob tracking of {} is off", data.camera_name),
+        };
+        self.send_camera_command(
+            data.main_camera,
+            types::CameraCommand::SetBlobTracking(tracking),
+        );
+        data.camera_status = self.main_camera_status(data);
+    }
+
+    /// Start or stop following a line with the main camera.
+    pub fn toggle_line_following(&mut self, data: &mut AppState) {
+        if self.line_following.len() <= data.main_camera {
+            self.line_following.resize(data.main_camera + 1, None);
+        }
+        let following = match self.line_following[data.main_camera] {
+            Some(_) => None,
+            None => Some(types::LineFollowing {
+                bright: self.settings.line.bright,
+                threshold: self.settings.line.threshold,
+                speed: self.settings.line.speed,
+            }),
+        };
+        self.line_following[data.main_camera] = following;
+        data.connection_status = match following {
+            Some(_) => format!("Following the line with {}", data.camera_name),
+            None => format!("Line following of {} is off", data.camera_name),
+        };
+        self.send_camera_command(
+            data.main_camera,
+            types::CameraCommand::SetLineFollowing(following),
+        );
+        data.camera_status = self.main_camera_status(data);
+    }
+
+    /// Turn detection of markers like QR codes by the main camera on or off.
+    pub fn toggle_marker_detection(&mut self, data: &mut AppState) {
+        if self.marker_detection.len() <= data.main_camera {
+            self.marker_detection.resize(data.main_camera + 1, false);
+        }
+        let enabled = !self.marker_detection[data.main_camera];
+        self.marker_detection[data.main_camera] = enabled;
+        data.connection_status = format!(
+            "Marker detection of {} is {}",
+            data.camera_name,
+            if enabled { "on" } else { "off" }
+        );
+        self.send_camera_command(
+            data.main_camera,
+            types::CameraCommand::SetMarkerDetection(enabled),
+        );
+        data.camera_status = self.main_camera_status(data);
+    }
+
+    /// Upload the mission file and start it on the server, or abort the running mission.
+    pub fn toggle_mission(&mut self, data: &mut AppState) {
+        let command = match &self.mission {
+            Some(progress) if progress.is_active() => {
+                data.connection_status = format!("Aborting mission {}...", progress.name);
+                types::MissionCommand::Abort
+            }
+            _ => match utils::load_mission(&self.settings.mission.file) {
+                Ok(mission) => {
+                    data.connection_status = format!(
+                        "Starting mission {} of {} steps...",
+                        mission.name,
+                        mission.steps.len()
+                    );
+                    types::MissionCommand::Start(mission)
+                }
+                Err(e) => {
+                    data.connection_status =
+                        format!("Failed to load {}: {}", self.settings.mission.file, e);
+                    return;
+                }
+            },
+        };
+        self.send_mission_command(command);
+    }
+
+    /// Pause or resume the running mission.
+    pub fn pause_mission(&mut self, data: &mut AppState) {
+        let command = match &self.mission {
+            Some(progress) if progress.status == types::MissionStatus::Paused => {
+                data.connection_status = format!("Resuming mission {}...", progress.name);
+                types::MissionCommand::Resume
+            }
+            Some(progress) if progress.is_active() => {
+                data.connection_status = format!("Pausing mission {}...", progress.name);
+                types::MissionCommand::Pause
+            }
+            _ => {
+                data.connection_status = "No mission is running".to_string();
+                return;
+            }
+        };
+        self.send_mission_command(command);
+    }
+
+    fn send_mission_command(&self, command: types::MissionCommand) {
+        if let Some(sender) = &self.control_sender {
+            if let Err(e) = sender.send(msg::Control::Mission(command)) {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    /// Request the scripts the server can run.
+    pub fn list_scripts(&mut self) {
+        let sink = self.sink.clone();
+        let settings = self.settings.clone();
+
+        thread::spawn(move || {
+            let names = conn::list_scripts(&settings).map_err(|e| format!("{}", e));
+            sink.submit_command(SCRIPTS_COMMAND, names, None)
+                .expect("Failed to submit command");
+        });
+    }
+
+    /// Start a script by its number in the last list, counting from 1.
+    pub fn start_script(&mut self, data: &mut AppState, number: usize) {
+        let name = match self.scripts.get(number - 1) {
+            Some(res) => res.clone(),
+            None => {
+                data.connection_status = format!("No script {}. Press S to list scripts", number);
+                return;
+            }
+        };
+        data.connection_status = format!("Starting script {}...", name);
+        self.send_script_command(types::ScriptCommand::Start(name));
+    }
+
+    /// Stop the running script.
+    pub fn stop_script(&mut self, data: &mut AppState) {
+        match &self.script {
+            Some(run) if run.running => {
+                data.connection_status = format!("Stopping script {}...", run.name);
+                self.send_script_command(types::ScriptCommand::Stop);
+            }
+            _ => data.connection_status = "No script is running".to_string(),
+        }
+    }
+
+    fn send_script_command(&self, command: types::ScriptCommand) {
+        if let Some(sender) = &self.control_sender {
+            if let Err(e) = sender.send(msg::Control::Script(command)) {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    /// Start recording manual machine states, or stop and save them into the macro file.
+    pub fn toggle_macro_recording(&mut self, data: &mut AppState) {
+        let file = &self.settings.macros.file;
+        data.connection_status = match self.macro_recording.take() {
+            Some((_, steps)) => match utils::save_macro(file, &steps) {
+                Ok(_) => format!("Saved {} states to {}", steps.len(), file),
+                Err(e) => format!("Failed to save {}: {}", file, e),
+            },
+            None => {
+                // The machine may be moving already when the recording starts.
+                let initial = utils::MacroStep {
+                    at_ms: 0,
+                    state: self.machine_state,
+                };
+                self.macro_recording = Some((time::Instant::now(), vec![initial]));
+                "Recording a macro...".to_string()
+            }
+        };
+    }
+
+    /// Replay the macro file with its timings. Any manual input aborts the replay.
+    pub fn replay_macro(&mut self, data: &mut AppState) {
+        if self.control_sender.is_none() {
+            data.connection_status = "Not connected".to_string();
+            return;
+        }
+        if self.macro_recording.is_some() {
+            data.connection_status = "Stop the macro recording first".to_string();
+            return;
+        }
+        let file = &self.settings.macros.file;
+        let steps = match utils::load_macro(file) {
+            Ok(res) => res,
+            Err(e) => {
+                data.connection_status = format!("Failed to load {}: {}", file, e);
+                return;
+            }
+        };
+        data.connection_status = format!("Replaying {} states of {}...", steps.len(), file);
+
+        let sink = self.sink.clone();
+        self.macro_replay = Some(st_thread::spawn(move |stopped| {
+            let started = time::Instant::now();
+            for step in steps {
+                let at = time::Duration::from_millis(step.at_ms);
+                while started.elapsed() < at {
+                    if stopped.get() {
+                        return;
+                    }
+                    thread::sleep(time::Duration::from_millis(2));
+                }
+                sink.submit_command(MACRO_COMMAND, Some(step.state), None)
+                    .expect("Failed to submit command");
+            }
+            sink.submit_command(MACRO_COMMAND, None, None)
+                .expect("Failed to submit command");
+        }));
+    }
+
+    /// Stop a running macro replay and the machine. Returns whether a replay was running.
+    fn abort_macro_replay(&mut self, data: &mut AppState) -> bool {
+        match self.macro_replay.take() {
+            Some(replay) => {
+                replay.stop();
+                self.set_machine_state(data, self.machine_state.halted());
+                data.connection_status = "Macro replay is aborted".to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn switch_camera(&mut self, data: &mut AppState) {
+        if data.camera_count > 1 {
+            // Zoom is reset by the view when it's switched.
+            self.send_camera_command(data.main_camera, types::CameraCommand::SetRegion(None));
+            data.main_camera = (data.main_camera + 1) % data.camera_count;
+            data.camera_name = self.cameras[data.main_camera].clone();
+            data.camera_status = self.main_camera_status(data);
+            debug!("Switched main view to {} camera", data.camera_name);
+        }
+    }
+
+    fn main_camera_status(&self, data: &AppState) -> String {
+        let recording = match self.recordings.get(data.main_camera) {
+            Some(Some(_)) => "● REC ",
+            _ => "",
+        };
+        let timelapse = if self.timelapses.contains(&data.camera_name) {
+            "⏱ TL "
+        } else {
+            ""
+        };
+        let motion = match self.motion_detection.get(data.main_camera) {
+            Some(Some(detection)) if detection.sentry.is_some() => "🛡 SENTRY ",
+            Some(Some(_)) => "👁 MOTION ",
+            _ => "",
+        };
+        let blob = match self.blob_tracking.get(data.main_camera) {
+            Some(Some(tracking)) if tracking.follow => "🤖 FOLLOW ",
+            Some(Some(_)) => "🎯 TRACK ",
+            _ => "",
+        };
+        let line = match self.line_following.get(data.main_camera) {
+            Some(Some(_)) => "🛤 LINE ",
+            _ => "",
+        };
+        let markers = match self.marker_detection.get(data.main_camera) {
+            Some(true) => "⌖ MARKERS ",
+            _ => "",
+        };
+        let modes = format!(
+            "{}{}{}{}{}{}",
+            recording, timelapse, motion, blob, line, markers
+        );
+        match self.camera_statuses.get(data.main_camera) {
+            Some(Some(status)) => format!("{}⚠ {}", modes, status),
+            _ => modes,
+        }
+    }
+
+    /// Render the settings of the main camera, marking the selected row.
+    pub fn update_camera_panel(&mut self, data: &mut AppState) {
+        let capabilities = match self.capabilities.get(data.main_camera) {
+            Some(Some(res)) => res,
+            _ => {
+                data.camera_panel_text = format!("{}: no camera info", data.camera_name);
+                return;
+            }
+        };
+
+        let mut rows = vec![
+            format!(
+                "Resolution: {}x{}",
+                capabilities.resolution.0, capabilities.resolution.1
+            ),
+            format!(
+                "Framerate: {} / {}",
+                capabilities.framerate, capabilities.max_framerate
+            ),
+        ];
+        for control in capabilities.controls.iter() {
+            rows.push(format!(
+                "{}: {} [{}..{}]",
+                control.name, control.value, control.minimum, control.maximum
+            ));
+        }
+        self.camera_panel_row = self.camera_panel_row.min(rows.len() - 1);
+
+        let mut text = format!(
+            "{} ({})\n",
+            data.camera_name,
+            capabilities.formats.join(", ")
+        );
+        for (index, row) in rows.iter().enumerate() {
+            let marker = if index == self.camera_panel_row {
+                "▶"
+            } else {
+                " "
+            };
+            text.push_str(&format!("{} {}\n", marker, row));
+        }
+        data.camera_panel_text = text;
+    }
+
+    /// Step the selected setting of the main camera up or down.
+    pub fn adjust_camera(&mut self, data: &mut AppState, up: bool) {
+        let capabilities = match self.capabilities.get_mut(data.main_camera) {
+            Some(Some(res)) => res,
+            _ => return,
+        };
+
+        let command = match self.camera_panel_row {
+            0 => {
+                let resolutions = &capabilities.resolutions;
+                if resolutions.is_empty() {
+                    return;
+                }
+                let current = resolutions
+                    .iter()
+                    .position(|res| *res == capabilities.resolution)
+                    .unwrap_or(0);
+                let next = if up {
+                    (current + 1) % resolutions.len()
+                } else {
+                    (current + resolutions.len() - 1) % resolutions.len()
+                };
+                capabilities.resolution = resolutions[next];
+                types::CameraCommand::SetResolution(resolutions[next])
+            }
+            1 => {
+                capabilities.framerate = if up {
+                    (capabilities.framerate + 1).min(capabilities.max_framerate)
+                } else {
+                    capabilities.framerate.saturating_sub(1).max(1)
+                };
+                types::CameraCommand::SetFramerate(capabilities.framerate)
+            }
+            row => match capabilities.controls.get_mut(row - 2) {
+                Some(control) => {
+                    let step = control.step.max(1);
+                    control.value = if up {
+                        (control.value + step).min(control.maximum)
+                    } else {
+                        (control.value - step).max(control.minimum)
+                    };
+                    types::CameraCommand::SetControl(control.id, control.value)
+                }
+                None => return,
+            },
+        };
+
+        self.send_camera_command(data.main_camera, command);
+        self.update_camera_panel(data);
+    }
+
+    pub fn move_mount(&mut self, command: types::MountCommand) {
+        if let Some(sender) = &self.control_sender {
+            if let Err(e) = sender.send(msg::Control::Mount(command)) {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    /// Point the mount where the right stick is deflected, it returns to the center on release.
+    fn move_mount_with_stick(&mut self, axis: gilrs::Axis, value: f32) {
+        let value = if value.abs() < 0.1 { 0.0 } else { value };
+        let mut stick = self.mount_stick;
+        match axis {
+            gilrs::Axis::RightStickX => stick.0 = value,
+            gilrs::Axis::RightStickY => stick.1 = value,
+            _ => return,
+        }
+        // The stick reports small changes at rest, only send real movements.
+        if stick != self.mount_stick {
+            self.mount_stick = stick;
+            self.move_mount(types::MountCommand::Absolute {
+                pan: stick.0 * MOUNT_STICK_RANGE,
+                tilt: stick.1 * MOUNT_STICK_RANGE,
+            });
+        }
+    }
+
+    fn send_camera_command(&self, camera: usize, command: types::CameraCommand) {
+        if let (Some(sender), Some(name)) = (&self.control_sender, self.cameras.get(camera)) {
+            if let Err(e) = sender.send(msg::Control::Camera(name.clone(), command)) {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    /// Send a state which doesn't come from manual input, e.g. of a replayed macro.
+    fn set_machine_state(&mut self, data: &mut AppState, state: types::MachineState) {
+        self.machine_state = state;
+        if let Some(sender) = &self.control_sender {
+            if let Err(e) = sender.send(msg::Control::State(state)) {
+                warn!("{}", e);
+            }
+        }
+        show_machine_state(data, &state);
+    }
+
     pub fn update_machine_state(
         &mut self,
         event: types::MachineEvents,
     ) -> Option<types::MachineState> {
         if self.machine_state.update(event) {
+            if let Some((started, steps)) = &mut self.macro_recording {
+                steps.push(utils::MacroStep {
+                    at_ms: started.elapsed().as_millis() as u64,
+                    state: self.machine_state,
+                });
+            }
             match &self.control_sender {
                 Some(sender) => {
-                    match sender.send(self.machine_state) {
+                    match sender.send(msg::Control::State(self.machine_state)) {
                         Err(e) => {
                             warn!("{}", e);
                         }
@@ -282,6 +975,53 @@ impl Delegate {
     }
 }
 
+/// Whether a command is a key press or a gamepad input that drives the machine.
+fn is_manual_input(cmd: &Command) -> bool {
+    if cmd.is(KEYBOARD_COMMAND) {
+        return match cmd.get_unchecked(KEYBOARD_COMMAND) {
+            Event::KeyDown(key) => !key.is_repeat,
+            _ => false,
+        };
+    }
+    if cmd.is(GAMEPAD_COMMAND) {
+        return match cmd.get_unchecked(GAMEPAD_COMMAND) {
+            gilrs::EventType::ButtonPressed(_, _) => true,
+            gilrs::EventType::ButtonChanged(_, value, _) => *value > 0.5,
+            gilrs::EventType::AxisChanged(gilrs::Axis::LeftStickX, value, _) => value.abs() > 0.5,
+            _ => false,
+        };
+    }
+    false
+}
+
+/// Show the direction and the lamp of a state sent to the machine.
+fn show_machine_state(data: &mut AppState, ms: &types::MachineState) {
+    let mut status = "";
+    if ms.forward {
+        if ms.left {
+            status = "↖";
+        } else if ms.right {
+            status = "↗";
+        } else {
+            status = "⬆";
+        }
+    } else if ms.backward {
+        if ms.left {
+            status = "↙";
+        } else if ms.right {
+            status = "↘";
+        } else {
+            status = "⬇";
+        }
+    } else if ms.left {
+        status = "⬅";
+    } else if ms.right {
+        status = "➡";
+    }
+    data.direction_state = status.to_string();
+    data.light_state = if ms.lamp_enabled { "💡" } else { "" }.to_string();
+}
+
 impl AppDelegate<AppState> for Delegate {
     fn command(
         &mut self,
@@ -291,12 +1031,216 @@ impl AppDelegate<AppState> for Delegate {
         data: &mut AppState,
         _env: &Env,
     ) -> bool {
+        // Manual input only stops the machine while a macro is replayed.
+        if is_manual_input(cmd) && self.abort_macro_replay(data) {
+            return true;
+        }
+        if cmd.is(KEYBOARD_COMMAND) {
+            match cmd.get_unchecked(KEYBOARD_COMMAND) {
+                Event::KeyDown(key) => match key.key_code {
+                    KeyCode::KeyB => {
+                        data.connection_status = format!("Downloading journal...");
+                        self.download_journal();
+                    }
+                    KeyCode::KeyI => self.move_mount(types::MountCommand::Relative {
+                        pan: 0.0,
+                        tilt: MOUNT_STEP,
+                    }),
+                    KeyCode::KeyK => self.move_mount(types::MountCommand::Relative {
+                        pan: 0.0,
+                        tilt: -MOUNT_STEP,
+                    }),
+                    KeyCode::KeyJ => self.move_mount(types::MountCommand::Relative {
+                        pan: -MOUNT_STEP,
+                        tilt: 0.0,
+                    }),
+                    KeyCode::KeyL => self.move_mount(types::MountCommand::Relative {
+                        pan: MOUNT_STEP,
+                        tilt: 0.0,
+                    }),
+                    KeyCode::KeyH => self.move_mount(types::MountCommand::Center),
+                    KeyCode::KeyP => {
+                        data.connection_status = format!("Taking snapshot...");
+                        self.send_camera_command(data.main_camera, types::CameraCommand::Snapshot);
+                    }
+                    KeyCode::KeyR => self.toggle_recording(data),
+                    KeyCode::KeyT => self.toggle_timelapse(data),
+                    KeyCode::KeyM => self.toggle_motion_detection(data, false),
+                    KeyCode::KeyG => self.toggle_motion_detection(data, true),
+                    KeyCode::KeyO => self.toggle_blob_tracking(data, false),
+                    KeyCode::KeyU => self.toggle_blob_tracking(data, true),
+                    KeyCode::KeyW => self.toggle_line_following(data),
+                    KeyCode::KeyQ => self.toggle_marker_detection(data),
+                    KeyCode::KeyX => self.toggle_mission(data),
+                    KeyCode::KeyZ => self.pause_mission(data),
+                    KeyCode::KeyE => self.toggle_macro_recording(data),
+                    KeyCode::KeyA => self.replay_macro(data),
+                    KeyCode::KeyS => {
+                        data.connection_status = "Listing scripts...".to_string();
+                        self.list_scripts();
+                    }
+                    KeyCode::Key0 => self.stop_script(data),
+                    KeyCode::Key1 => self.start_script(data, 1),
+                    KeyCode::Key2 => self.start_script(data, 2),
+                    KeyCode::Key3 => self.start_script(data, 3),
+                    KeyCode::Key4 => self.start_script(data, 4),
+                    KeyCode::Key5 => self.start_script(data, 5),
+                    KeyCode::Key6 => self.start_script(data, 6),
+                    KeyCode::Key7 => self.start_script(data, 7),
+                    KeyCode::Key8 => self.start_script(data, 8),
+                    KeyCode::Key9 => self.start_script(data, 9),
+                    KeyCode::KeyN => {
+                        data.connection_status = format!("Downloading recordings...");
+                        self.download_recordings();
+                    }
+                    KeyCode::KeyC => {
+                        self.switch_camera(data);
+                        self.update_camera_panel(data);
+                    }
+                    KeyCode::KeyV => {
+                        data.camera_panel = !data.camera_panel;
+                        self.update_camera_panel(data);
+                    }
+                    KeyCode::Tab if data.camera_panel => {
+                        self.camera_panel_row += 1;
+                        let rows = match self.capabilities.get(data.main_camera) {
+                            Some(Some(res)) => res.controls.len() + 2,
+                            _ => 1,
+                        };
+                        self.camera_panel_row %= rows;
+                        self.update_camera_panel(data);
+                    }
+                    KeyCode::Minus if data.camera_panel => self.adjust_camera(data, false),
+                    KeyCode::Equals if data.camera_panel => self.adjust_camera(data, true),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        if cmd.is(GAMEPAD_COMMAND) {
+            match cmd.get_unchecked(GAMEPAD_COMMAND) {
+                gilrs::EventType::ButtonPressed(gilrs::Button::North, _) => {
+                    self.switch_camera(data)
+                }
+                gilrs::EventType::ButtonPressed(gilrs::Button::RightThumb, _) => {
+                    self.move_mount(types::MountCommand::Center)
+                }
+                gilrs::EventType::AxisChanged(axis, value, _) => {
+                    self.move_mount_with_stick(*axis, *value)
+                }
+                _ => {}
+            }
+        }
+        if cmd.is(CAMERA_CAPABILITIES_COMMAND) {
+            let (camera, capabilities) = cmd.get_unchecked(CAMERA_CAPABILITIES_COMMAND).clone();
+            if self.capabilities.len() <= camera {
+                self.capabilities.resize(camera + 1, None);
+            }
+            self.capabilities[camera] = Some(capabilities);
+            self.update_camera_panel(data);
+        }
+        if cmd.is(MOUNT_COMMAND) {
+            self.move_mount(cmd.get_unchecked(MOUNT_COMMAND).clone());
+        }
+        if cmd.is(VIDEO_SET_REGION_COMMAND) {
+            let region = cmd.get_unchecked(VIDEO_SET_REGION_COMMAND).clone();
+            self.send_camera_command(data.main_camera, types::CameraCommand::SetRegion(region));
+        }
+        if cmd.is(CAMERA_STATUS_COMMAND) {
+            let (camera, status) = cmd.get_unchecked(CAMERA_STATUS_COMMAND).clone();
+            if self.camera_statuses.len() <= camera {
+                self.camera_statuses.resize(camera + 1, None);
+            }
+            self.camera_statuses[camera] = status;
+            data.camera_status = self.main_camera_status(data);
+        }
+        if cmd.is(RECORDING_COMMAND) {
+            let (camera, recording) = cmd.get_unchecked(RECORDING_COMMAND).clone();
+            if self.recordings.len() <= camera {
+                self.recordings.resize(camera + 1, None);
+            }
+            let name = self.cameras.get(camera).cloned().unwrap_or_default();
+            data.connection_status = match recording {
+                Ok(Some(clip)) => {
+                    let status = format!("Recording {} to {}", name, clip);
+                    self.recordings[camera] = Some(clip);
+                    status
+                }
+                Ok(None) => {
+                    self.recordings[camera] = None;
+                    format!("Stopped recording {}", name)
+                }
+                Err(e) => {
+                    self.recordings[camera] = None;
+                    e
+                }
+            };
+            data.camera_status = self.main_camera_status(data);
+        }
+        if cmd.is(AUTOPILOT_COMMAND) {
+            let (camera, mode) = cmd.get_unchecked(AUTOPILOT_COMMAND).clone();
+            // The server runs one mode at a time. Manual input or a lost heartbeat cancel it,
+            // blob tracking goes on.
+            let mut cancelled = false;
+            if mode.as_deref() != Some(msg::AUTOPILOT_FOLLOW_BLOB) {
+                if let Some(Some(tracking)) = self.blob_tracking.get_mut(camera) {
+                    cancelled |= tracking.follow;
+                    tracking.follow = false;
+                }
+            }
+            if mode.as_deref() != Some(msg::AUTOPILOT_FOLLOW_LINE) {
+                if let Some(following) = self.line_following.get_mut(camera) {
+                    cancelled |= following.take().is_some();
+                }
+            }
+            if cancelled && mode.is_none() {
+                data.connection_status = format!("Autonomous mode is cancelled");
+            }
+            data.camera_status = self.main_camera_status(data);
+        }
+        if cmd.is(MACRO_COMMAND) && self.macro_replay.is_some() {
+            match *cmd.get_unchecked(MACRO_COMMAND) {
+                Some(state) => self.set_machine_state(data, state),
+                None => {
+                    self.macro_replay = None;
+                    self.set_machine_state(data, self.machine_state.halted());
+                    data.connection_status = "Macro replay is finished".to_string();
+                }
+            }
+        }
+        if cmd.is(SCRIPTS_COMMAND) {
+            data.connection_status = match cmd.get_unchecked(SCRIPTS_COMMAND) {
+                Ok(names) => {
+                    self.scripts = names.clone();
+                    let list: Vec<String> = names
+                        .iter()
+                        .enumerate()
+                        .map(|(i, name)| format!("{} {}", i + 1, name))
+                        .collect();
+                    if list.is_empty() {
+                        "No scripts on the server".to_string()
+                    } else {
+                        format!("Scripts: {}", list.join(", "))
+                    }
+                }
+                Err(e) => e.clone(),
+            };
+        }
+        if cmd.is(RECORDINGS_COMMAND) {
+            data.connection_status = cmd.get_unchecked(RECORDINGS_COMMAND).clone();
+        }
+        if cmd.is(JOURNAL_COMMAND) {
+            data.connection_status = cmd.get_unchecked(JOURNAL_COMMAND).clone();
+        }
+        if cmd.is(SNAPSHOT_COMMAND) {
+            data.connection_status = cmd.get_unchecked(SNAPSHOT_COMMAND).clone();
+        }
         if cmd.is(KEYBOARD_COMMAND) | cmd.is(GAMEPAD_COMMAND) {
             let mut event: Option<types::MachineEvents> = None;
             if cmd.is(KEYBOARD_COMMAND) {
                 event = match cmd.get_unchecked(KEYBOARD_COMMAND) {
                     Event::KeyDown(key) => match key.key_code {
-                        KeyCode::KeyL => Some(types::MachineEvents::LightTrigger),
+                        KeyCode::KeyF => Some(types::MachineEvents::LightTrigger),
                         KeyCode::ArrowUp => Some(types::MachineEvents::Forward),
                         KeyCode::ArrowDown => Some(types::MachineEvents::Backward),
                         KeyCode::ArrowRight => Some(types::MachineEvents::Right),
@@ -354,37 +1298,70 @@ impl AppDelegate<AppState> for Delegate {
             }
             match event {
                 Some(event) => match self.update_machine_state(event) {
-                    Some(ms) => {
-                        let mut status = "";
-                        if ms.forward {
-                            if ms.left {
-                                status = "↖";
-                            } else if ms.right {
-                                status = "↗";
-                            } else {
-                                status = "⬆";
-                            }
-                        } else if ms.backward {
-                            if ms.left {
-                                status = "↙";
-                            } else if ms.right {
-                                status = "↘";
-                            } else {
-                                status = "⬇";
-                            }
-                        } else if ms.left {
-                            status = "⬅";
-                        } else if ms.right {
-                            status = "➡";
-                        }
-                        data.direction_state = status.to_string();
-                        data.light_state = if ms.lamp_enabled { "💡" } else { "" }.to_string();
-                    }
+                    Some(ms) => show_machine_state(data, &ms),
                     None => {}
                 },
                 None => {}
             }
         }
+        if cmd.is(TELEMETRY_COMMAND) {
+            let telemetry = cmd.get_unchecked(TELEMETRY_COMMAND);
+            data.gps_status = match telemetry.gps {
+                Some(gps) if gps.has_fix => format!(
+                    "📍 {:.6}, {:.6} ({} sat)",
+                    gps.latitude, gps.longitude, gps.satellites
+                ),
+                Some(gps) => format!("📍 No fix ({} sat)", gps.satellites),
+                None => "".to_string(),
+            };
+            if let Some(fix) = &telemetry.marker_fix {
+                data.gps_status += &format!(" ⌖ {} ({:.1}, {:.1})", fix.payload, fix.x, fix.y);
+            }
+            if let Some(progress) = telemetry.mission.as_ref().filter(|m| m.is_active()) {
+                data.gps_status += &format!(
+                    " 🗺 {} {}/{}{}",
+                    progress.name,
+                    progress.step + 1,
+                    progress.steps,
+                    if progress.status == types::MissionStatus::Paused {
+                        " ⏸"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            if let (Some(last), Some(progress)) = (&self.mission, &telemetry.mission) {
+                if last.is_active() && !progress.is_active() {
+                    data.connection_status = match &progress.status {
+                        types::MissionStatus::Aborted(reason) => {
+                            format!("Mission {} is aborted: {}", progress.name, reason)
+                        }
+                        _ => format!("Mission {} is finished", progress.name),
+                    };
+                }
+            }
+            self.mission = telemetry.mission.clone();
+            if let Some(run) = telemetry.script.as_ref().filter(|s| s.running) {
+                data.gps_status += &format!(" 📜 {}", run.name);
+            }
+            if let (Some(last), Some(run)) = (&self.script, &telemetry.script) {
+                if last.running && !run.running {
+                    data.connection_status = match &run.error {
+                        Some(e) => format!("Script {} is stopped: {}", run.name, e),
+                        None => format!("Script {} is finished", run.name),
+                    };
+                }
+            }
+            self.script = telemetry.script.clone();
+            data.mount_status = match telemetry.mount {
+                Some(mount) => format!("🎥 {:.0}° {:.0}°", mount.pan, mount.tilt),
+                None => "".to_string(),
+            };
+            if self.timelapses != telemetry.timelapses {
+                self.timelapses = telemetry.timelapses.clone();
+                data.camera_status = self.main_camera_status(data);
+            }
+        }
         if cmd.is(CONNECTION_COMMAND) {
             match cmd.get_unchecked(CONNECTION_COMMAND) {
                 ConnectionEvent::InitConnect => {
@@ -394,9 +1371,16 @@ impl AppDelegate<AppState> for Delegate {
                         }
                     }
                 }
-                ConnectionEvent::Connected => {
+                ConnectionEvent::Connected(cameras) => {
                     data.connection_status = format!("");
                     data.is_connected = true;
+                    data.main_camera = 0;
+                    self.cameras = cameras.clone();
+                    data.camera_count = self.cameras.len();
+                    data.camera_name = match self.cameras.first() {
+                        Some(camera) => camera.clone(),
+                        None => "".to_string(),
+                    };
                     self.on_connected();
                 }
                 ConnectionEvent::InitDisconnect => {
@@ -408,7 +1392,25 @@ impl AppDelegate<AppState> for Delegate {
                 }
                 ConnectionEvent::Disconnected => {
                     data.connection_status = format!("");
+                    data.gps_status = "".to_string();
+                    data.mount_status = "".to_string();
                     data.is_connected = false;
+                    data.camera_panel = false;
+                    data.camera_status = "".to_string();
+                    self.capabilities.clear();
+                    self.camera_statuses.clear();
+                    self.recordings.clear();
+                    self.timelapses.clear();
+                    self.motion_detection.clear();
+                    self.blob_tracking.clear();
+                    self.line_following.clear();
+                    self.marker_detection.clear();
+                    self.mission = None;
+                    self.scripts.clear();
+                    self.script = None;
+                    if let Some(replay) = self.macro_replay.take() {
+                        replay.stop();
+                    }
                 }
                 ConnectionEvent::Error(e) => {
                     data.connection_status = format!("{}", e);
@@ -424,11 +1426,32 @@ impl AppDelegate<AppState> for Delegate {
     }
 }
 
+/// Mount movement of a single key press in degrees.
+const MOUNT_STEP: f32 = 5.0;
+/// Mount angle at the full deflection of the gamepad stick in degrees.
+const MOUNT_STICK_RANGE: f32 = 90.0;
+/// Mount movement when dragging over the whole video width in degrees.
+const MOUNT_DRAG_RANGE: f64 = 60.0;
+
+/// Zoom change of a single mouse wheel step.
+const ZOOM_STEP: f32 = 1.25;
+/// Smallest zoomed region relative to the full frame.
+const MIN_ZOOM_REGION: f32 = 0.1;
 pub struct MovingImage {
     image_data: std::vec::Vec<u8>,
     timestamp_ms: i64,
     size: Size,
+    pip_image_data: std::vec::Vec<u8>,
+    pip_size: Size,
     fill: FillStrat,
+    /// Region of the full frame requested from the server.
+    zoom: Option<types::Region>,
+    /// Mouse position and zoom region at the start of dragging.
+    drag: Option<(Point, types::Region)>,
+    /// Last mouse position while dragging the camera mount.
+    mount_drag: Option<Point>,
+    /// Annotations of the last frame of the main camera.
+    annotations: Vec<types::Annotation>,
 }
 
 impl MovingImage {
@@ -437,10 +1460,74 @@ impl MovingImage {
             image_data: vec![0; 0],
             timestamp_ms: 0,
             size: Size::new(0.0, 0.0),
+            pip_image_data: vec![0; 0],
+            pip_size: Size::new(0.0, 0.0),
             fill: FillStrat::default(),
+            zoom: None,
+            drag: None,
+            mount_drag: None,
+            annotations: vec![],
         }
     }
 
+    /// Map a region of the full frame to the shown image, which may be zoomed.
+    fn shown_rect(&self, region: &types::Region) -> Rect {
+        Rect::from_points(
+            self.shown_point((region.x, region.y)),
+            self.shown_point((region.x + region.width, region.y + region.height)),
+        )
+    }
+
+    /// Map a point of the full frame to the shown image, which may be zoomed.
+    fn shown_point(&self, (x, y): (f32, f32)) -> Point {
+        let zoom = self.zoom.unwrap_or(types::Region::full());
+        Point::new(
+            ((x - zoom.x) / zoom.width) as f64 * self.size.width,
+            ((y - zoom.y) / zoom.height) as f64 * self.size.height,
+        )
+    }
+
+    /// Position in the shown frame relative to its size.
+    fn frame_position(&self, size: Size, pos: Point) -> (f32, f32) {
+        let point = self.fill.affine_to_fill(size, self.size).inverse() * pos;
+        (
+            (point.x / self.size.width) as f32,
+            (point.y / self.size.height) as f32,
+        )
+    }
+
+    fn set_zoom(&mut self, ctx: &mut EventCtx, zoom: Option<types::Region>) {
+        if self.zoom != zoom {
+            self.zoom = zoom;
+            ctx.submit_command(Command::new(VIDEO_SET_REGION_COMMAND, zoom), None);
+        }
+    }
+
+    /// Zoom in or out keeping the point under the cursor in place.
+    fn zoom_at(&mut self, ctx: &mut EventCtx, pos: Point, zoom_in: bool) {
+        let (u, v) = self.frame_position(ctx.size(), pos);
+        let (u, v) = (u.max(0.0).min(1.0), v.max(0.0).min(1.0));
+        let current = self.zoom.unwrap_or(types::Region::full());
+        let factor = if zoom_in { 1.0 / ZOOM_STEP } else { ZOOM_STEP };
+        let side = (current.width * factor).max(MIN_ZOOM_REGION);
+        if side >= 1.0 {
+            self.set_zoom(ctx, None);
+            return;
+        }
+
+        let x = current.x + u * (current.width - side);
+        let y = current.y + v * (current.height - side);
+        self.set_zoom(
+            ctx,
+            Some(types::Region {
+                x: x.max(0.0).min(1.0 - side),
+                y: y.max(0.0).min(1.0 - side),
+                width: side,
+                height: side,
+            }),
+        );
+    }
+
     pub fn fill_mode(mut self, mode: FillStrat) -> Self {
         self.fill = mode;
         self
@@ -450,20 +1537,75 @@ impl MovingImage {
 impl Widget<AppState> for MovingImage {
     fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState, _env: &Env) {
         match event {
+            Event::Wheel(mouse) if self.image_data.len() > 0 => {
+                self.zoom_at(ctx, mouse.pos, mouse.wheel_delta.y < 0.0);
+            }
+            Event::MouseDown(mouse) if self.image_data.len() > 0 => match mouse.button {
+                MouseButton::Left => {
+                    match self.zoom {
+                        Some(zoom) => self.drag = Some((mouse.pos, zoom)),
+                        None => self.mount_drag = Some(mouse.pos),
+                    }
+                    ctx.set_active(true);
+                }
+                MouseButton::Right => self.set_zoom(ctx, None),
+                _ => {}
+            },
+            Event::MouseMove(mouse) if ctx.is_active() => {
+                // Without zoom dragging moves the camera mount as if grabbing the view.
+                if let Some(last) = self.mount_drag {
+                    let scale = MOUNT_DRAG_RANGE / ctx.size().width.max(1.0);
+                    let command = types::MountCommand::Relative {
+                        pan: ((last.x - mouse.pos.x) * scale) as f32,
+                        tilt: ((mouse.pos.y - last.y) * scale) as f32,
+                    };
+                    ctx.submit_command(Command::new(MOUNT_COMMAND, command), None);
+                    self.mount_drag = Some(mouse.pos);
+                }
+                if let Some((origin, start)) = self.drag {
+                    let (u0, v0) = self.frame_position(ctx.size(), origin);
+                    let (u, v) = self.frame_position(ctx.size(), mouse.pos);
+                    let x = start.x - (u - u0) * start.width;
+                    let y = start.y - (v - v0) * start.height;
+                    self.set_zoom(
+                        ctx,
+                        Some(types::Region {
+                            x: x.max(0.0).min(1.0 - start.width),
+                            y: y.max(0.0).min(1.0 - start.height),
+                            ..start
+                        }),
+                    );
+                }
+            }
+            Event::MouseUp(_) if ctx.is_active() => {
+                self.drag = None;
+                self.mount_drag = None;
+                ctx.set_active(false);
+            }
             Event::Command(cmd) => {
                 if cmd.is(VIDEO_SET_FRAME_COMMAND) {
                     let rgb_image = cmd.get_unchecked(VIDEO_SET_FRAME_COMMAND);
                     let sizeofimage = &rgb_image.image.dimensions();
-                    self.image_data = rgb_image.image.to_vec();
-                    self.timestamp_ms = rgb_image.timestamp_ms;
-                    self.size = Size::new(sizeofimage.0 as f64, sizeofimage.1 as f64);
-                    ctx.request_paint();
+                    if rgb_image.camera == data.main_camera {
+                        self.image_data = rgb_image.image.to_vec();
+                        self.timestamp_ms = rgb_image.timestamp_ms;
+                        self.annotations = rgb_image.annotations.clone();
+                        self.size = Size::new(sizeofimage.0 as f64, sizeofimage.1 as f64);
+                        ctx.request_paint();
 
-                    data.video_height = sizeofimage.0 as u16;
-                    data.video_width = sizeofimage.1 as u16;
+                        data.video_height = sizeofimage.0 as u16;
+                        data.video_width = sizeofimage.1 as u16;
+                    } else if Some(rgb_image.camera) == data.pip_camera() {
+                        self.pip_image_data = rgb_image.image.to_vec();
+                        self.pip_size = Size::new(sizeofimage.0 as f64, sizeofimage.1 as f64);
+                        ctx.request_paint();
+                    }
                 }
                 if cmd.is(VIDEO_SET_FPS_COMMAND) {
-                    data.fps = cmd.get_unchecked(VIDEO_SET_FPS_COMMAND).clone();
+                    let (camera, fps) = cmd.get_unchecked(VIDEO_SET_FPS_COMMAND).clone();
+                    if camera == data.main_camera {
+                        data.fps = fps;
+                    }
                 }
             }
             _ => {}
@@ -479,7 +1621,12 @@ impl Widget<AppState> for MovingImage {
     ) {
     }
 
-    fn update(&mut self, _ctx: &mut UpdateCtx, _old_data: &AppState, _data: &AppState, _env: &Env) {
+    fn update(&mut self, _ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, _env: &Env) {
+        if old_data.main_camera != data.main_camera {
+            self.zoom = None;
+            self.drag = None;
+            self.annotations.clear();
+        }
     }
 
     fn layout(
@@ -498,54 +1645,128 @@ impl Widget<AppState> for MovingImage {
         }
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, _data: &AppState, _env: &Env) {
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, _env: &Env) {
         if self.image_data.len() == 0 {
             return;
         }
+        let size = ctx.size();
 
-        if self.fill != FillStrat::Contain {
-            let clip_rect = Rect::ZERO.with_size(ctx.size());
-            ctx.clip(clip_rect);
-        }
+        ctx.with_save(|ctx| {
+            if self.fill != FillStrat::Contain {
+                let clip_rect = Rect::ZERO.with_size(size);
+                ctx.clip(clip_rect);
+            }
 
-        let offset_matrix = self.fill.affine_to_fill(ctx.size(), self.size);
-        ctx.transform(offset_matrix);
+            let offset_matrix = self.fill.affine_to_fill(size, self.size);
+            ctx.transform(offset_matrix);
 
-        // Draw a video frame
-        match ctx.make_image(
-            self.size.width as usize,
-            self.size.height as usize,
-            &self.image_data,
-            ImageFormat::Rgb,
-        ) {
-            Ok(im) => {
-                ctx.draw_image(&im, self.size.to_rect(), InterpolationMode::NearestNeighbor);
-            }
-            Err(e) => {
-                error!("Failed to render a frame: {}", e);
+            // Draw a video frame
+            match ctx.make_image(
+                self.size.width as usize,
+                self.size.height as usize,
+                &self.image_data,
+                ImageFormat::Rgb,
+            ) {
+                Ok(im) => {
+                    ctx.draw_image(&im, self.size.to_rect(), InterpolationMode::NearestNeighbor);
+                }
+                Err(e) => {
+                    error!("Failed to render a frame: {}", e);
+                }
             }
-        }
 
-        // Draw timestamp
-        let duration =
-            std::time::UNIX_EPOCH + std::time::Duration::from_millis(self.timestamp_ms as u64);
-        let datetime = chrono::prelude::DateTime::<chrono::Local>::from(duration);
-        let timestamp_str = datetime.format("%Y-%m-%d %H:%M:%S.%3f").to_string();
+            // Draw timestamp
+            let duration =
+                std::time::UNIX_EPOCH + std::time::Duration::from_millis(self.timestamp_ms as u64);
+            let datetime = chrono::prelude::DateTime::<chrono::Local>::from(duration);
+            let timestamp_str = datetime.format("%Y-%m-%d %H:%M:%S.%3f").to_string();
 
-        let font = ctx
-            .text()
-            .new_font_by_name("Default", 16.0)
-            .build()
-            .unwrap();
-        let layout = ctx
-            .text()
-            .new_text_layout(&font, &timestamp_str, std::f64::INFINITY)
-            .build()
-            .unwrap();
+            let font = ctx
+                .text()
+                .new_font_by_name("Default", 16.0)
+                .build()
+                .unwrap();
+            let layout = ctx
+                .text()
+                .new_text_layout(&font, &timestamp_str, std::f64::INFINITY)
+                .build()
+                .unwrap();
 
-        ctx.with_save(|ctx| {
-            ctx.draw_text(&layout, (20.0, 20.0), &VIDEO_OVERLAY_COLOR);
+            ctx.with_save(|ctx| {
+                ctx.draw_text(&layout, (20.0, 20.0), &VIDEO_OVERLAY_COLOR);
+            });
+
+            // Draw annotations of the server analyses
+            ctx.with_save(|ctx| {
+                let bounds = self.size.to_rect();
+                ctx.clip(bounds);
+                for annotation in &self.annotations {
+                    let (r, g, b) = annotation.color;
+                    let color = Color::rgb8(r, g, b);
+                    let (text, position) = match &annotation.shape {
+                        types::AnnotationShape::Box { region, label } => {
+                            let rect = self.shown_rect(region).intersect(bounds);
+                            if rect.area() <= 0.0 {
+                                continue;
+                            }
+                            ctx.stroke(rect, &color, 2.0);
+                            (
+                                label.as_ref(),
+                                Point::new(rect.x0, (rect.y0 - 4.0).max(16.0)),
+                            )
+                        }
+                        types::AnnotationShape::Line { from, to } => {
+                            let line = druid::kurbo::Line::new(
+                                self.shown_point(*from),
+                                self.shown_point(*to),
+                            );
+                            ctx.stroke(line, &color, 3.0);
+                            continue;
+                        }
+                        types::AnnotationShape::Point { x, y } => {
+                            let dot = druid::kurbo::Circle::new(self.shown_point((*x, *y)), 4.0);
+                            ctx.fill(dot, &color);
+                            continue;
+                        }
+                        types::AnnotationShape::Text { x, y, text } => {
+                            (Some(text), self.shown_point((*x, *y)))
+                        }
+                    };
+                    if let Some(text) = text {
+                        let layout = ctx
+                            .text()
+                            .new_text_layout(&font, text, std::f64::INFINITY)
+                            .build()
+                            .unwrap();
+                        ctx.draw_text(&layout, position, &color);
+                    }
+                }
+            });
         });
+
+        // Draw a picture-in-picture in the bottom right corner
+        if data.pip_camera().is_some() && self.pip_image_data.len() > 0 {
+            let width = size.width / 4.0;
+            let height = width * self.pip_size.height / self.pip_size.width;
+            let rect = Rect::from_origin_size(
+                (size.width - width - 10.0, size.height - height - 10.0),
+                (width, height),
+            );
+            match ctx.make_image(
+                self.pip_size.width as usize,
+                self.pip_size.height as usize,
+                &self.pip_image_data,
+                ImageFormat::Rgb,
+            ) {
+                Ok(im) => {
+                    ctx.draw_image(&im, rect, InterpolationMode::Bilinear);
+                    ctx.stroke(rect, &VIDEO_OVERLAY_COLOR, 1.0);
+                }
+                Err(e) => {
+                    error!("Failed to render a picture-in-picture frame: {}", e);
+                }
+            }
+        }
     }
 }
 
@@ -620,15 +1841,25 @@ pub fn build_ui() -> impl Widget<AppState> {
     left_block.add_child(
         Align::centered(Label::new(|d: &AppState, _: &Env| {
             if d.is_connected {
-                format!("{} x {}", d.video_width, d.video_height)
+                format!("{} {} x {}", d.camera_name, d.video_width, d.video_height)
             } else {
                 "".to_string()
             }
         }))
-        .fix_width(60.0),
+        .fix_width(120.0),
     );
+    left_block.add_child(Label::new(|d: &AppState, _: &Env| {
+        format!("{}", d.camera_status)
+    }));
 
     let mut right_block = Flex::row();
+    right_block.add_child(
+        Label::new(|d: &AppState, _: &Env| format!("{}", d.mount_status))
+            .padding((0.0, 0.0, 10.0, 0.0)),
+    );
+    right_block.add_child(Label::new(|d: &AppState, _: &Env| {
+        format!("{}", d.gps_status)
+    }));
     right_block.add_child(
         Align::centered(Label::new(|d: &AppState, _: &Env| {
             format!("{}", d.light_state)
@@ -642,7 +1873,8 @@ pub fn build_ui() -> impl Widget<AppState> {
         .fix_width(30.0),
     );
 
-    col.add_flex_child(
+    let mut video_block = Flex::row();
+    video_block.add_flex_child(
         ViewSwitcher::new(
             |data: &AppState, _env| data.is_connected,
             |selector, _data, _env| match selector {
@@ -657,6 +1889,22 @@ pub fn build_ui() -> impl Widget<AppState> {
         ),
         1.0,
     );
+    video_block.add_child(ViewSwitcher::new(
+        |data: &AppState, _env| data.is_connected && data.camera_panel,
+        |selector, _data, _env| match selector {
+            true => Box::new(
+                Align::vertical(
+                    UnitPoint::TOP_LEFT,
+                    Label::new(|d: &AppState, _: &Env| d.camera_panel_text.clone()).padding(10.0),
+                )
+                .fix_width(260.0)
+                .expand_height()
+                .background(BASE_LIGHT_BG_COLOR),
+            ),
+            false => Box::new(SizedBox::empty()),
+        },
+    ));
+    col.add_flex_child(video_block, 1.0);
     col.add_flex_child(
         Split::columns(Align::left(left_block), Align::right(right_block))
             .bar_size(0.0)
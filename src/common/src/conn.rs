@@ -2,12 +2,18 @@ extern crate bincode;
 
 use std::io;
 use std::io::{Read, Write};
-use std::net::TcpStream;
 use std::thread;
 use std::time;
 
 pub trait MessageStream {
     fn write_msg<T: ?Sized>(&mut self, value: &T) -> Result<usize, io::Error>
+    where
+        T: serde::Serialize;
+    fn write_msg_with_payload<T: ?Sized>(
+        &mut self,
+        value: &T,
+        payload: &[u8],
+    ) -> Result<usize, io::Error>
     where
         T: serde::Serialize;
     fn read_msg<'a, T: ?Sized + Clone>(&mut self, buf: &'a mut Vec<u8>) -> Result<T, io::Error>
@@ -15,7 +21,7 @@ pub trait MessageStream {
         T: serde::Deserialize<'a>;
 }
 
-impl MessageStream for TcpStream {
+impl<S: Read + Write> MessageStream for S {
     /// Write message to the stream.
     fn write_msg<T: ?Sized>(&mut self, value: &T) -> Result<usize, io::Error>
     where
@@ -51,6 +57,63 @@ impl MessageStream for TcpStream {
         }
     }
 
+    /// Write message to the stream followed by a raw payload.
+    ///
+    /// The payload is written straight from the given buffer together with the message
+    /// in vectored writes. The reader must know the payload size from the message.
+    fn write_msg_with_payload<T: ?Sized>(
+        &mut self,
+        value: &T,
+        payload: &[u8],
+    ) -> Result<usize, io::Error>
+    where
+        T: serde::Serialize,
+    {
+        let mut header = match bincode::serialize(&value) {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Failed to serialize frame. {}", e),
+                ))
+            }
+        };
+        if header.len() > (u32::MAX as usize) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Data too large to fit into frame.",
+            ));
+        }
+        header.splice(0..0, (header.len() as u32).to_be_bytes().iter().cloned());
+
+        let (mut header_pos, mut payload_pos) = (0, 0);
+        while header_pos < header.len() || payload_pos < payload.len() {
+            let written = match self.write_vectored(&[
+                io::IoSlice::new(&header[header_pos..]),
+                io::IoSlice::new(&payload[payload_pos..]),
+            ]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Failed to write frame. Connection is closed.",
+                    ))
+                }
+                Ok(size) => size,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("Failed to write frame. {}", e),
+                    ))
+                }
+            };
+            let from_header = written.min(header.len() - header_pos);
+            header_pos += from_header;
+            payload_pos += written - from_header;
+        }
+        Ok(header.len() + payload.len())
+    }
+
     /// Read message from the stream.
     ///
     /// TODO: fight the borrow checker and initialize the buffer within a function.
@@ -1,13 +1,26 @@
 extern crate serde;
 
 use self::serde::{Deserialize, Serialize};
-use settings::{Controller, Heartbeat, Video};
+use settings::{
+    Controller, Heartbeat as HeartbeatSettings, Journal as JournalSettings,
+    Telemetry as TelemetrySettings, Video as VideoSettings,
+};
+use types::{
+    Annotation, CameraCapabilities, CameraCommand, GpsFix, LossPolicy, MachineState, MarkerFix,
+    MissionCommand, MissionProgress, MountCommand, MountPosition, Orientation, ScriptCommand,
+    ScriptRun, TimelapseCommand,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ConnectionType {
-    Session(Heartbeat),
-    Video(Video),
+    Session(HeartbeatSettings),
+    Video(VideoSettings),
     Controller(Controller),
+    Telemetry(TelemetrySettings),
+    Journal(JournalSettings),
+    Recordings(RecordingsRequest),
+    /// List the scripts the server can run.
+    Scripts,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -21,13 +34,21 @@ pub struct RequestConnection {
 pub struct OpenSession {
     pub ok: bool,
     pub session_id: Option<String>,
+    pub loss_policy: Option<LossPolicy>,
     pub error: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Heartbeat {
+    pub timestamp_ms: i64,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct OpenVideoConnection {
     pub ok: bool,
     pub error: Option<String>,
+    /// Transform the client must apply to every frame.
+    pub orientation: Orientation,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -36,8 +57,194 @@ pub struct OpenControllerConnection {
     pub error: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OpenTelemetryConnection {
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Telemetry {
+    pub timestamp_ms: i64,
+    pub gps: Option<GpsFix>,
+    /// Camera mount position when a mount is installed.
+    pub mount: Option<MountPosition>,
+    /// Cameras with a running timelapse.
+    pub timelapses: Vec<String>,
+    /// Position known from the last seen marker with a configured position.
+    pub marker_fix: Option<MarkerFix>,
+    /// Progress of the last mission started since the server start.
+    pub mission: Option<MissionProgress>,
+    /// State of the last script started since the server start.
+    pub script: Option<ScriptRun>,
+}
+
+/// Header of a video frame. It is followed by `size` bytes of JPEG data
+/// outside of the message, so the frame can be sent without copying.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct VideoFrame {
+    pub size: u32,
+    pub timestamp_ms: i64,
+    /// Results of frame analyses on the server, e.g. motion or found markers.
+    pub annotations: Vec<Annotation>,
+}
+
+/// Receiver report sent by the client for every processed frame.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VideoFeedback {
+    /// Total number of frames received on the connection.
+    pub frames_received: u64,
+    /// Server timestamp of the last processed frame.
+    pub last_timestamp_ms: i64,
+    /// Time spent on decoding the last frame.
+    pub decode_ms: u32,
+    /// Decoded frames waiting to be rendered.
+    pub queue_depth: u32,
+}
+
+/// Still frame captured on request.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub camera: String,
+    pub timestamp_ms: i64,
+    pub resolution: (u32, u32),
+    /// Transform the client must apply to show the image upright.
+    pub orientation: Orientation,
+    pub telemetry: Telemetry,
+    /// JPEG data.
     pub data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Human readable description of when and where the snapshot was taken.
+    pub fn metadata(&self) -> String {
+        let mut lines = vec![
+            format!("camera: {}", self.camera),
+            format!("timestamp_ms: {}", self.timestamp_ms),
+            format!("resolution: {}x{}", self.resolution.0, self.resolution.1),
+            format!(
+                "orientation: rotate {}, hflip {}, vflip {}",
+                self.orientation.rotate, self.orientation.hflip, self.orientation.vflip
+            ),
+        ];
+        match self.telemetry.gps {
+            Some(gps) if gps.has_fix => {
+                lines.push(format!("latitude: {:.7}", gps.latitude));
+                lines.push(format!("longitude: {:.7}", gps.longitude));
+                lines.push(format!("altitude: {:.1}", gps.altitude));
+                lines.push(format!("speed_kmh: {:.2}", gps.speed_kmh));
+                lines.push(format!("satellites: {}", gps.satellites));
+            }
+            Some(gps) => lines.push(format!("gps: no fix ({} satellites)", gps.satellites)),
+            None => {}
+        }
+        if let Some(mount) = self.telemetry.mount {
+            lines.push(format!("mount_pan: {:.1}", mount.pan));
+            lines.push(format!("mount_tilt: {:.1}", mount.tilt));
+        }
+        if let Some(fix) = &self.telemetry.marker_fix {
+            lines.push(format!("marker: {}", fix.payload));
+            lines.push(format!("marker_x: {:.2}", fix.x));
+            lines.push(format!("marker_y: {:.2}", fix.y));
+        }
+        if let Some(mission) = self.telemetry.mission.as_ref().filter(|m| m.is_active()) {
+            lines.push(format!("mission: {}", mission.name));
+            lines.push(format!("mission_step: {}", mission.step + 1));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// Autonomous mode following a color blob.
+pub const AUTOPILOT_FOLLOW_BLOB: &str = "follow";
+/// Autonomous mode following a line.
+pub const AUTOPILOT_FOLLOW_LINE: &str = "line";
+
+/// Message of a video connection.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Video {
+    Frame(VideoFrame),
+    Capabilities(CameraCapabilities),
+    /// Problem with the camera, e.g. it is reconnecting. `None` when streaming again.
+    Status(Option<String>),
+    /// Reply to a snapshot request.
+    Snapshot(Result<Snapshot, String>),
+    /// Name of the clip being recorded, `None` when the recording is stopped.
+    Recording(Result<Option<String>, String>),
+    /// Autonomous mode driving the machine, e.g. `AUTOPILOT_FOLLOW_BLOB`.
+    /// `None` when it's stopped or cancelled.
+    Autopilot(Option<String>),
+}
+
+/// Message of a controller connection.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Control {
+    State(MachineState),
+    Camera(String, CameraCommand),
+    Mount(MountCommand),
+    Timelapse(String, TimelapseCommand),
+    Mission(MissionCommand),
+    Script(ScriptCommand),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum JournalEvent {
+    /// State received from the client.
+    Command(MachineState),
+    /// State applied to the machine outputs.
+    Output(MachineState),
+    Session(String),
+    Telemetry(Telemetry),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JournalRecord {
+    pub timestamp_ms: i64,
+    pub event: JournalEvent,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Journal {
+    pub ok: bool,
+    pub records: Vec<JournalRecord>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum RecordingsRequest {
+    List,
+    /// Download a clip by its name.
+    Download(String),
+}
+
+/// Video clip recorded on the server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Clip {
+    pub name: String,
+    pub size: u64,
+    /// Time of the last modification.
     pub timestamp_ms: i64,
 }
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Recordings {
+    pub ok: bool,
+    pub clips: Vec<Clip>,
+    pub error: Option<String>,
+}
+
+/// Header of a downloaded clip, `size` bytes of the file follow it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecordingDownload {
+    pub ok: bool,
+    pub size: u64,
+    pub error: Option<String>,
+}
+
+/// Names of the scripts in the server script directory.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Scripts {
+    pub ok: bool,
+    pub names: Vec<String>,
+    pub error: Option<String>,
+}
@@ -3,6 +3,7 @@ extern crate serde;
 
 use self::config::{Config, ConfigError, File};
 use self::serde::{Deserialize, Serialize};
+use types::{ColorRange, SentryActions, TimelapseFormat};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Connection {
@@ -11,11 +12,33 @@ pub struct Connection {
     pub token: String,
 }
 
+/// Frames re-encoded by the server for low-bandwidth links.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VideoProfile {
+    /// Bounding box of the frames in the same format as `Video::resolution`.
+    /// Frames are downscaled to fit it keeping the aspect ratio.
+    pub resolution: (u32, u32),
+    /// JPEG quality from 1 to 100.
+    pub quality: u8,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Video {
-    pub device: String,
+    /// Camera requested by a video connection.
+    #[serde(default)]
+    pub camera: String,
+    /// Cameras opened by the client. The first one is the main camera.
+    #[serde(default)]
+    pub cameras: Vec<String>,
     pub resolution: (u32, u32),
     pub max_framerate: u8,
+    /// Latency the server keeps the stream under by dropping frames and lowering quality.
+    /// Zero disables the adaptation.
+    #[serde(default)]
+    pub max_latency_ms: u32,
+    /// Camera frames are forwarded as is when it's not set.
+    #[serde(default)]
+    pub profile: Option<VideoProfile>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -27,18 +50,179 @@ pub struct Heartbeat {
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Controller {}
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Telemetry {
+    pub interval: u16,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Telemetry { interval: 500 }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Journal {
+    pub minutes: u32,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal { minutes: 10 }
+    }
+}
+
+/// Timelapse started by the client on the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Timelapse {
+    /// Seconds between captured frames.
+    pub interval_s: u32,
+    pub format: TimelapseFormat,
+}
+
+impl Default for Timelapse {
+    fn default() -> Self {
+        Timelapse {
+            interval_s: 10,
+            format: TimelapseFormat::Images,
+        }
+    }
+}
+
+/// Motion detection started by the client on the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Motion {
+    /// Share of changed pixels from 0 to 1 from which motion is reported.
+    pub min_score: f32,
+    /// Actions of the sentry mode.
+    #[serde(default)]
+    pub sentry: SentryActions,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Motion {
+            min_score: 0.02,
+            sentry: SentryActions {
+                snapshot: true,
+                record: true,
+                lamp: false,
+            },
+        }
+    }
+}
+
+/// Color blob tracked by the server and followed on demand.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Follow {
+    pub color: ColorRange,
+    /// Height of the blob relative to the frame height the machine keeps by driving.
+    pub target_size: f32,
+}
+
+impl Default for Follow {
+    fn default() -> Self {
+        Follow {
+            color: ColorRange {
+                hue: 0.0,
+                hue_tolerance: 15.0,
+                min_saturation: 0.5,
+                min_value: 0.3,
+            },
+            target_size: 0.3,
+        }
+    }
+}
+
+/// Line followed autonomously by the machine.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Line {
+    /// Follow a line brighter than the floor instead of a darker one.
+    pub bright: bool,
+    /// Brightness from 0 to 1 which separates the line from the floor.
+    pub threshold: f32,
+    /// Share of time the engines run, from 0 to 1.
+    pub speed: f32,
+}
+
+impl Default for Line {
+    fn default() -> Self {
+        Line {
+            bright: false,
+            threshold: 0.35,
+            speed: 0.6,
+        }
+    }
+}
+
+/// Mission uploaded to the server on demand.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Mission {
+    /// TOML file with the mission steps.
+    pub file: String,
+}
+
+impl Default for Mission {
+    fn default() -> Self {
+        Mission {
+            file: "Mission.toml".to_string(),
+        }
+    }
+}
+
+/// Recorded manual maneuvers.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Macros {
+    /// TOML file a macro is recorded to and replayed from.
+    pub file: String,
+}
+
+impl Default for Macros {
+    fn default() -> Self {
+        Macros {
+            file: "Macro.toml".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Settings {
     pub connection: Connection,
     pub heartbeat: Heartbeat,
     pub video: Video,
     pub controller: Controller,
+    #[serde(default)]
+    pub telemetry: Telemetry,
+    #[serde(default)]
+    pub journal: Journal,
+    #[serde(default)]
+    pub timelapse: Timelapse,
+    #[serde(default)]
+    pub motion: Motion,
+    #[serde(default)]
+    pub follow: Follow,
+    #[serde(default)]
+    pub line: Line,
+    #[serde(default)]
+    pub mission: Mission,
+    #[serde(default)]
+    pub macros: Macros,
 }
 
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
         let mut s = Config::new();
         s.merge(File::with_name("Settings.toml"))?;
-        s.try_into()
+        let mut settings: Settings = s.try_into()?;
+        // A single `camera` is accepted for configs written before multiple cameras.
+        if settings.video.cameras.is_empty() && !settings.video.camera.is_empty() {
+            settings.video.cameras = vec![settings.video.camera.clone()];
+        }
+        if settings.video.cameras.is_empty() {
+            return Err(ConfigError::Message(
+                "No cameras are set in video.cameras".to_string(),
+            ));
+        }
+        Ok(settings)
     }
 }
@@ -5,8 +5,11 @@ use self::serde::{Deserialize, Serialize};
 use std::fmt;
 
 pub struct VideoFrame {
+    /// Index of the camera in the `video.cameras` settings.
+    pub camera: usize,
     pub image: image::RgbImage,
     pub timestamp_ms: i64,
+    pub annotations: Vec<Annotation>,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -38,6 +41,459 @@ impl fmt::Debug for MachineState {
 }
 impl Eq for MachineState {}
 
+/// What the machine does when the client heartbeat is lost.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum LossPolicy {
+    /// Stop engines immediately.
+    Stop,
+    /// Keep the last movement with a decreasing duty cycle for the given number of milliseconds.
+    Coast(u32),
+    /// Retrace movements since the last good-signal position, up to the given number of milliseconds.
+    ReturnHome(u32),
+}
+
+impl fmt::Display for LossPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LossPolicy::Stop => write!(f, "stop"),
+            LossPolicy::Coast(ms) => write!(f, "coast for {} ms", ms),
+            LossPolicy::ReturnHome(ms) => write!(f, "return home (up to {} ms)", ms),
+        }
+    }
+}
+
+/// Position reported by a GPS receiver.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
+pub struct GpsFix {
+    pub has_fix: bool,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f32,
+    pub speed_kmh: f32,
+    pub satellites: u8,
+}
+
+/// How a camera image must be transformed to be shown upright.
+///
+/// The image is rotated clockwise first, then flipped.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+pub struct Orientation {
+    /// Clockwise rotation in degrees: 0, 90, 180 or 270.
+    #[serde(default)]
+    pub rotate: u16,
+    #[serde(default)]
+    pub hflip: bool,
+    #[serde(default)]
+    pub vflip: bool,
+}
+
+impl Orientation {
+    pub fn is_valid(&self) -> bool {
+        [0, 90, 180, 270].contains(&self.rotate)
+    }
+
+    /// Map a region of a transformed image back to the camera image.
+    pub fn source_region(&self, region: Region) -> Region {
+        let map = |x: f32, y: f32| {
+            let (x, y) = (
+                if self.hflip { 1.0 - x } else { x },
+                if self.vflip { 1.0 - y } else { y },
+            );
+            match self.rotate {
+                90 => (y, 1.0 - x),
+                180 => (1.0 - x, 1.0 - y),
+                270 => (1.0 - y, x),
+                _ => (x, y),
+            }
+        };
+        let (x1, y1) = map(region.x, region.y);
+        let (x2, y2) = map(region.x + region.width, region.y + region.height);
+        Region {
+            x: x1.min(x2),
+            y: y1.min(y2),
+            width: (x2 - x1).abs(),
+            height: (y2 - y1).abs(),
+        }
+    }
+
+    /// Map a region of the camera image to the transformed image.
+    pub fn view_region(&self, region: Region) -> Region {
+        let map = |x: f32, y: f32| {
+            let (x, y) = match self.rotate {
+                90 => (1.0 - y, x),
+                180 => (1.0 - x, 1.0 - y),
+                270 => (y, 1.0 - x),
+                _ => (x, y),
+            };
+            (
+                if self.hflip { 1.0 - x } else { x },
+                if self.vflip { 1.0 - y } else { y },
+            )
+        };
+        let (x1, y1) = map(region.x, region.y);
+        let (x2, y2) = map(region.x + region.width, region.y + region.height);
+        Region {
+            x: x1.min(x2),
+            y: y1.min(y2),
+            width: (x2 - x1).abs(),
+            height: (y2 - y1).abs(),
+        }
+    }
+
+    pub fn apply(&self, img: image::DynamicImage) -> image::DynamicImage {
+        let mut img = match self.rotate {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            270 => img.rotate270(),
+            _ => img,
+        };
+        if self.hflip {
+            img = img.fliph();
+        }
+        if self.vflip {
+            img = img.flipv();
+        }
+        img
+    }
+}
+
+/// Rectangle of an image in coordinates relative to its size, from 0 to 1.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Region {
+    pub fn full() -> Self {
+        Region {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+
+    /// Pixel rectangle `(x, y, width, height)` of an image of the given size.
+    /// It is at least one pixel and never goes outside of the image.
+    pub fn to_pixels(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x = ((self.x.max(0.0) * width as f32) as u32).min(width - 1);
+        let y = ((self.y.max(0.0) * height as f32) as u32).min(height - 1);
+        let w = ((self.width * width as f32) as u32).max(1).min(width - x);
+        let h = ((self.height * height as f32) as u32)
+            .max(1)
+            .min(height - y);
+        (x, y, w, h)
+    }
+}
+
+/// Motion found between consecutive frames of a camera.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Motion {
+    /// Share of changed pixels from 0 to 1.
+    pub score: f32,
+    /// Areas of the image with motion.
+    pub regions: Vec<Region>,
+}
+
+/// What the server does when motion appears while the machine is parked.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+pub struct SentryActions {
+    /// Save a snapshot on the server.
+    #[serde(default)]
+    pub snapshot: bool,
+    /// Record the camera until the motion is over.
+    #[serde(default)]
+    pub record: bool,
+    /// Turn the lamp on.
+    #[serde(default)]
+    pub lamp: bool,
+}
+
+/// Motion detection of a video stream.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct MotionDetection {
+    /// Score from which motion is reported.
+    pub min_score: f32,
+    /// Actions on motion, `None` when only reports are sent.
+    pub sentry: Option<SentryActions>,
+}
+
+/// Range of colors in the HSV space.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct ColorRange {
+    /// Hue in degrees from 0 to 360.
+    pub hue: f32,
+    /// Largest hue difference in degrees.
+    pub hue_tolerance: f32,
+    /// Smallest saturation from 0 to 1.
+    pub min_saturation: f32,
+    /// Smallest value (brightness) from 0 to 1.
+    pub min_value: f32,
+}
+
+/// Tracking of a color blob in a video stream.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct BlobTracking {
+    pub color: ColorRange,
+    /// Drive the machine to keep the blob centered at `target_size`.
+    pub follow: bool,
+    /// Height of the followed blob relative to the frame height, from 0 to 1.
+    pub target_size: f32,
+}
+
+/// Autonomous following of a line on the floor.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct LineFollowing {
+    /// Follow a line brighter than the floor instead of a darker one.
+    pub bright: bool,
+    /// Brightness from 0 to 1 which separates the line from the floor.
+    pub threshold: f32,
+    /// Share of time the engines run, from 0 to 1.
+    pub speed: f32,
+}
+
+/// Line found in the bottom of a frame.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct LinePosition {
+    /// Horizontal position of the line center from 0 (left) to 1 (right).
+    pub x: f32,
+    /// Part of the frame the line is searched in.
+    pub band: Region,
+}
+
+/// Marker, e.g. a QR code, found in a frame.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Marker {
+    /// Decoded content.
+    pub payload: String,
+    /// Bounding box in coordinates of the image shown by the client. Its size is
+    /// the apparent size of the marker.
+    pub region: Region,
+}
+
+/// Position of the machine known from the last seen marker with a configured position.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MarkerFix {
+    pub payload: String,
+    /// Position of the marker in metres of the configured map.
+    pub x: f64,
+    pub y: f64,
+    /// Apparent height of the marker relative to the frame height. Larger is closer.
+    pub size: f32,
+    pub timestamp_ms: i64,
+}
+
+/// Drawing over a video frame attached by server-side analysis.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub shape: AnnotationShape,
+    /// RGB color.
+    pub color: (u8, u8, u8),
+}
+
+/// Shape of an annotation. Coordinates are relative to the size of the image shown
+/// by the client, from 0 to 1.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AnnotationShape {
+    /// Rectangle outline with an optional label above it.
+    Box {
+        region: Region,
+        label: Option<String>,
+    },
+    Line {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    Point {
+        x: f32,
+        y: f32,
+    },
+    Text {
+        x: f32,
+        y: f32,
+        text: String,
+    },
+}
+
+/// Adjustable camera control, e.g. brightness or exposure.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CameraControl {
+    pub id: u32,
+    pub name: String,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub step: i32,
+    pub default: i32,
+    pub value: i32,
+}
+
+/// Current stream parameters and what can be changed at runtime.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CameraCapabilities {
+    /// Pixel formats reported by the device.
+    pub formats: Vec<String>,
+    /// Resolutions both allowed by the server and supported by the device.
+    pub resolutions: Vec<(u32, u32)>,
+    pub max_framerate: u8,
+    pub resolution: (u32, u32),
+    pub framerate: u8,
+    pub controls: Vec<CameraControl>,
+}
+
+/// Changes of a live video stream.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CameraCommand {
+    SetResolution((u32, u32)),
+    SetFramerate(u8),
+    SetControl(u32, i32),
+    /// Crop frames to a region of the image as shown by the client, `None` for the full frame.
+    SetRegion(Option<Region>),
+    /// Capture a still frame at the largest allowed resolution.
+    Snapshot,
+    /// Record the camera frames into clips on the server.
+    StartRecording,
+    StopRecording,
+    /// Detect motion in the frames, `None` to stop.
+    SetMotionDetection(Option<MotionDetection>),
+    /// Track a color blob in the frames, `None` to stop.
+    SetBlobTracking(Option<BlobTracking>),
+    /// Follow a line seen by the camera, `None` to stop.
+    SetLineFollowing(Option<LineFollowing>),
+    /// Look for markers like QR codes in the frames.
+    SetMarkerDetection(bool),
+}
+
+/// Output of a timelapse.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelapseFormat {
+    /// Numbered JPEG files in a directory.
+    Images,
+    /// MJPEG AVI file.
+    Mjpeg,
+}
+
+/// Frames captured on the server at fixed intervals. A timelapse keeps running
+/// when the client disconnects.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum TimelapseCommand {
+    Start {
+        interval_s: u32,
+        format: TimelapseFormat,
+    },
+    Stop,
+}
+
+/// Step of a mission. Speeds are shares of the full speed from 0 to 1.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MissionStep {
+    /// Drive straight, backward with a negative speed.
+    Drive {
+        speed: f32,
+        duration_ms: u32,
+    },
+    /// Turn in place by an angle in degrees, positive to the right.
+    Turn {
+        angle: f32,
+    },
+    Lamp {
+        enabled: bool,
+    },
+    /// Take a snapshot with a camera streamed to the client.
+    Snapshot {
+        camera: String,
+    },
+    Wait {
+        duration_ms: u32,
+    },
+}
+
+/// Program of timed steps which the server executes on its own.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mission {
+    pub name: String,
+    pub steps: Vec<MissionStep>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum MissionCommand {
+    Start(Mission),
+    Pause,
+    Resume,
+    Abort,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum MissionStatus {
+    Running,
+    Paused,
+    Finished,
+    /// The reason why the mission was stopped.
+    Aborted(String),
+}
+
+/// Progress of the last started mission.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MissionProgress {
+    pub name: String,
+    /// Index of the current step. It equals `steps` when the mission is finished.
+    pub step: usize,
+    pub steps: usize,
+    pub status: MissionStatus,
+}
+
+impl MissionProgress {
+    /// Whether the mission is running or paused.
+    pub fn is_active(&self) -> bool {
+        matches!(self.status, MissionStatus::Running | MissionStatus::Paused)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ScriptCommand {
+    /// Start a script of the server script directory by its name.
+    Start(String),
+    Stop,
+}
+
+/// State of the last started script.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScriptRun {
+    pub name: String,
+    pub running: bool,
+    /// Why the script stopped before its end.
+    pub error: Option<String>,
+}
+
+/// Angles of a pan/tilt camera mount in degrees relative to its center.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+pub struct MountPosition {
+    /// Positive to the right.
+    pub pan: f32,
+    /// Positive up.
+    pub tilt: f32,
+}
+
+/// Movement of a pan/tilt camera mount. Angles are in degrees.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum MountCommand {
+    /// Move to the angles relative to the center.
+    Absolute {
+        pan: f32,
+        tilt: f32,
+    },
+    /// Move by the angles from the current position.
+    Relative {
+        pan: f32,
+        tilt: f32,
+    },
+    Center,
+}
+
 pub enum MachineEvents {
     Forward,
     Backward,
@@ -61,6 +517,25 @@ impl MachineState {
         }
     }
 
+    /// Same state with all movements stopped. The lamp is kept as is.
+    pub fn halted(&self) -> MachineState {
+        MachineState {
+            lamp_enabled: self.lamp_enabled,
+            ..MachineState::new()
+        }
+    }
+
+    /// State that undoes the movement of this one, e.g. forward-left becomes backward-right.
+    pub fn inverted(&self) -> MachineState {
+        MachineState {
+            forward: self.backward,
+            backward: self.forward,
+            left: self.right,
+            right: self.left,
+            lamp_enabled: self.lamp_enabled,
+        }
+    }
+
     pub fn update(&mut self, event: MachineEvents) -> bool {
         match event {
             MachineEvents::Forward => {
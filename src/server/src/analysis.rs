@@ -0,0 +1,232 @@
+use std::sync;
+
+use crate::annotations;
+use crate::autopilot;
+use crate::common::messages as msg;
+use crate::common::types::{
+    Annotation, BlobTracking, CameraCommand, LinePosition, MachineState, Marker, Motion,
+    Orientation, Region,
+};
+use crate::detect;
+use crate::failsafe;
+use crate::localization;
+
+/// Analyses of the frames of a video stream and the autonomous modes driven by them.
+///
+/// Only one mode of a stream drives the machine at a time.
+pub struct Analyses {
+    camera: String,
+    /// Transform the client applies to frames, results are in its coordinates.
+    orientation: Orientation,
+    failsafe: sync::Arc<failsafe::Failsafe>,
+    localization: sync::Arc<localization::Localization>,
+    blob_tracking: Option<(BlobTracking, detect::Worker<Option<Region>>)>,
+    line_following: Option<(
+        detect::Worker<Option<LinePosition>>,
+        autopilot::LineFollower,
+    )>,
+    markers: Option<detect::Worker<Vec<Marker>>>,
+    annotations: annotations::Annotations,
+    /// Autonomous mode which drives the machine.
+    driving: Option<&'static str>,
+    /// Token the mode drives with.
+    autonomy: usize,
+}
+
+impl Analyses {
+    pub fn new(
+        camera: String,
+        orientation: Orientation,
+        failsafe: sync::Arc<failsafe::Failsafe>,
+        localization: sync::Arc<localization::Localization>,
+    ) -> Self {
+        Analyses {
+            camera: camera,
+            orientation: orientation,
+            failsafe: failsafe,
+            localization: localization,
+            blob_tracking: None,
+            line_following: None,
+            markers: None,
+            annotations: annotations::Annotations::new(),
+            driving: None,
+            autonomy: 0,
+        }
+    }
+
+    /// Start or stop an analysis.
+    ///
+    /// Returns a message for the client when the autonomous mode changes. Commands
+    /// of the camera itself are ignored.
+    pub fn handle_command(&mut self, command: &CameraCommand) -> Option<msg::Video> {
+        match *command {
+            CameraCommand::SetMotionDetection(_) => {
+                self.annotations.clear(annotations::MOTION);
+                None
+            }
+            CameraCommand::SetBlobTracking(res) => {
+                info!("Camera {:?} blob tracking: {:?}", self.camera, res);
+                self.annotations.clear(annotations::BLOB);
+                let follow = res.map_or(false, |tracking| tracking.follow);
+                // Only one mode drives the machine at a time.
+                if follow || self.driving == Some(msg::AUTOPILOT_FOLLOW_BLOB) {
+                    self.stop_driving();
+                    self.line_following = None;
+                }
+                let (camera, orientation) = (self.camera.clone(), self.orientation);
+                self.blob_tracking = res.map(|tracking| {
+                    (
+                        tracking,
+                        detect::blob_worker(camera, orientation, tracking.color),
+                    )
+                });
+                if follow {
+                    self.autonomy = self.failsafe.start_autonomy();
+                    self.driving = Some(msg::AUTOPILOT_FOLLOW_BLOB);
+                }
+                Some(self.autopilot())
+            }
+            CameraCommand::SetLineFollowing(res) => {
+                info!("Camera {:?} line following: {:?}", self.camera, res);
+                self.annotations.clear(annotations::LINE);
+                // Only one mode drives the machine at a time.
+                if res.is_some() || self.driving == Some(msg::AUTOPILOT_FOLLOW_LINE) {
+                    self.stop_driving();
+                }
+                let (camera, orientation) = (self.camera.clone(), self.orientation);
+                self.line_following = res.map(|following| {
+                    (
+                        detect::line_worker(camera, orientation, following),
+                        autopilot::LineFollower::new(following.speed),
+                    )
+                });
+                if self.line_following.is_some() {
+                    self.autonomy = self.failsafe.start_autonomy();
+                    self.driving = Some(msg::AUTOPILOT_FOLLOW_LINE);
+                }
+                Some(self.autopilot())
+            }
+            CameraCommand::SetMarkerDetection(enabled) => {
+                info!("Camera {:?} marker detection: {}", self.camera, enabled);
+                self.annotations.clear(annotations::MARKERS);
+                self.markers = if enabled {
+                    Some(detect::marker_worker(self.camera.clone(), self.orientation))
+                } else {
+                    None
+                };
+                None
+            }
+            CameraCommand::SetResolution(_)
+            | CameraCommand::SetFramerate(_)
+            | CameraCommand::SetControl(_, _)
+            | CameraCommand::SetRegion(_)
+            | CameraCommand::Snapshot
+            | CameraCommand::StartRecording
+            | CameraCommand::StopRecording => None,
+        }
+    }
+
+    /// Show the result of the motion detection of the camera.
+    pub fn on_motion(&mut self, motion: &Motion) {
+        self.annotations.set(
+            annotations::MOTION,
+            annotations::motion(motion),
+            annotations::MOTION_TTL_MS,
+        );
+    }
+
+    /// Analyze a captured frame and drive the machine by the results.
+    ///
+    /// Returns a message for the client when the autonomous mode is cancelled.
+    pub fn on_frame(&mut self, frame: &[u8]) -> Option<msg::Video> {
+        let mut cancelled = false;
+        if let Some((tracking, worker)) = &mut self.blob_tracking {
+            worker.offer(frame);
+            if let Some(blob) = worker.result() {
+                if self.driving == Some(msg::AUTOPILOT_FOLLOW_BLOB)
+                    && !self.failsafe.apply_autonomous(
+                        self.autonomy,
+                        &autopilot::follow_blob(blob, tracking.target_size),
+                    )
+                {
+                    info!("Following on camera {:?} is cancelled", self.camera);
+                    self.driving = None;
+                    cancelled = true;
+                }
+                self.annotations.set(
+                    annotations::BLOB,
+                    annotations::blob(blob),
+                    annotations::TRACKING_TTL_MS,
+                );
+            }
+        }
+        if let Some(worker) = &mut self.markers {
+            worker.offer(frame);
+            if let Some(found) = worker.result() {
+                if let Some(fix) = self.localization.update(&found) {
+                    debug!(
+                        "Position from marker {:?}: {:.2}, {:.2}",
+                        fix.payload, fix.x, fix.y
+                    );
+                }
+                self.annotations.set(
+                    annotations::MARKERS,
+                    annotations::markers(&found),
+                    annotations::MARKERS_TTL_MS,
+                );
+            }
+        }
+        let mut line_lost = false;
+        if let Some((worker, follower)) = &mut self.line_following {
+            worker.offer(frame);
+            if let Some(line) = worker.result() {
+                if !self
+                    .failsafe
+                    .apply_autonomous(self.autonomy, &follower.steer(line))
+                {
+                    info!("Line following on camera {:?} is cancelled", self.camera);
+                    line_lost = true;
+                }
+                self.annotations.set(
+                    annotations::LINE,
+                    annotations::line(line),
+                    annotations::TRACKING_TTL_MS,
+                );
+            }
+        }
+        if line_lost {
+            self.line_following = None;
+            self.driving = None;
+            cancelled = true;
+        }
+        if cancelled {
+            Some(msg::Video::Autopilot(None))
+        } else {
+            None
+        }
+    }
+
+    /// Stop the machine while no frames come, as the target can't be seen.
+    pub fn on_capture_error(&mut self) {
+        if self.driving.is_some() {
+            self.failsafe
+                .apply_autonomous(self.autonomy, &MachineState::new());
+        }
+    }
+
+    /// Annotations of the results to attach to the next frame.
+    pub fn annotations(&mut self) -> Vec<Annotation> {
+        self.annotations.current()
+    }
+
+    /// Stop the autonomous mode of the stream, e.g. when the stream is closed.
+    pub fn stop_driving(&mut self) {
+        if self.driving.take().is_some() {
+            self.failsafe.stop_autonomy(self.autonomy);
+        }
+    }
+
+    fn autopilot(&self) -> msg::Video {
+        msg::Video::Autopilot(self.driving.map(|mode| mode.to_string()))
+    }
+}
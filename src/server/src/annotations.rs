@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::common::types::{Annotation, AnnotationShape, LinePosition, Marker, Motion, Region};
+
+/// Source of the motion regions.
+pub const MOTION: &str = "motion";
+/// Source of the tracked color blob.
+pub const BLOB: &str = "blob";
+/// Source of the followed line.
+pub const LINE: &str = "line";
+/// Source of the found markers.
+pub const MARKERS: &str = "markers";
+
+const MOTION_COLOR: (u8, u8, u8) = (0xff, 0x45, 0x3a);
+const BLOB_COLOR: (u8, u8, u8) = (0x30, 0xd1, 0x58);
+const LINE_COLOR: (u8, u8, u8) = (0xff, 0xd6, 0x0a);
+const MARKER_COLOR: (u8, u8, u8) = (0x0a, 0x84, 0xff);
+
+/// Position of the motion score in the frame, relative to its size.
+const MOTION_SCORE_POSITION: (f32, f32) = (0.02, 0.96);
+
+/// How long detected motion stays on the video in milliseconds.
+pub const MOTION_TTL_MS: u64 = 1000;
+/// How long a tracked blob or a followed line stays on the video after it's lost
+/// in milliseconds.
+pub const TRACKING_TTL_MS: u64 = 500;
+/// How long found markers stay on the video in milliseconds.
+pub const MARKERS_TTL_MS: u64 = 1500;
+
+struct Layer {
+    annotations: Vec<Annotation>,
+    expires: Instant,
+}
+
+/// Latest annotations of the analyses of a video stream, attached to every sent frame.
+///
+/// Analyses report results less often than frames are sent, so annotations of a source
+/// are kept until they're replaced or expire. Sources are drawn in the order of their names.
+pub struct Annotations {
+    layers: BTreeMap<&'static str, Layer>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Annotations {
+            layers: BTreeMap::new(),
+        }
+    }
+
+    /// Replace the annotations of a source for `ttl_ms` milliseconds.
+    /// Empty annotations keep the previous ones until they expire.
+    pub fn set(&mut self, source: &'static str, annotations: Vec<Annotation>, ttl_ms: u64) {
+        if annotations.is_empty() {
+            return;
+        }
+        self.layers.insert(
+            source,
+            Layer {
+                annotations: annotations,
+                expires: Instant::now() + Duration::from_millis(ttl_ms),
+            },
+        );
+    }
+
+    /// Remove the annotations of a source, e.g. when its analysis is stopped.
+    pub fn clear(&mut self, source: &'static str) {
+        self.layers.remove(source);
+    }
+
+    /// Annotations of all sources which haven't expired.
+    pub fn current(&mut self) -> Vec<Annotation> {
+        let now = Instant::now();
+        self.layers.retain(|_, layer| layer.expires > now);
+        self.layers
+            .values()
+            .flat_map(|layer| layer.annotations.iter().cloned())
+            .collect()
+    }
+}
+
+/// Moving areas and the score of the motion.
+pub fn motion(motion: &Motion) -> Vec<Annotation> {
+    let mut annotations: Vec<Annotation> = motion
+        .regions
+        .iter()
+        .map(|region| Annotation {
+            shape: AnnotationShape::Box {
+                region: *region,
+                label: None,
+            },
+            color: MOTION_COLOR,
+        })
+        .collect();
+    annotations.push(Annotation {
+        shape: AnnotationShape::Text {
+            x: MOTION_SCORE_POSITION.0,
+            y: MOTION_SCORE_POSITION.1,
+            text: format!("Motion {:.1}%", motion.score * 100.0),
+        },
+        color: MOTION_COLOR,
+    });
+    annotations
+}
+
+pub fn blob(blob: Option<Region>) -> Vec<Annotation> {
+    blob.into_iter()
+        .map(|region| Annotation {
+            shape: AnnotationShape::Box {
+                region: region,
+                label: None,
+            },
+            color: BLOB_COLOR,
+        })
+        .collect()
+}
+
+/// The band the line is searched in and a mark at the line position.
+pub fn line(line: Option<LinePosition>) -> Vec<Annotation> {
+    let line = match line {
+        Some(res) => res,
+        None => return vec![],
+    };
+    vec![
+        Annotation {
+            shape: AnnotationShape::Box {
+                region: line.band,
+                label: None,
+            },
+            color: LINE_COLOR,
+        },
+        Annotation {
+            shape: AnnotationShape::Line {
+                from: (line.x, line.band.y),
+                to: (line.x, line.band.y + line.band.height),
+            },
+            color: LINE_COLOR,
+        },
+    ]
+}
+
+pub fn markers(markers: &[Marker]) -> Vec<Annotation> {
+    markers
+        .iter()
+        .map(|marker| Annotation {
+            shape: AnnotationShape::Box {
+                region: marker.region,
+                label: Some(marker.payload.clone()),
+            },
+            color: MARKER_COLOR,
+        })
+        .collect()
+}
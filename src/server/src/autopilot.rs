@@ -0,0 +1,69 @@
+use crate::common::types::{LinePosition, MachineState, Region};
+
+/// Distance of a target from the frame center, relative to the frame width,
+/// within which it counts as centered.
+const CENTER_DEADBAND: f32 = 0.1;
+/// Distance of a line from the frame center beyond which the machine turns in place.
+const LINE_PIVOT: f32 = 0.3;
+/// Relative difference from the target size which is tolerated.
+const SIZE_TOLERANCE: f32 = 0.15;
+
+/// Movement that brings a blob to the center of the frame at the target height.
+///
+/// The machine stops when the blob is lost. Coordinates are of the image as shown
+/// by the client, so the camera is expected to look forward.
+pub fn follow_blob(blob: Option<Region>, target_size: f32) -> MachineState {
+    let mut state = MachineState::new();
+    let blob = match blob {
+        Some(res) => res,
+        None => return state,
+    };
+
+    state.forward = blob.height < target_size * (1.0 - SIZE_TOLERANCE);
+    state.backward = blob.height > target_size * (1.0 + SIZE_TOLERANCE);
+    // Turning while backing off would lose the target.
+    if !state.backward {
+        let center = blob.x + blob.width / 2.0;
+        state.left = center < 0.5 - CENTER_DEADBAND;
+        state.right = center > 0.5 + CENTER_DEADBAND;
+    }
+    state
+}
+
+/// Steers along a line on the floor.
+///
+/// The engines can only be on or off, so a lower speed is made by running them
+/// for a share of the analyzed frames.
+pub struct LineFollower {
+    speed: f32,
+    pulse: f32,
+}
+
+impl LineFollower {
+    pub fn new(speed: f32) -> Self {
+        LineFollower {
+            speed: speed.max(0.0).min(1.0),
+            pulse: 0.0,
+        }
+    }
+
+    /// Movement along a line found in a frame. The machine stops when the line is lost.
+    pub fn steer(&mut self, line: Option<LinePosition>) -> MachineState {
+        let mut state = MachineState::new();
+        let line = match line {
+            Some(res) => res,
+            None => return state,
+        };
+        self.pulse += self.speed;
+        if self.pulse < 1.0 {
+            return state;
+        }
+        self.pulse -= 1.0;
+
+        let offset = line.x - 0.5;
+        state.left = offset < -CENTER_DEADBAND;
+        state.right = offset > CENTER_DEADBAND;
+        state.forward = offset.abs() <= LINE_PIVOT;
+        state
+    }
+}
@@ -0,0 +1,202 @@
+use std::fs;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+/// Size of the RIFF and hdrl headers preceding the `movi` list.
+const HEADER_SIZE: u64 = 212;
+/// Offsets of the header fields which are only known once the file is complete.
+const RIFF_SIZE_OFFSET: u64 = 4;
+const MICROSEC_PER_FRAME_OFFSET: u64 = 32;
+const TOTAL_FRAMES_OFFSET: u64 = 48;
+const SUGGESTED_BUFFER_OFFSET: u64 = 60;
+const STREAM_RATE_OFFSET: u64 = 132;
+const STREAM_LENGTH_OFFSET: u64 = 140;
+const STREAM_BUFFER_OFFSET: u64 = 144;
+const MOVI_SIZE_OFFSET: u64 = 216;
+
+struct IndexEntry {
+    offset: u32,
+    size: u32,
+}
+
+/// Writer of an AVI file with a single MJPEG video stream.
+///
+/// Frames are stored as they come from the camera. The frame rate in the header is
+/// measured from the frame timestamps when the file is finished, so the clip plays
+/// at the speed it was recorded.
+pub struct AviWriter {
+    file: io::BufWriter<fs::File>,
+    position: u64,
+    index: Vec<IndexEntry>,
+    max_frame_size: u32,
+    first_timestamp_ms: Option<i64>,
+    last_timestamp_ms: i64,
+}
+
+impl AviWriter {
+    pub fn create<P: AsRef<Path>>(path: P, resolution: (u32, u32)) -> Result<AviWriter, io::Error> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        file.write_all(&header(resolution))?;
+        Ok(AviWriter {
+            file: file,
+            position: HEADER_SIZE + 12,
+            index: vec![],
+            max_frame_size: 0,
+            first_timestamp_ms: None,
+            last_timestamp_ms: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, data: &[u8], timestamp_ms: i64) -> Result<(), io::Error> {
+        let size = data.len() as u32;
+        self.file.write_all(b"00dc")?;
+        self.file.write_all(&size.to_le_bytes())?;
+        self.file.write_all(data)?;
+        if size % 2 == 1 {
+            self.file.write_all(&[0])?;
+        }
+
+        // Index offsets are relative to the `movi` fourcc.
+        self.index.push(IndexEntry {
+            offset: (self.position - HEADER_SIZE - 8) as u32,
+            size: size,
+        });
+        self.position += 8 + size as u64 + size as u64 % 2;
+        self.max_frame_size = self.max_frame_size.max(size);
+        if self.first_timestamp_ms.is_none() {
+            self.first_timestamp_ms = Some(timestamp_ms);
+        }
+        self.last_timestamp_ms = timestamp_ms;
+        Ok(())
+    }
+
+    /// Size of the file written so far in bytes.
+    pub fn size(&self) -> u64 {
+        self.position
+    }
+
+    pub fn frames(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Write the index and complete the headers.
+    pub fn finish(mut self) -> Result<(), io::Error> {
+        let movi_size = self.position - HEADER_SIZE - 8;
+
+        self.file.write_all(b"idx1")?;
+        self.file
+            .write_all(&((self.index.len() * 16) as u32).to_le_bytes())?;
+        for entry in &self.index {
+            self.file.write_all(b"00dc")?;
+            self.file.write_all(&AVIIF_KEYFRAME.to_le_bytes())?;
+            self.file.write_all(&entry.offset.to_le_bytes())?;
+            self.file.write_all(&entry.size.to_le_bytes())?;
+        }
+        let file_size = self.position + 8 + self.index.len() as u64 * 16;
+
+        let frames = self.index.len() as u32;
+        let duration_ms = match self.first_timestamp_ms {
+            Some(first) => (self.last_timestamp_ms - first).max(0) as u64,
+            None => 0,
+        };
+        // The last frame is shown for an average frame time as well.
+        let microsec_per_frame = if frames > 1 {
+            (duration_ms * 1000 / (frames as u64 - 1)).max(1)
+        } else {
+            1_000_000
+        };
+        // The stream rate is in frames per 1000 seconds with a scale of 1000.
+        let rate = (1_000_000_000 / microsec_per_frame) as u32;
+
+        let mut file = self.file.into_inner()?;
+        let patches = [
+            (RIFF_SIZE_OFFSET, (file_size - 8) as u32),
+            (MICROSEC_PER_FRAME_OFFSET, microsec_per_frame as u32),
+            (TOTAL_FRAMES_OFFSET, frames),
+            (SUGGESTED_BUFFER_OFFSET, self.max_frame_size),
+            (STREAM_RATE_OFFSET, rate),
+            (STREAM_LENGTH_OFFSET, frames),
+            (STREAM_BUFFER_OFFSET, self.max_frame_size),
+            (MOVI_SIZE_OFFSET, movi_size as u32),
+        ];
+        for (offset, value) in patches.iter() {
+            file.seek(SeekFrom::Start(*offset))?;
+            file.write_all(&value.to_le_bytes())?;
+        }
+        file.sync_all()
+    }
+}
+
+fn header(resolution: (u32, u32)) -> Vec<u8> {
+    let (width, height) = resolution;
+    let mut buf = Vec::with_capacity(HEADER_SIZE as usize + 12);
+
+    buf.extend_from_slice(b"RIFF");
+    put(&mut buf, 0);
+    buf.extend_from_slice(b"AVI ");
+
+    buf.extend_from_slice(b"LIST");
+    put(&mut buf, 192);
+    buf.extend_from_slice(b"hdrl");
+
+    buf.extend_from_slice(b"avih");
+    put(&mut buf, 56);
+    put(&mut buf, 0); // dwMicroSecPerFrame
+    put(&mut buf, 0); // dwMaxBytesPerSec
+    put(&mut buf, 0); // dwPaddingGranularity
+    put(&mut buf, AVIF_HASINDEX);
+    put(&mut buf, 0); // dwTotalFrames
+    put(&mut buf, 0); // dwInitialFrames
+    put(&mut buf, 1); // dwStreams
+    put(&mut buf, 0); // dwSuggestedBufferSize
+    put(&mut buf, width);
+    put(&mut buf, height);
+    for _ in 0..4 {
+        put(&mut buf, 0);
+    }
+
+    buf.extend_from_slice(b"LIST");
+    put(&mut buf, 116);
+    buf.extend_from_slice(b"strl");
+
+    buf.extend_from_slice(b"strh");
+    put(&mut buf, 56);
+    buf.extend_from_slice(b"vids");
+    buf.extend_from_slice(b"MJPG");
+    put(&mut buf, 0); // dwFlags
+    put(&mut buf, 0); // wPriority, wLanguage
+    put(&mut buf, 0); // dwInitialFrames
+    put(&mut buf, 1000); // dwScale
+    put(&mut buf, 0); // dwRate
+    put(&mut buf, 0); // dwStart
+    put(&mut buf, 0); // dwLength
+    put(&mut buf, 0); // dwSuggestedBufferSize
+    put(&mut buf, u32::max_value()); // dwQuality
+    put(&mut buf, 0); // dwSampleSize
+    put(&mut buf, 0); // rcFrame left, top
+    put(&mut buf, (width & 0xffff) | (height << 16)); // rcFrame right, bottom
+
+    buf.extend_from_slice(b"strf");
+    put(&mut buf, 40);
+    put(&mut buf, 40); // biSize
+    put(&mut buf, width);
+    put(&mut buf, height);
+    put(&mut buf, 1 | (24 << 16)); // biPlanes, biBitCount
+    buf.extend_from_slice(b"MJPG");
+    put(&mut buf, width * height * 3);
+    for _ in 0..4 {
+        put(&mut buf, 0);
+    }
+
+    buf.extend_from_slice(b"LIST");
+    put(&mut buf, 0);
+    buf.extend_from_slice(b"movi");
+    buf
+}
+
+fn put(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,685 @@
+extern crate image;
+
+use self::image::GenericImageView;
+use chrono;
+use serde::Deserialize;
+use simple_error::SimpleError as Error;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time;
+
+use crate::common::settings;
+use crate::common::types::{CameraCapabilities, CameraControl, Orientation, Region};
+
+/// JPEG quality of synthetic frames.
+const TEST_PATTERN_QUALITY: u8 = 80;
+/// JPEG quality of cropped frames when no lower quality is requested.
+pub const REGION_QUALITY: u8 = 85;
+
+/// JPEG encoded frame. Either a device buffer or an owned one.
+///
+/// Frames are shared with the threads of video streams, so a device buffer returns
+/// to the driver when the last of them drops it.
+pub type Frame = Box<dyn Deref<Target = [u8]> + Send + Sync>;
+
+const V4L2_CID_HFLIP: u32 = 0x0098_0914;
+const V4L2_CID_VFLIP: u32 = 0x0098_0915;
+
+/// Source of JPEG frames.
+pub trait FrameSource {
+    /// Capture the next frame. Blocks until it's available.
+    fn capture(&mut self) -> Result<Frame, io::Error>;
+
+    /// Pixel formats supported by the source.
+    fn formats(&self) -> Vec<String> {
+        vec!["MJPG".to_string()]
+    }
+
+    /// Whether the source is able to produce frames of the given resolution.
+    fn supports_resolution(&self, _resolution: (u32, u32)) -> bool {
+        true
+    }
+
+    /// Adjustable controls with their current values.
+    fn controls(&self) -> Vec<CameraControl> {
+        vec![]
+    }
+
+    fn set_control(&mut self, _id: u32, _value: i32) -> Result<(), Error> {
+        Err(Error::new("Controls are not supported by the source"))
+    }
+}
+
+/// Camera allowed to be opened by clients.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CameraConfig {
+    pub name: String,
+    /// One of:
+    /// - `test` for a synthetic test pattern;
+    /// - `file:<path>` to replay an MJPEG file or a directory of JPEG images;
+    /// - a V4L2 device path, e.g. `/dev/video0`.
+    pub device: String,
+    /// Allowed `(width, height)` pairs.
+    pub resolutions: Vec<(u32, u32)>,
+    pub max_framerate: u8,
+    /// Transform that makes the image upright, depends on how the camera is mounted.
+    #[serde(default)]
+    pub orientation: Orientation,
+}
+
+/// Stream parameters negotiated with a client.
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    pub device: String,
+    pub resolution: (u32, u32),
+    pub framerate: u8,
+}
+
+/// Check the requested video settings against the configured cameras.
+///
+/// Unknown cameras and resolutions are rejected, framerate is clamped to the camera limit.
+pub fn negotiate(
+    cameras: &Vec<CameraConfig>,
+    request: &settings::Video,
+) -> Result<StreamConfig, Error> {
+    let camera = match cameras.iter().find(|c| c.name == request.camera) {
+        Some(res) => res,
+        None => {
+            let names: Vec<&str> = cameras.iter().map(|c| c.name.as_str()).collect();
+            return Err(Error::new(format!(
+                "Unknown camera {:?}. Available cameras: {}",
+                request.camera,
+                names.join(", ")
+            )));
+        }
+    };
+
+    if !camera.resolutions.contains(&request.resolution) {
+        let resolutions: Vec<String> = camera
+            .resolutions
+            .iter()
+            .map(|(w, h)| format!("{}x{}", w, h))
+            .collect();
+        return Err(Error::new(format!(
+            "Resolution {}x{} is not allowed for camera {:?}. Allowed resolutions: {}",
+            request.resolution.0,
+            request.resolution.1,
+            camera.name,
+            resolutions.join(", ")
+        )));
+    }
+
+    if request.max_framerate == 0 {
+        return Err(Error::new(format!(
+            "Framerate 0 is not allowed for camera {:?}. Use 1-{}.",
+            camera.name, camera.max_framerate
+        )));
+    }
+
+    if let Some(profile) = &request.profile {
+        if profile.quality == 0 || profile.quality > 100 {
+            return Err(Error::new(format!(
+                "Invalid JPEG quality {} of the video profile. Use 1-100.",
+                profile.quality
+            )));
+        }
+        if profile.resolution.0 == 0 || profile.resolution.1 == 0 {
+            return Err(Error::new("Video profile resolution must not be empty"));
+        }
+    }
+
+    let mut framerate = request.max_framerate;
+    if framerate > camera.max_framerate {
+        warn!(
+            "Framerate {} is clamped to {} for camera {:?}",
+            framerate, camera.max_framerate, camera.name
+        );
+        framerate = camera.max_framerate;
+    }
+
+    Ok(StreamConfig {
+        device: camera.device.clone(),
+        resolution: request.resolution,
+        framerate: framerate,
+    })
+}
+
+/// Describe the current stream and what can be changed for the given camera.
+pub fn capabilities(
+    source: &Box<dyn FrameSource>,
+    camera: &CameraConfig,
+    config: &StreamConfig,
+) -> CameraCapabilities {
+    CameraCapabilities {
+        formats: source.formats(),
+        resolutions: camera
+            .resolutions
+            .iter()
+            .filter(|res| source.supports_resolution(**res))
+            .cloned()
+            .collect(),
+        max_framerate: camera.max_framerate,
+        resolution: config.resolution,
+        framerate: config.framerate,
+        controls: source.controls(),
+    }
+}
+
+/// Apply as much of the orientation as the device supports with its flip controls.
+///
+/// Returns the transform that is left for the client.
+pub fn apply_orientation(
+    source: &mut Box<dyn FrameSource>,
+    orientation: Orientation,
+) -> Orientation {
+    let controls = source.controls();
+    let has_hflip = controls.iter().any(|c| c.id == V4L2_CID_HFLIP);
+    let has_vflip = controls.iter().any(|c| c.id == V4L2_CID_VFLIP);
+
+    let mut rest = orientation;
+    // Flipping both axes is the same as rotating by 180 degrees.
+    if rest.rotate == 180 && has_hflip && has_vflip {
+        rest = Orientation {
+            rotate: 0,
+            hflip: !rest.hflip,
+            vflip: !rest.vflip,
+        };
+    }
+    // Device flips happen before the rotation, so they can only replace it when there is none.
+    if rest.rotate != 0 {
+        return rest;
+    }
+    if has_hflip {
+        match source.set_control(V4L2_CID_HFLIP, rest.hflip as i32) {
+            Ok(_) => rest.hflip = false,
+            Err(e) => warn!("{}", e),
+        }
+    }
+    if has_vflip {
+        match source.set_control(V4L2_CID_VFLIP, rest.vflip as i32) {
+            Ok(_) => rest.vflip = false,
+            Err(e) => warn!("{}", e),
+        }
+    }
+    rest
+}
+
+/// Decode a JPEG frame and encode it again with the given quality,
+/// cropped to `region` and downscaled to fit into `bounds`.
+pub fn transcode(
+    data: &[u8],
+    region: Option<Region>,
+    bounds: (u32, u32),
+    quality: u8,
+) -> Result<Vec<u8>, Error> {
+    let mut img = match image::load_from_memory_with_format(data, image::ImageFormat::Jpeg) {
+        Ok(res) => res.to_rgb(),
+        Err(e) => return Err(Error::new(format!("Failed to decode a frame: {}", e))),
+    };
+    if let Some(region) = region {
+        let (x, y, width, height) = region.to_pixels(img.width(), img.height());
+        img = image::imageops::crop(&mut img, x, y, width, height).to_image();
+    }
+    let scale = (bounds.0 as f32 / img.width() as f32).min(bounds.1 as f32 / img.height() as f32);
+    if scale < 1.0 {
+        let width = ((img.width() as f32 * scale) as u32).max(1);
+        let height = ((img.height() as f32 * scale) as u32).max(1);
+        img = image::imageops::resize(&img, width, height, image::imageops::FilterType::Triangle);
+    }
+    encode(&img, quality)
+}
+
+/// Decode a JPEG frame, transform it by `orientation` and encode it again with the
+/// given quality at exactly `size`.
+pub fn reorient(
+    data: &[u8],
+    orientation: Orientation,
+    size: (u32, u32),
+    quality: u8,
+) -> Result<Vec<u8>, Error> {
+    let mut img = match image::load_from_memory_with_format(data, image::ImageFormat::Jpeg) {
+        Ok(res) => orientation.apply(res),
+        Err(e) => return Err(Error::new(format!("Failed to decode a frame: {}", e))),
+    };
+    if (img.width(), img.height()) != size {
+        img = img.resize_exact(size.0, size.1, image::imageops::FilterType::Triangle);
+    }
+    encode(&img.to_rgb(), quality)
+}
+
+fn encode(img: &image::RgbImage, quality: u8) -> Result<Vec<u8>, Error> {
+    let mut encoded_data = vec![];
+    let encoded = image::jpeg::JPEGEncoder::new_with_quality(&mut encoded_data, quality).encode(
+        img,
+        img.width(),
+        img.height(),
+        image::ColorType::Rgb8,
+    );
+    match encoded {
+        Ok(_) => Ok(encoded_data),
+        Err(e) => Err(Error::new(format!("Failed to encode a frame: {}", e))),
+    }
+}
+
+/// Open a frame source for the negotiated stream.
+pub fn open(config: &StreamConfig) -> Result<Box<dyn FrameSource>, Error> {
+    let device = config.device.as_str();
+    if device == "test" {
+        info!("Opening test pattern source...");
+        Ok(Box::new(TestPatternSource::new(
+            config.resolution,
+            config.framerate,
+        )))
+    } else if let Some(path) = device.strip_prefix("file:") {
+        info!("Opening file playback source {}...", path);
+        Ok(Box::new(FileSource::new(
+            path,
+            config.resolution,
+            config.framerate,
+        )?))
+    } else {
+        info!("Opening V4L2 source {}...", device);
+        Ok(Box::new(V4l2Source::new(
+            device,
+            config.resolution,
+            config.framerate,
+        )?))
+    }
+}
+
+pub struct V4l2Source {
+    camera: rscam::Camera,
+}
+
+impl V4l2Source {
+    pub fn new(device: &str, resolution: (u32, u32), framerate: u8) -> Result<Self, Error> {
+        let mut camera = match rscam::new(device) {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(Error::new(format!(
+                    "Failed to initialize video device: {}",
+                    e
+                )))
+            }
+        };
+        match camera.start(&rscam::Config {
+            interval: (1, framerate as u32),
+            resolution: resolution,
+            format: b"MJPG",
+            nbuffers: 32,
+            field: rscam::FIELD_NONE,
+        }) {
+            Ok(_) => Ok(V4l2Source { camera: camera }),
+            Err(e) => Err(Error::new(format!("Failed to start the stream: {}", e))),
+        }
+    }
+}
+
+/// Buffer of the device mapped into memory.
+struct DeviceFrame(rscam::Frame);
+
+// The buffer is only read, and the driver takes it back by an ioctl which may come
+// from any thread.
+unsafe impl Send for DeviceFrame {}
+unsafe impl Sync for DeviceFrame {}
+
+impl Deref for DeviceFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FrameSource for V4l2Source {
+    fn capture(&mut self) -> Result<Frame, io::Error> {
+        Ok(Box::new(DeviceFrame(self.camera.capture()?)))
+    }
+
+    fn formats(&self) -> Vec<String> {
+        self.camera
+            .formats()
+            .filter_map(|format| format.ok())
+            .map(|format| String::from_utf8_lossy(&format.format).to_string())
+            .collect()
+    }
+
+    fn supports_resolution(&self, resolution: (u32, u32)) -> bool {
+        match self.camera.resolutions(b"MJPG") {
+            Ok(rscam::ResolutionInfo::Discretes(resolutions)) => resolutions.contains(&resolution),
+            Ok(rscam::ResolutionInfo::Stepwise { min, max, .. }) => {
+                resolution.0 >= min.0
+                    && resolution.0 <= max.0
+                    && resolution.1 >= min.1
+                    && resolution.1 <= max.1
+            }
+            // The device doesn't enumerate resolutions, let it fail on start.
+            Err(_) => true,
+        }
+    }
+
+    fn controls(&self) -> Vec<CameraControl> {
+        self.camera
+            .controls()
+            .filter_map(|control| control.ok())
+            .filter_map(|control| {
+                let (minimum, maximum, step, default, value) = match control.data {
+                    rscam::CtrlData::Integer {
+                        value,
+                        default,
+                        minimum,
+                        maximum,
+                        step,
+                    } => (minimum, maximum, step, default, value),
+                    rscam::CtrlData::Boolean { value, default } => {
+                        (0, 1, 1, default as i32, value as i32)
+                    }
+                    rscam::CtrlData::Menu {
+                        value,
+                        default,
+                        ref items,
+                    } => (0, items.len() as i32 - 1, 1, default as i32, value as i32),
+                    _ => return None,
+                };
+                Some(CameraControl {
+                    id: control.id,
+                    name: control.name.clone(),
+                    minimum: minimum,
+                    maximum: maximum,
+                    step: step,
+                    default: default,
+                    value: value,
+                })
+            })
+            .collect()
+    }
+
+    fn set_control(&mut self, id: u32, value: i32) -> Result<(), Error> {
+        match self.camera.set_control(id, &value) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::new(format!("Failed to set control {}: {}", id, e))),
+        }
+    }
+}
+
+/// Sleeps between frames to keep the given framerate.
+struct Pacer {
+    interval: time::Duration,
+    next: time::Instant,
+}
+
+impl Pacer {
+    fn new(framerate: u8) -> Self {
+        Pacer {
+            interval: time::Duration::from_millis(1000 / framerate.max(1) as u64),
+            next: time::Instant::now(),
+        }
+    }
+
+    fn wait(&mut self) {
+        let now = time::Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+            self.next += self.interval;
+        } else {
+            self.next = now + self.interval;
+        }
+    }
+}
+
+/// Moving color bars with an embedded frame counter and timestamp.
+pub struct TestPatternSource {
+    width: u32,
+    height: u32,
+    counter: u64,
+    pacer: Pacer,
+}
+
+/// 3x5 glyphs of digits, `:` and `.`. Every row is 3 bits wide.
+const GLYPHS: [(char, [u8; 5]); 12] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+];
+
+const BARS: [[u8; 3]; 7] = [
+    [0xc0, 0xc0, 0xc0],
+    [0xc0, 0xc0, 0x00],
+    [0x00, 0xc0, 0xc0],
+    [0x00, 0xc0, 0x00],
+    [0xc0, 0x00, 0xc0],
+    [0xc0, 0x00, 0x00],
+    [0x00, 0x00, 0xc0],
+];
+
+impl TestPatternSource {
+    pub fn new(resolution: (u32, u32), framerate: u8) -> Self {
+        TestPatternSource {
+            width: resolution.0.max(1),
+            height: resolution.1.max(1),
+            counter: 0,
+            pacer: Pacer::new(framerate),
+        }
+    }
+
+    fn draw_text(&self, canvas: &mut image::RgbImage, text: &str, x: u32, y: u32, scale: u32) {
+        let mut offset = x;
+        for c in text.chars() {
+            if let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == c) {
+                for (row, bits) in rows.iter().enumerate() {
+                    for col in 0..3 {
+                        if bits & (0b100 >> col) == 0 {
+                            continue;
+                        }
+                        for dy in 0..scale {
+                            for dx in 0..scale {
+                                let px = offset + col * scale + dx;
+                                let py = y + row as u32 * scale + dy;
+                                if px < self.width && py < self.height {
+                                    canvas.put_pixel(px, py, image::Rgb([0xff, 0xff, 0xff]));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            offset += 4 * scale;
+        }
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    fn capture(&mut self) -> Result<Frame, io::Error> {
+        self.pacer.wait();
+        self.counter += 1;
+
+        let bar_width = (self.width / BARS.len() as u32).max(1);
+        let shift = (self.counter * 4) as u32 % self.width;
+        let mut canvas = image::RgbImage::from_fn(self.width, self.height, |x, y| {
+            if y > self.height * 3 / 4 {
+                // Gray gradient at the bottom.
+                let level = (x * 0xff / self.width) as u8;
+                image::Rgb([level, level, level])
+            } else {
+                let bar = ((x + shift) % self.width) / bar_width;
+                image::Rgb(BARS[(bar as usize).min(BARS.len() - 1)])
+            }
+        });
+
+        let scale = (self.height / 60).max(1);
+        let timestamp = chrono::Local::now().format("%H:%M:%S.%3f").to_string();
+        self.draw_text(&mut canvas, &format!("{}", self.counter), 10, 10, scale);
+        self.draw_text(&mut canvas, &timestamp, 10, 10 + 7 * scale, scale);
+
+        let mut data = vec![];
+        let encoded = image::jpeg::JPEGEncoder::new_with_quality(&mut data, TEST_PATTERN_QUALITY)
+            .encode(&canvas, self.width, self.height, image::ColorType::Rgb8);
+        match encoded {
+            Ok(_) => Ok(Box::new(data)),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Failed to encode a test frame: {}", e),
+            )),
+        }
+    }
+}
+
+/// Frames of a played file.
+enum Playback {
+    /// Sorted JPEG images of a directory.
+    Images(Vec<PathBuf>, usize),
+    /// Concatenated JPEG images.
+    Mjpeg(io::BufReader<fs::File>),
+}
+
+/// Replays an MJPEG file or a directory of JPEG images in a loop.
+///
+/// Frames are read as they're played, so files of any size can be replayed. Frames of
+/// another resolution than the negotiated one are scaled to it.
+pub struct FileSource {
+    playback: Playback,
+    resolution: (u32, u32),
+    pacer: Pacer,
+}
+
+impl FileSource {
+    pub fn new(path: &str, resolution: (u32, u32), framerate: u8) -> Result<Self, Error> {
+        let playback = if Path::new(path).is_dir() {
+            FileSource::list_images(path).map(|paths| Playback::Images(paths, 0))
+        } else {
+            fs::File::open(path).map(|file| Playback::Mjpeg(io::BufReader::new(file)))
+        };
+        let mut source = match playback {
+            Ok(res) => FileSource {
+                playback: res,
+                resolution: resolution,
+                pacer: Pacer::new(framerate),
+            },
+            Err(e) => return Err(Error::new(format!("Failed to read {}: {}", path, e))),
+        };
+        // The first frame is read ahead to check that there is one.
+        if let Err(e) = source.next_frame().and_then(|_| source.rewind()) {
+            return Err(Error::new(format!("Failed to read {}: {}", path, e)));
+        }
+        info!("Playing frames from {}", path);
+        Ok(source)
+    }
+
+    fn list_images(path: &str) -> Result<Vec<PathBuf>, io::Error> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("jpg") | Some("jpeg") | Some("JPG") | Some("JPEG") => paths.push(path),
+                _ => {}
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn rewind(&mut self) -> Result<(), io::Error> {
+        match &mut self.playback {
+            Playback::Images(_, position) => {
+                *position = 0;
+                Ok(())
+            }
+            Playback::Mjpeg(reader) => reader.seek(io::SeekFrom::Start(0)).map(|_| ()),
+        }
+    }
+
+    /// Read the next frame, starting over at the end.
+    fn next_frame(&mut self) -> Result<Vec<u8>, io::Error> {
+        match &mut self.playback {
+            Playback::Images(paths, position) => {
+                if paths.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "No JPEG frames found",
+                    ));
+                }
+                let data = fs::read(&paths[*position]);
+                *position = (*position + 1) % paths.len();
+                data
+            }
+            Playback::Mjpeg(reader) => match FileSource::read_mjpeg_frame(reader)? {
+                Some(frame) => Ok(frame),
+                None => {
+                    reader.seek(io::SeekFrom::Start(0))?;
+                    match FileSource::read_mjpeg_frame(reader)? {
+                        Some(frame) => Ok(frame),
+                        None => Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            "No JPEG frames found",
+                        )),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Read the next image of concatenated JPEG images, split by SOI and EOI markers.
+    ///
+    /// Returns `None` at the end of the file.
+    fn read_mjpeg_frame(
+        reader: &mut io::BufReader<fs::File>,
+    ) -> Result<Option<Vec<u8>>, io::Error> {
+        // Frame started by an SOI marker and not ended yet.
+        let mut frame: Option<Vec<u8>> = None;
+        let mut previous = 0u8;
+
+        for byte in reader.bytes() {
+            let byte = byte?;
+            if let Some(data) = frame.as_mut() {
+                data.push(byte);
+            }
+            if previous == 0xff && (byte == 0xd8 || byte == 0xd9) {
+                if byte == 0xd8 && frame.is_none() {
+                    frame = Some(vec![0xff, 0xd8]);
+                } else if byte == 0xd9 && frame.is_some() {
+                    return Ok(frame);
+                }
+                // The second byte of a marker doesn't start another one.
+                previous = 0;
+                continue;
+            }
+            previous = byte;
+        }
+        Ok(None)
+    }
+}
+
+impl FrameSource for FileSource {
+    fn capture(&mut self) -> Result<Frame, io::Error> {
+        self.pacer.wait();
+        let frame = self.next_frame()?;
+        let size =
+            image::io::Reader::with_format(io::Cursor::new(&frame), image::ImageFormat::Jpeg)
+                .into_dimensions();
+        match size {
+            Ok(size) if size == self.resolution => Ok(Box::new(frame)),
+            _ => match reorient(
+                &frame,
+                Orientation::default(),
+                self.resolution,
+                TEST_PATTERN_QUALITY,
+            ) {
+                Ok(scaled) => Ok(Box::new(scaled)),
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}", e))),
+            },
+        }
+    }
+}
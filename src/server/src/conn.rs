@@ -5,40 +5,145 @@ use simple_error::SimpleError as Error;
 use std::collections::HashMap;
 use std::error;
 use std::io;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::net::{Shutdown, TcpListener, TcpStream};
 use std::sync;
 use std::sync::mpsc;
 use std::thread;
 use std::time;
 
+use crate::analysis;
+use crate::camera;
 use crate::common::conn::MessageStream;
 use crate::common::messages as msg;
 use crate::common::settings;
 use crate::common::types;
+use crate::failsafe;
+use crate::feed;
+use crate::gps;
+use crate::journal;
+use crate::localization;
 use crate::machine;
+use crate::mission;
+use crate::mount;
+use crate::quality;
+use crate::recorder;
+use crate::scripts;
+use crate::timelapse;
 use crate::utils;
 
+/// Time without frames after which the autonomous modes stop the machine in milliseconds.
+const FRAME_TIMEOUT_MS: u64 = 1500;
+
+/// Server resources used by the channels of a session.
+#[derive(Clone)]
+struct VideoContext {
+    cameras: Vec<camera::CameraConfig>,
+    gps: Option<sync::Arc<gps::Gps>>,
+    mount: Option<sync::Arc<mount::Mount>>,
+    feeds: sync::Arc<feed::Feeds>,
+    timelapse: sync::Arc<timelapse::Timelapse>,
+    localization: sync::Arc<localization::Localization>,
+    missions: sync::Arc<mission::Missions>,
+    scripts: sync::Arc<scripts::Scripts>,
+    failsafe: sync::Arc<failsafe::Failsafe>,
+}
+
 pub struct SessionPool {
     config: utils::Config,
     sessions: HashMap<String, Session>,
-    machine: sync::Arc<sync::Mutex<machine::Machine>>,
+    failsafe: sync::Arc<failsafe::Failsafe>,
+    gps: Option<sync::Arc<gps::Gps>>,
+    mount: Option<sync::Arc<mount::Mount>>,
+    timelapse: sync::Arc<timelapse::Timelapse>,
+    localization: sync::Arc<localization::Localization>,
+    missions: sync::Arc<mission::Missions>,
+    scripts: sync::Arc<scripts::Scripts>,
+    journal: sync::Arc<journal::Journal>,
+    feeds: sync::Arc<feed::Feeds>,
 }
 
 impl SessionPool {
-    pub fn new(config: utils::Config, machine: sync::Arc<sync::Mutex<machine::Machine>>) -> Self {
+    pub fn new(
+        config: utils::Config,
+        machine: sync::Arc<sync::Mutex<machine::Machine>>,
+        gps: Option<sync::Arc<gps::Gps>>,
+        mount: Option<sync::Arc<mount::Mount>>,
+        timelapse: sync::Arc<timelapse::Timelapse>,
+        localization: sync::Arc<localization::Localization>,
+        journal: sync::Arc<journal::Journal>,
+    ) -> Self {
+        let failsafe = sync::Arc::new(failsafe::Failsafe::new(
+            config.loss_policy,
+            machine,
+            journal.clone(),
+        ));
+        let missions = sync::Arc::new(mission::Missions::new(failsafe.clone(), config.turn_rate));
+        let scripts = sync::Arc::new(scripts::Scripts::new(
+            config.script_dir.clone(),
+            failsafe.clone(),
+            gps.clone(),
+            mount.clone(),
+            localization.clone(),
+        ));
+        let feeds = sync::Arc::new(feed::Feeds::new(feed::Context {
+            gps: gps.clone(),
+            mount: mount.clone(),
+            snapshot_dir: config.snapshot_dir.clone(),
+            recording_dir: config.recording_dir.clone(),
+            recording_limit: config.recording_limit,
+            timelapse: timelapse.clone(),
+            localization: localization.clone(),
+            missions: missions.clone(),
+            scripts: scripts.clone(),
+            failsafe: failsafe.clone(),
+            journal: journal.clone(),
+        }));
         SessionPool {
             config: config,
             sessions: HashMap::new(),
-            machine: machine,
+            failsafe: failsafe,
+            gps: gps,
+            mount: mount,
+            timelapse: timelapse,
+            localization: localization,
+            missions: missions,
+            scripts: scripts,
+            journal: journal,
+            feeds: feeds,
         }
     }
 
+    /// Missions driven by the failsafe of the pool.
+    pub fn missions(&self) -> sync::Arc<mission::Missions> {
+        self.missions.clone()
+    }
+
+    /// Scripts driven by the failsafe of the pool.
+    pub fn scripts(&self) -> sync::Arc<scripts::Scripts> {
+        self.scripts.clone()
+    }
+
     pub fn listen(&mut self) -> Result<(), Box<dyn error::Error>> {
         let listener = TcpListener::bind(format!("[::]:{}", &self.config.port))?;
         listener.set_ttl(5)?;
         info!("Server listening on port {:?}", &self.config.port);
-        let machine = self.machine.clone();
+        let failsafe = self.failsafe.clone();
+        let gps = self.gps.clone();
+        let mount = self.mount.clone();
+        let timelapse = self.timelapse.clone();
+        let localization = self.localization.clone();
+        let video_context = VideoContext {
+            cameras: self.config.cameras.clone(),
+            gps: gps.clone(),
+            mount: mount.clone(),
+            feeds: self.feeds.clone(),
+            timelapse: timelapse.clone(),
+            localization: localization.clone(),
+            missions: self.missions.clone(),
+            scripts: self.scripts.clone(),
+            failsafe: failsafe.clone(),
+        };
 
         for stream in listener.incoming() {
             match stream {
@@ -61,6 +166,25 @@ impl SessionPool {
                                         Err(e) => error!("Failed to open a session: {}", e),
                                     };
                                 }
+                                msg::ConnectionType::Journal(settings) => {
+                                    if let Err(e) =
+                                        self.send_journal(stream, settings, message.token)
+                                    {
+                                        error!("Failed to send a journal: {}", e);
+                                    }
+                                }
+                                msg::ConnectionType::Recordings(request) => {
+                                    if let Err(e) =
+                                        self.send_recordings(stream, request, message.token)
+                                    {
+                                        error!("Failed to send recordings: {}", e);
+                                    }
+                                }
+                                msg::ConnectionType::Scripts => {
+                                    if let Err(e) = self.send_scripts(stream, message.token) {
+                                        error!("Failed to send scripts: {}", e);
+                                    }
+                                }
                                 _ => {
                                     error!("Unknown message type: {:?}", message.conn_type);
                                 }
@@ -71,13 +195,24 @@ impl SessionPool {
                             } => match self.lookup_session(&session_id) {
                                 Some(session) => match message.conn_type {
                                     msg::ConnectionType::Video(settings) => {
-                                        session.open_video_channel(stream, settings)?;
+                                        session.open_video_channel(
+                                            stream,
+                                            settings,
+                                            video_context.clone(),
+                                        )?;
                                     }
                                     msg::ConnectionType::Controller(settings) => {
                                         session.open_controller_channel(
                                             stream,
                                             settings,
-                                            machine.clone(),
+                                            video_context.clone(),
+                                        )?;
+                                    }
+                                    msg::ConnectionType::Telemetry(settings) => {
+                                        session.open_telemetry_channel(
+                                            stream,
+                                            settings,
+                                            video_context.clone(),
                                         )?;
                                     }
                                     _ => {
@@ -116,9 +251,29 @@ impl SessionPool {
             return match stream.write_msg(&msg::OpenSession {
                 ok: false,
                 session_id: None,
+                loss_policy: None,
                 error: Some("Invalid token".to_string()),
             }) {
-                Ok(_) => Err(Error::new("Session is rejected due to invalid token.")),
+                Ok(_) => {
+                    self.journal.record(msg::JournalEvent::Session(
+                        "Session is rejected due to invalid token".to_string(),
+                    ));
+                    Err(Error::new("Session is rejected due to invalid token."))
+                }
+                Err(e) => Err(Error::new(format!("{}", e))),
+            };
+        }
+
+        if config.interval == 0 {
+            return match stream.write_msg(&msg::OpenSession {
+                ok: false,
+                session_id: None,
+                loss_policy: None,
+                error: Some("Heartbeat interval must be positive".to_string()),
+            }) {
+                Ok(_) => Err(Error::new(
+                    "Session is rejected due to zero heartbeat interval.",
+                )),
                 Err(e) => Err(Error::new(format!("{}", e))),
             };
         }
@@ -127,10 +282,27 @@ impl SessionPool {
         return match stream.write_msg(&msg::OpenSession {
             ok: true,
             session_id: Some(session_id.clone()),
+            loss_policy: Some(self.failsafe.policy()),
             error: None,
         }) {
             Ok(_) => {
-                let session = Session::new(session_id.clone(), stream);
+                self.journal.record(msg::JournalEvent::Session(format!(
+                    "Opened session {}",
+                    session_id
+                )));
+                // The machine follows one client, so older sessions are closed
+                // together with their heartbeat watchers.
+                for (id, session) in self.sessions.drain() {
+                    info!("Closing session {} replaced by {}", id, session_id);
+                    session.close();
+                }
+                let session = Session::new(
+                    session_id.clone(),
+                    self.failsafe.open_session(),
+                    stream,
+                    self.journal.clone(),
+                );
+                session.watch_heartbeat(config, self.failsafe.clone())?;
                 self.sessions.insert(session_id.clone(), session);
                 Ok(session_id)
             }
@@ -138,82 +310,581 @@ impl SessionPool {
         };
     }
 
+    /// Send journal records of the last minutes. Doesn't require an open session
+    /// to be usable after the connection is lost.
+    fn send_journal(
+        &mut self,
+        mut stream: TcpStream,
+        config: common::settings::Journal,
+        token: String,
+    ) -> Result<(), Error> {
+        if !self.config.is_valid_token(token) {
+            let _ = stream.write_msg(&msg::Journal {
+                ok: false,
+                records: vec![],
+                error: Some("Invalid token".to_string()),
+            });
+            return Err(Error::new("Invalid token"));
+        }
+
+        // The journal is read from disk, so it's sent without blocking new connections.
+        let journal = self.journal.clone();
+        thread::spawn(move || {
+            let since_ms =
+                chrono::Utc::now().timestamp_millis() - config.minutes as i64 * 60 * 1000;
+            let response = match journal.read_since(since_ms) {
+                Ok(records) => msg::Journal {
+                    ok: true,
+                    records: records,
+                    error: None,
+                },
+                Err(e) => msg::Journal {
+                    ok: false,
+                    records: vec![],
+                    error: Some(format!("Failed to read journal: {}", e)),
+                },
+            };
+            match stream.write_msg(&response) {
+                Ok(_) => match response.error {
+                    Some(e) => error!("Failed to send a journal: {}", e),
+                    None => info!("Sent {} journal records", response.records.len()),
+                },
+                Err(e) => error!("Failed to send a journal: {}", e),
+            }
+        });
+        Ok(())
+    }
+
+    /// List or download recorded clips. Like the journal, it doesn't require an open session.
+    fn send_recordings(
+        &mut self,
+        mut stream: TcpStream,
+        request: msg::RecordingsRequest,
+        token: String,
+    ) -> Result<(), Error> {
+        let valid_token = self.config.is_valid_token(token);
+        match request {
+            msg::RecordingsRequest::List => {
+                let response = if !valid_token {
+                    msg::Recordings {
+                        ok: false,
+                        clips: vec![],
+                        error: Some("Invalid token".to_string()),
+                    }
+                } else {
+                    match recorder::list(&self.config.recording_dir) {
+                        Ok(clips) => msg::Recordings {
+                            ok: true,
+                            clips: clips,
+                            error: None,
+                        },
+                        Err(e) => msg::Recordings {
+                            ok: false,
+                            clips: vec![],
+                            error: Some(format!("Failed to list recordings: {}", e)),
+                        },
+                    }
+                };
+
+                match stream.write_msg(&response) {
+                    Ok(_) => match response.error {
+                        Some(e) => Err(Error::new(e)),
+                        None => {
+                            info!("Sent a list of {} clips", response.clips.len());
+                            Ok(())
+                        }
+                    },
+                    Err(e) => Err(Error::new(format!("{}", e))),
+                }
+            }
+            msg::RecordingsRequest::Download(name) => {
+                let file = if !valid_token {
+                    Err("Invalid token".to_string())
+                } else {
+                    recorder::open(&self.config.recording_dir, &name)
+                        .and_then(|file| {
+                            let size = file.metadata()?.len();
+                            Ok((file, size))
+                        })
+                        .map_err(|e| format!("Failed to open clip {}: {}", name, e))
+                };
+
+                let (file, size) = match file {
+                    Ok(res) => res,
+                    Err(e) => {
+                        let _ = stream.write_msg(&msg::RecordingDownload {
+                            ok: false,
+                            size: 0,
+                            error: Some(e.clone()),
+                        });
+                        return Err(Error::new(e));
+                    }
+                };
+                if let Err(e) = stream.write_msg(&msg::RecordingDownload {
+                    ok: true,
+                    size: size,
+                    error: None,
+                }) {
+                    return Err(Error::new(format!("{}", e)));
+                }
+
+                // Clips are large, so they are sent without blocking new connections.
+                // A clip which is still recorded is sent up to its size at the request.
+                thread::spawn(move || match io::copy(&mut file.take(size), &mut stream) {
+                    Ok(sent) => info!("Sent clip {} ({} bytes)", name, sent),
+                    Err(e) => error!("Failed to send clip {}: {}", name, e),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// List the scripts clients may run. Like the journal, it doesn't require an open session.
+    fn send_scripts(&mut self, mut stream: TcpStream, token: String) -> Result<(), Error> {
+        let response = if !self.config.is_valid_token(token) {
+            msg::Scripts {
+                ok: false,
+                names: vec![],
+                error: Some("Invalid token".to_string()),
+            }
+        } else {
+            match self.scripts.list() {
+                Ok(names) => msg::Scripts {
+                    ok: true,
+                    names: names,
+                    error: None,
+                },
+                Err(e) => msg::Scripts {
+                    ok: false,
+                    names: vec![],
+                    error: Some(format!("Failed to list scripts: {}", e)),
+                },
+            }
+        };
+
+        match stream.write_msg(&response) {
+            Ok(_) => match response.error {
+                Some(e) => Err(Error::new(e)),
+                None => {
+                    info!("Sent a list of {} scripts", response.names.len());
+                    Ok(())
+                }
+            },
+            Err(e) => Err(Error::new(format!("{}", e))),
+        }
+    }
+
     fn lookup_session(&mut self, session_id: &String) -> Option<&mut Session> {
         self.sessions.get_mut(session_id)
     }
 }
 
+/// Collect current telemetry from available sensors.
+pub fn collect_telemetry(
+    gps: &Option<sync::Arc<gps::Gps>>,
+    mount: &Option<sync::Arc<mount::Mount>>,
+    timelapse: &sync::Arc<timelapse::Timelapse>,
+    localization: &sync::Arc<localization::Localization>,
+    missions: &sync::Arc<mission::Missions>,
+    scripts: &sync::Arc<scripts::Scripts>,
+) -> msg::Telemetry {
+    msg::Telemetry {
+        timestamp_ms: chrono::Utc::now().timestamp_millis(),
+        gps: match gps {
+            Some(gps) => gps.fix(),
+            None => None,
+        },
+        mount: match mount {
+            Some(mount) => Some(mount.position()),
+            None => None,
+        },
+        timelapses: timelapse.cameras(),
+        marker_fix: localization.fix(),
+        mission: missions.progress(),
+        script: scripts.status(),
+    }
+}
+
+/// Stream of a camera feed to a client together with the analyses of its frames.
+struct VideoStream {
+    stream: TcpStream,
+    camera: String,
+    config: settings::Video,
+    feed: feed::Subscription,
+    quality: quality::QualityController,
+    region: Option<types::Region>,
+    analyses: analysis::Analyses,
+}
+
+impl VideoStream {
+    /// Accept the video connection of the client on a camera feed.
+    fn new(
+        mut stream: TcpStream,
+        camera: String,
+        config: settings::Video,
+        stream_config: camera::StreamConfig,
+        feed: feed::Subscription,
+        context: VideoContext,
+    ) -> Self {
+        let _ = stream.write_msg(&msg::OpenVideoConnection {
+            ok: true,
+            error: None,
+            orientation: feed.orientation,
+        });
+        VideoStream {
+            stream: stream,
+            quality: quality::QualityController::new(
+                config.max_latency_ms,
+                stream_config.framerate,
+            ),
+            analyses: analysis::Analyses::new(
+                camera.clone(),
+                feed.orientation,
+                context.failsafe.clone(),
+                context.localization.clone(),
+            ),
+            camera: camera,
+            config: config,
+            feed: feed,
+            region: None,
+        }
+    }
+
+    /// Stream frames until the client can't be reached anymore.
+    fn run(
+        &mut self,
+        commands: &mpsc::Receiver<types::CameraCommand>,
+        feedback: &mpsc::Receiver<msg::VideoFeedback>,
+    ) {
+        let timeout = time::Duration::from_millis(FRAME_TIMEOUT_MS);
+        loop {
+            while let Ok(feedback) = feedback.try_recv() {
+                self.quality
+                    .feedback(&feedback, chrono::Utc::now().timestamp_millis());
+            }
+            while let Ok(command) = commands.try_recv() {
+                self.handle_command(command);
+            }
+
+            let mut result = Ok(());
+            while let Ok(event) = self.feed.events.try_recv() {
+                result = result.and(self.on_event(event));
+            }
+            result = result.and(match self.feed.frames.recv_timeout(timeout) {
+                Ok(frame) => self.on_frame(frame),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    // The target can't be seen, so stop until frames are back.
+                    self.analyses.on_capture_error();
+                    Ok(())
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    error!("Camera {:?} feed is stopped", self.camera);
+                    break;
+                }
+            });
+            if let Err(e) = result {
+                error!("Failed to send video: {:?}. Stopping video stream...", e);
+                break;
+            }
+        }
+        self.analyses.stop_driving();
+    }
+
+    /// Handle a command of the client. Commands of the camera go to its feed.
+    fn handle_command(&mut self, command: types::CameraCommand) {
+        if let Some(reply) = self.analyses.handle_command(&command) {
+            let _ = self.stream.write_msg(&reply);
+        }
+        match command {
+            types::CameraCommand::SetRegion(res) => {
+                // The client sends regions of the image as it shows it.
+                let orientation = self.feed.orientation;
+                self.region = res.map(|res| orientation.source_region(res));
+                debug!("Camera {:?} region: {:?}", self.camera, self.region);
+                self.feed.set_zoomed(self.region.is_some());
+            }
+            types::CameraCommand::SetBlobTracking(_)
+            | types::CameraCommand::SetLineFollowing(_)
+            | types::CameraCommand::SetMarkerDetection(_) => {}
+            types::CameraCommand::SetResolution(_)
+            | types::CameraCommand::SetFramerate(_)
+            | types::CameraCommand::SetControl(_, _)
+            | types::CameraCommand::Snapshot
+            | types::CameraCommand::StartRecording
+            | types::CameraCommand::StopRecording
+            | types::CameraCommand::SetMotionDetection(_) => self.feed.send(command),
+        }
+    }
+
+    /// Forward a change of the feed to the client.
+    fn on_event(&mut self, event: feed::Event) -> Result<(), io::Error> {
+        match event {
+            feed::Event::Video(message) => {
+                if let msg::Video::Capabilities(ref capabilities) = *message {
+                    self.quality.set_framerate(capabilities.framerate);
+                }
+                self.stream.write_msg(&*message).map(|_| ())
+            }
+            feed::Event::Motion(motion) => {
+                self.analyses.on_motion(&motion);
+                Ok(())
+            }
+        }
+    }
+
+    /// Analyze a frame of the feed and send it to the client.
+    fn on_frame(&mut self, frame: feed::Frame) -> Result<(), io::Error> {
+        if let Some(message) = self.analyses.on_frame(&frame.data) {
+            let _ = self.stream.write_msg(&message);
+        }
+
+        let level = match self.quality.next_frame() {
+            quality::Decision::Send(level) => level,
+            quality::Decision::Skip => return Ok(()),
+        };
+        let transcoded;
+        let encoding = match level.encoding(&self.config.profile, frame.selected_resolution) {
+            // Frames captured for a zoomed region are scaled to the selected resolution.
+            None if self.region.is_some() || frame.resolution != frame.selected_resolution => {
+                Some((frame.selected_resolution, camera::REGION_QUALITY))
+            }
+            encoding => encoding,
+        };
+        let data: &[u8] = match encoding {
+            Some((bounds, jpeg_quality)) => {
+                match camera::transcode(&frame.data, self.region, bounds, jpeg_quality) {
+                    Ok(res) => {
+                        transcoded = res;
+                        &transcoded
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        &frame.data
+                    }
+                }
+            }
+            None => &frame.data,
+        };
+        let header = msg::Video::Frame(msg::VideoFrame {
+            size: data.len() as u32,
+            timestamp_ms: frame.timestamp_ms,
+            annotations: self.analyses.annotations(),
+        });
+        self.stream
+            .write_msg_with_payload(&header, data)
+            .map(|_| ())
+    }
+}
+
 pub struct Session {
     pub id: String,
+    /// Generation of the session in the failsafe.
+    generation: usize,
     conn: TcpStream,
     video_conn: Option<TcpStream>,
     state_conn: Option<TcpStream>,
+    journal: sync::Arc<journal::Journal>,
+    /// Command queues of the open video streams by camera name.
+    camera_commands: sync::Arc<sync::Mutex<HashMap<String, mpsc::Sender<types::CameraCommand>>>>,
+    /// Latest video stream of every camera, a reconnected stream replaces the previous one.
+    video_streams: sync::Arc<sync::Mutex<HashMap<String, usize>>>,
+    last_video_stream: usize,
 }
 
 impl Session {
-    pub fn new(id: String, conn: TcpStream) -> Self {
+    pub fn new(
+        id: String,
+        generation: usize,
+        conn: TcpStream,
+        journal: sync::Arc<journal::Journal>,
+    ) -> Self {
         Session {
             id: id,
+            generation: generation,
             conn: conn,
             video_conn: None,
             state_conn: None,
+            journal: journal,
+            camera_commands: sync::Arc::new(sync::Mutex::new(HashMap::new())),
+            video_streams: sync::Arc::new(sync::Mutex::new(HashMap::new())),
+            last_video_stream: 0,
         }
     }
 
+    /// Close the session connection, which stops its heartbeat watcher.
+    fn close(&self) {
+        if let Err(e) = self.conn.shutdown(Shutdown::Both) {
+            debug!("Failed to shut down session {}: {}", self.id, e);
+        }
+    }
+
+    /// Watch client heartbeats on the session connection and engage the failsafe
+    /// policy when too many beats are missed.
+    ///
+    /// The watcher stops when a newer session is opened.
+    fn watch_heartbeat(
+        &self,
+        config: common::settings::Heartbeat,
+        failsafe: sync::Arc<failsafe::Failsafe>,
+    ) -> Result<(), Error> {
+        let mut stream = match self.conn.try_clone() {
+            Ok(stream) => stream,
+            Err(e) => return Err(Error::new(format!("{}", e))),
+        };
+        let session_id = self.id.clone();
+        let generation = self.generation;
+        let journal = self.journal.clone();
+        let interval = time::Duration::from_millis(config.interval as u64);
+        let now = time::Instant::now();
+        // Time of the last received beat and of the last beat that came in time.
+        let beats = sync::Arc::new(sync::Mutex::new((now, now)));
+
+        let reader_beats = beats.clone();
+        thread::spawn(move || loop {
+            match stream.read_msg::<msg::Heartbeat>(&mut vec![]) {
+                Ok(_) => {
+                    let mut beats = reader_beats.lock().expect("Failed to lock heartbeat");
+                    let now = time::Instant::now();
+                    if now.duration_since(beats.0) <= interval * 2 {
+                        beats.1 = now;
+                    }
+                    beats.0 = now;
+                }
+                Err(e) => {
+                    debug!("Heartbeat connection is closed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            // Set after the failsafe is engaged until the heartbeat comes back.
+            let mut engaged = false;
+            loop {
+                thread::sleep(interval);
+                if !failsafe.is_current_session(generation) {
+                    debug!("Heartbeat watcher of session {} is stopped", session_id);
+                    break;
+                }
+                let (last_beat, last_good) = *beats.lock().expect("Failed to lock heartbeat");
+                let lost = last_beat.elapsed() > interval * (config.missed_beats as u32 + 1);
+                if lost && !engaged {
+                    warn!(
+                        "Session {} missed {} heartbeats.",
+                        session_id, config.missed_beats
+                    );
+                    journal.record(msg::JournalEvent::Session(format!(
+                        "Session {} missed {} heartbeats",
+                        session_id, config.missed_beats
+                    )));
+                    failsafe.engage(generation, last_good);
+                    engaged = true;
+                } else if !lost && engaged {
+                    info!("Session {} heartbeat is restored", session_id);
+                    journal.record(msg::JournalEvent::Session(format!(
+                        "Session {} heartbeat is restored",
+                        session_id
+                    )));
+                    engaged = false;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
     fn open_video_channel(
         &mut self,
         mut stream: TcpStream,
         config: common::settings::Video,
+        context: VideoContext,
     ) -> Result<(), Box<dyn error::Error>> {
-        thread::spawn(move || match rscam::new(config.device.as_str()) {
-            Ok(mut camera) => {
-                match camera.start(&rscam::Config {
-                    interval: (1, config.max_framerate as u32),
-                    resolution: config.resolution,
-                    format: b"MJPG",
-                    nbuffers: 32,
-                    field: rscam::FIELD_NONE,
-                }) {
-                    Ok(_) => {
-                        let _ = stream.write_msg(&msg::OpenVideoConnection {
-                            ok: true,
-                            error: None,
-                        });
-                        loop {
-                            match camera.capture() {
-                                Ok(mut frame) => match stream.write_msg(&msg::VideoFrame {
-                                    data: frame.to_vec(),
-                                    timestamp_ms: chrono::Utc::now().timestamp_millis(),
-                                }) {
-                                    Err(e) => {
-                                        error!(
-                                                "Failed to send VideoFrame: {:?}. Stopping video stream...",
-                                                e
-                                            );
-                                        break;
-                                    }
-                                    _ => {}
-                                },
-                                Err(e) => {
-                                    error!("Unable to take picture: {:?}", e);
-                                }
-                            }
-                            thread::sleep(time::Duration::from_millis(10));
-                        }
-                    }
-                    Err(e) => {
-                        let _ = stream.write_msg(&msg::OpenVideoConnection {
-                            ok: false,
-                            error: Some(format!("Failed to start the stream: {}", e)),
-                        });
-                    }
-                }
-            }
+        let stream_config = match camera::negotiate(&context.cameras, &config) {
+            Ok(res) => res,
             Err(e) => {
+                warn!("Video connection is rejected: {}", e);
                 let _ = stream.write_msg(&msg::OpenVideoConnection {
                     ok: false,
-                    error: Some(format!("Failed to initialize video device: {}", e)),
+                    error: Some(format!("{}", e)),
+                    orientation: types::Orientation::default(),
                 });
+                return Ok(());
+            }
+        };
+
+        let camera = match context.cameras.iter().find(|c| c.name == config.camera) {
+            Some(res) => res.clone(),
+            None => return Ok(()),
+        };
+
+        // Receiver reports come back on the same connection.
+        let (feedback_sender, feedback_receiver) = mpsc::channel();
+        let mut feedback_stream = match stream.try_clone() {
+            Ok(res) => res,
+            Err(e) => {
+                error!("Failed to open video feedback stream: {}", e);
+                return Ok(());
+            }
+        };
+        thread::spawn(move || loop {
+            match feedback_stream.read_msg::<msg::VideoFeedback>(&mut vec![]) {
+                Ok(feedback) => {
+                    if feedback_sender.send(feedback).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    debug!("Video feedback connection is closed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        let (command_sender, command_receiver) = mpsc::channel();
+        self.last_video_stream += 1;
+        let id = self.last_video_stream;
+        let mut streams = self
+            .video_streams
+            .lock()
+            .expect("Failed to lock video streams");
+        streams.insert(camera.name.clone(), id);
+        self.camera_commands
+            .lock()
+            .expect("Failed to lock camera commands")
+            .insert(camera.name.clone(), command_sender);
+        drop(streams);
+        let camera_commands = self.camera_commands.clone();
+        let video_streams = self.video_streams.clone();
+
+        thread::spawn(move || {
+            match context.feeds.subscribe(&camera, stream_config.clone()) {
+                Ok(feed) => {
+                    let mut video = VideoStream::new(
+                        stream,
+                        camera.name.clone(),
+                        config,
+                        stream_config,
+                        feed,
+                        context,
+                    );
+                    video.run(&command_receiver, &feedback_receiver);
+                }
+                Err(e) => {
+                    let _ = stream.write_msg(&msg::OpenVideoConnection {
+                        ok: false,
+                        error: Some(format!("{}", e)),
+                        orientation: types::Orientation::default(),
+                    });
+                }
+            }
+            let mut streams = video_streams.lock().expect("Failed to lock video streams");
+            // The client may have reconnected the camera in a new stream already.
+            if streams.get(&camera.name) == Some(&id) {
+                streams.remove(&camera.name);
+                camera_commands
+                    .lock()
+                    .expect("Failed to lock camera commands")
+                    .remove(&camera.name);
             }
         });
 
@@ -224,19 +895,88 @@ impl Session {
         &mut self,
         mut stream: TcpStream,
         config: common::settings::Controller,
-        machine: sync::Arc<sync::Mutex<machine::Machine>>,
+        context: VideoContext,
     ) -> Result<(), Box<dyn error::Error>> {
+        let VideoContext {
+            failsafe,
+            mount,
+            timelapse,
+            missions,
+            scripts,
+            ..
+        } = context;
         let open_ctrl_msg = stream.write_msg(&msg::OpenControllerConnection {
             ok: true,
             error: None,
         });
 
+        let journal = self.journal.clone();
+        let camera_commands = self.camera_commands.clone();
         thread::spawn(move || loop {
-            match stream.read_msg::<types::MachineState>(&mut vec![]) {
-                Ok(state) => {
+            match stream.read_msg::<msg::Control>(&mut vec![]) {
+                Ok(msg::Control::State(state)) => {
                     debug!("State: {:?}", state);
-                    let mut mutex = machine.try_lock().expect("Failed to lock GPIO");
-                    mutex.update(&state);
+                    journal.record(msg::JournalEvent::Command(state));
+                    failsafe.apply(&state);
+                }
+                Ok(msg::Control::Camera(name, command)) => {
+                    debug!("Camera {:?} command: {:?}", name, command);
+                    match camera_commands
+                        .lock()
+                        .expect("Failed to lock camera commands")
+                        .get(&name)
+                    {
+                        Some(sender) => {
+                            let _ = sender.send(command);
+                        }
+                        None => warn!("Camera {:?} is not streaming", name),
+                    }
+                }
+                Ok(msg::Control::Mount(command)) => match &mount {
+                    Some(mount) => match mount.apply(&command) {
+                        Ok(position) => debug!("Mount {:?}: {:?}", command, position),
+                        Err(e) => error!("{}", e),
+                    },
+                    None => warn!("Camera mount is not configured"),
+                },
+                Ok(msg::Control::Timelapse(name, command)) => match command {
+                    types::TimelapseCommand::Start { interval_s, format } => {
+                        match timelapse.start(&name, interval_s, format) {
+                            Ok(output) => info!("Started timelapse {}", output),
+                            Err(e) => error!("Failed to start timelapse: {}", e),
+                        }
+                    }
+                    types::TimelapseCommand::Stop => {
+                        if let Err(e) = timelapse.stop(&name) {
+                            error!("{}", e);
+                        }
+                    }
+                },
+                Ok(msg::Control::Mission(command)) => {
+                    debug!("Mission command: {:?}", command);
+                    let result = match command {
+                        types::MissionCommand::Start(mission) => {
+                            missions.start(mission, camera_commands.clone())
+                        }
+                        types::MissionCommand::Pause => missions.pause(),
+                        types::MissionCommand::Resume => missions.resume(),
+                        types::MissionCommand::Abort => missions.abort(),
+                    };
+                    if let Err(e) = result {
+                        error!("{}", e);
+                    }
+                }
+                Ok(msg::Control::Script(command)) => {
+                    debug!("Script command: {:?}", command);
+                    let result = match command {
+                        types::ScriptCommand::Start(name) => {
+                            scripts.start(&name, camera_commands.clone())
+                        }
+                        types::ScriptCommand::Stop => scripts.stop(),
+                    };
+                    if let Err(e) = result {
+                        error!("{}", e);
+                    }
                 }
                 Err(_) => {}
             }
@@ -245,4 +985,47 @@ impl Session {
 
         Ok(())
     }
+
+    fn open_telemetry_channel(
+        &mut self,
+        mut stream: TcpStream,
+        config: common::settings::Telemetry,
+        context: VideoContext,
+    ) -> Result<(), Box<dyn error::Error>> {
+        if config.interval == 0 {
+            let _ = stream.write_msg(&msg::OpenTelemetryConnection {
+                ok: false,
+                error: Some("Telemetry interval must be positive".to_string()),
+            });
+            return Ok(());
+        }
+        let _ = stream.write_msg(&msg::OpenTelemetryConnection {
+            ok: true,
+            error: None,
+        });
+
+        thread::spawn(move || loop {
+            let telemetry = collect_telemetry(
+                &context.gps,
+                &context.mount,
+                &context.timelapse,
+                &context.localization,
+                &context.missions,
+                &context.scripts,
+            );
+            match stream.write_msg(&telemetry) {
+                Err(e) => {
+                    error!(
+                        "Failed to send Telemetry: {:?}. Stopping telemetry stream...",
+                        e
+                    );
+                    break;
+                }
+                _ => {}
+            }
+            thread::sleep(time::Duration::from_millis(config.interval as u64));
+        });
+
+        Ok(())
+    }
 }
@@ -1 +1,426 @@
+extern crate image;
+extern crate rqrr;
 
+use simple_error::SimpleError as Error;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use crate::common::types::{
+    ColorRange, LineFollowing, LinePosition, Marker, Motion, Orientation, Region,
+};
+
+/// Width of the downscaled frames compared by the motion detector.
+const ANALYSIS_WIDTH: u32 = 160;
+/// Side of the grid cells motion regions are built from, in pixels of the downscaled frame.
+const CELL_SIZE: u32 = 8;
+/// Brightness difference of a pixel between frames which counts as a change.
+const PIXEL_THRESHOLD: u8 = 25;
+/// Share of changed pixels in a cell which marks the cell as moving.
+const CELL_THRESHOLD: f32 = 0.2;
+/// Shortest time between frames analyzed for motion in milliseconds.
+const MOTION_INTERVAL_MS: u64 = 200;
+/// Smallest color blob in pixels of the downscaled frame.
+const BLOB_MIN_PIXELS: usize = 12;
+/// Shortest time between frames analyzed for a color blob in milliseconds.
+const BLOB_INTERVAL_MS: u64 = 100;
+/// Height of the frame bottom searched for a line, relative to the frame height.
+const LINE_BAND: f32 = 0.3;
+/// Shares of line pixels in the band between which a line is reported. More of them
+/// mean the threshold matches the floor.
+const LINE_MIN_SHARE: f32 = 0.02;
+const LINE_MAX_SHARE: f32 = 0.5;
+/// Shortest time between frames analyzed for a line in milliseconds.
+const LINE_INTERVAL_MS: u64 = 100;
+/// Width of the downscaled frames searched for markers. Markers need more detail than motion.
+const MARKER_WIDTH: u32 = 640;
+/// Shortest time between frames analyzed for markers in milliseconds.
+const MARKER_INTERVAL_MS: u64 = 500;
+
+/// Detects motion by comparing consecutive downscaled grayscale frames.
+pub struct MotionDetector {
+    previous: Option<image::GrayImage>,
+}
+
+impl MotionDetector {
+    pub fn new() -> Self {
+        MotionDetector { previous: None }
+    }
+
+    /// Compare a JPEG frame with the previous one.
+    ///
+    /// The score is the share of changed pixels. Regions are in coordinates of the camera frame.
+    pub fn process(&mut self, data: &[u8]) -> Result<Motion, Error> {
+        let img = image::imageops::grayscale(&decode_small(data)?);
+
+        let motion = match &self.previous {
+            Some(previous) if previous.dimensions() == img.dimensions() => compare(previous, &img),
+            _ => Motion {
+                score: 0.0,
+                regions: vec![],
+            },
+        };
+        self.previous = Some(img);
+        Ok(motion)
+    }
+}
+
+fn compare(previous: &image::GrayImage, current: &image::GrayImage) -> Motion {
+    let (width, height) = current.dimensions();
+    let columns = (width + CELL_SIZE - 1) / CELL_SIZE;
+    let rows = (height + CELL_SIZE - 1) / CELL_SIZE;
+    let mut changed_cells = vec![0u32; (columns * rows) as usize];
+    let mut changed = 0;
+
+    for (x, y, pixel) in current.enumerate_pixels() {
+        let before = previous.get_pixel(x, y)[0];
+        if (pixel[0] as i16 - before as i16).abs() > PIXEL_THRESHOLD as i16 {
+            changed += 1;
+            changed_cells[((y / CELL_SIZE) * columns + x / CELL_SIZE) as usize] += 1;
+        }
+    }
+
+    let moving: Vec<bool> = changed_cells
+        .iter()
+        .map(|count| *count as f32 / (CELL_SIZE * CELL_SIZE) as f32 >= CELL_THRESHOLD)
+        .collect();
+    let regions = components(&moving, columns, rows)
+        .into_iter()
+        .map(|c| Region {
+            x: (c.x1 * CELL_SIZE) as f32 / width as f32,
+            y: (c.y1 * CELL_SIZE) as f32 / height as f32,
+            width: (((c.x2 + 1) * CELL_SIZE).min(width) - c.x1 * CELL_SIZE) as f32 / width as f32,
+            height: (((c.y2 + 1) * CELL_SIZE).min(height) - c.y1 * CELL_SIZE) as f32
+                / height as f32,
+        })
+        .collect();
+
+    Motion {
+        score: changed as f32 / (width * height) as f32,
+        regions: regions,
+    }
+}
+
+/// Connected group of marked cells of a grid.
+struct Component {
+    cells: usize,
+    x1: u32,
+    y1: u32,
+    x2: u32,
+    y2: u32,
+}
+
+/// Find groups of marked cells connected by their sides.
+fn components(marked: &[bool], columns: u32, rows: u32) -> Vec<Component> {
+    let mut visited = vec![false; marked.len()];
+    let mut groups = vec![];
+
+    for start in 0..marked.len() {
+        if !marked[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![start as u32];
+        let mut group = Component {
+            cells: 0,
+            x1: columns,
+            y1: rows,
+            x2: 0,
+            y2: 0,
+        };
+        while let Some(cell) = stack.pop() {
+            let (x, y) = (cell % columns, cell / columns);
+            group.cells += 1;
+            group.x1 = group.x1.min(x);
+            group.y1 = group.y1.min(y);
+            group.x2 = group.x2.max(x);
+            group.y2 = group.y2.max(y);
+
+            let mut neighbours = vec![];
+            if x > 0 {
+                neighbours.push(cell - 1);
+            }
+            if x + 1 < columns {
+                neighbours.push(cell + 1);
+            }
+            if y > 0 {
+                neighbours.push(cell - columns);
+            }
+            if y + 1 < rows {
+                neighbours.push(cell + columns);
+            }
+            for neighbour in neighbours {
+                let index = neighbour as usize;
+                if marked[index] && !visited[index] {
+                    visited[index] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+/// Decode a JPEG frame downscaled to the analysis width.
+fn decode_small(data: &[u8]) -> Result<image::RgbImage, Error> {
+    decode_scaled(data, ANALYSIS_WIDTH)
+}
+
+/// Decode a JPEG frame downscaled to at most `width`.
+fn decode_scaled(data: &[u8], width: u32) -> Result<image::RgbImage, Error> {
+    let img = match image::load_from_memory_with_format(data, image::ImageFormat::Jpeg) {
+        Ok(res) => res.to_rgb(),
+        Err(e) => return Err(Error::new(format!("Failed to decode a frame: {}", e))),
+    };
+    if img.width() <= width {
+        return Ok(img);
+    }
+    let height = (img.height() * width / img.width()).max(1);
+    Ok(image::imageops::resize(
+        &img,
+        width,
+        height,
+        image::imageops::FilterType::Triangle,
+    ))
+}
+
+/// Hue in degrees, saturation and value from 0 to 1.
+fn hsv(pixel: &image::Rgb<u8>) -> (f32, f32, f32) {
+    let [r, g, b] = [
+        pixel[0] as f32 / 255.0,
+        pixel[1] as f32 / 255.0,
+        pixel[2] as f32 / 255.0,
+    ];
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+fn matches(color: &ColorRange, pixel: &image::Rgb<u8>) -> bool {
+    let (hue, saturation, value) = hsv(pixel);
+    let distance = (hue - color.hue).abs() % 360.0;
+    distance.min(360.0 - distance) <= color.hue_tolerance
+        && saturation >= color.min_saturation
+        && value >= color.min_value
+}
+
+/// Find the largest area of the color in a JPEG frame.
+///
+/// Returns its bounding box in coordinates of the camera frame.
+pub fn find_blob(data: &[u8], color: &ColorRange) -> Result<Option<Region>, Error> {
+    let img = decode_small(data)?;
+    let (width, height) = img.dimensions();
+    let marked: Vec<bool> = img.pixels().map(|p| matches(color, p)).collect();
+
+    let blob = components(&marked, width, height)
+        .into_iter()
+        .filter(|c| c.cells >= BLOB_MIN_PIXELS)
+        .max_by_key(|c| c.cells);
+    Ok(blob.map(|c| Region {
+        x: c.x1 as f32 / width as f32,
+        y: c.y1 as f32 / height as f32,
+        width: (c.x2 - c.x1 + 1) as f32 / width as f32,
+        height: (c.y2 - c.y1 + 1) as f32 / height as f32,
+    }))
+}
+
+/// Find a line in the bottom of a JPEG frame as it's shown by the client,
+/// `orientation` is the transform the client applies.
+pub fn find_line(
+    data: &[u8],
+    orientation: Orientation,
+    following: &LineFollowing,
+) -> Result<Option<LinePosition>, Error> {
+    let img = orientation
+        .apply(image::DynamicImage::ImageRgb8(decode_small(data)?))
+        .to_luma();
+    let (width, height) = img.dimensions();
+    let top = ((height as f32 * (1.0 - LINE_BAND)) as u32).min(height - 1);
+    let threshold = (following.threshold.max(0.0).min(1.0) * 255.0) as u8;
+
+    let mut count = 0;
+    let mut sum: u64 = 0;
+    for y in top..height {
+        for x in 0..width {
+            let value = img.get_pixel(x, y)[0];
+            if (following.bright && value > threshold) || (!following.bright && value < threshold) {
+                count += 1;
+                sum += x as u64;
+            }
+        }
+    }
+
+    let share = count as f32 / (width * (height - top)) as f32;
+    if share < LINE_MIN_SHARE || share > LINE_MAX_SHARE {
+        return Ok(None);
+    }
+    Ok(Some(LinePosition {
+        x: (sum as f32 / count as f32 + 0.5) / width as f32,
+        band: Region {
+            x: 0.0,
+            y: top as f32 / height as f32,
+            width: 1.0,
+            height: (height - top) as f32 / height as f32,
+        },
+    }))
+}
+
+/// Find and decode QR codes in a JPEG frame.
+///
+/// Regions are in coordinates of the camera frame.
+pub fn find_markers(data: &[u8]) -> Result<Vec<Marker>, Error> {
+    let img = image::imageops::grayscale(&decode_scaled(data, MARKER_WIDTH)?);
+    let (width, height) = (img.width() as f32, img.height() as f32);
+    let mut prepared = rqrr::PreparedImage::prepare(img);
+
+    let mut markers = vec![];
+    for grid in prepared.detect_grids() {
+        let payload = match grid.decode() {
+            Ok((_, content)) => content,
+            Err(e) => {
+                debug!("Failed to decode a marker: {:?}", e);
+                continue;
+            }
+        };
+        let xs = grid.bounds.iter().map(|p| p.x.max(0) as f32);
+        let ys = grid.bounds.iter().map(|p| p.y.max(0) as f32);
+        let (x1, x2) = (xs.clone().fold(width, f32::min), xs.fold(0.0, f32::max));
+        let (y1, y2) = (ys.clone().fold(height, f32::min), ys.fold(0.0, f32::max));
+        markers.push(Marker {
+            payload: payload,
+            region: Region {
+                x: x1 / width,
+                y: y1 / height,
+                width: (x2.min(width) - x1).max(0.0) / width,
+                height: (y2.min(height) - y1).max(0.0) / height,
+            },
+        });
+    }
+    Ok(markers)
+}
+
+/// Runs frame analysis in a background thread, so it doesn't delay the stream.
+///
+/// Frames are skipped while the analysis is busy or when one was analyzed recently.
+pub struct Worker<R> {
+    frames: mpsc::SyncSender<Vec<u8>>,
+    results: mpsc::Receiver<R>,
+    interval: time::Duration,
+    last_frame: Option<time::Instant>,
+}
+
+impl<R: Send + 'static> Worker<R> {
+    pub fn start<F>(camera: String, interval_ms: u64, mut analyze: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Result<R, Error> + Send + 'static,
+    {
+        let (frames, frame_receiver) = mpsc::sync_channel::<Vec<u8>>(1);
+        let (result_sender, results) = mpsc::channel();
+
+        thread::spawn(move || {
+            // The thread ends when the worker is dropped.
+            while let Ok(frame) = frame_receiver.recv() {
+                match analyze(&frame) {
+                    Ok(result) => {
+                        if result_sender.send(result).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("Analysis of camera {:?} failed: {}", camera, e),
+                }
+            }
+        });
+
+        Worker {
+            frames: frames,
+            results: results,
+            interval: time::Duration::from_millis(interval_ms),
+            last_frame: None,
+        }
+    }
+
+    /// Hand a JPEG frame over for analysis.
+    pub fn offer(&mut self, frame: &[u8]) {
+        if self
+            .last_frame
+            .map_or(false, |t| t.elapsed() < self.interval)
+        {
+            return;
+        }
+        if self.frames.try_send(frame.to_vec()).is_ok() {
+            self.last_frame = Some(time::Instant::now());
+        }
+    }
+
+    /// Latest result of an analyzed frame, if any is ready.
+    pub fn result(&self) -> Option<R> {
+        self.results.try_iter().last()
+    }
+}
+
+/// Start motion detection of a camera. Regions of the results are in coordinates
+/// of the image shown by the client, `orientation` is the transform it applies.
+pub fn motion_worker(camera: String, orientation: Orientation) -> Worker<Motion> {
+    let mut detector = MotionDetector::new();
+    Worker::start(camera, MOTION_INTERVAL_MS, move |frame| {
+        let motion = detector.process(frame)?;
+        Ok(Motion {
+            regions: motion
+                .regions
+                .into_iter()
+                .map(|r| orientation.view_region(r))
+                .collect(),
+            ..motion
+        })
+    })
+}
+
+/// Start tracking of a color blob. Results are in coordinates of the image shown by the client,
+/// `orientation` is the transform it applies.
+pub fn blob_worker(
+    camera: String,
+    orientation: Orientation,
+    color: ColorRange,
+) -> Worker<Option<Region>> {
+    Worker::start(camera, BLOB_INTERVAL_MS, move |frame| {
+        Ok(find_blob(frame, &color)?.map(|r| orientation.view_region(r)))
+    })
+}
+
+/// Start following of a line. Results are in coordinates of the image shown by the client,
+/// `orientation` is the transform it applies.
+pub fn line_worker(
+    camera: String,
+    orientation: Orientation,
+    following: LineFollowing,
+) -> Worker<Option<LinePosition>> {
+    Worker::start(camera, LINE_INTERVAL_MS, move |frame| {
+        find_line(frame, orientation, &following)
+    })
+}
+
+/// Start marker detection of a camera. Regions of the results are in coordinates
+/// of the image shown by the client, `orientation` is the transform it applies.
+pub fn marker_worker(camera: String, orientation: Orientation) -> Worker<Vec<Marker>> {
+    Worker::start(camera, MARKER_INTERVAL_MS, move |frame| {
+        Ok(find_markers(frame)?
+            .into_iter()
+            .map(|marker| Marker {
+                region: orientation.view_region(marker.region),
+                ..marker
+            })
+            .collect())
+    })
+}
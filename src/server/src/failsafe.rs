@@ -0,0 +1,296 @@
+use std::collections::VecDeque;
+use std::sync;
+use std::sync::atomic;
+use std::thread;
+use std::time;
+
+use crate::common::messages as msg;
+use crate::common::types::{LossPolicy, MachineState};
+use crate::journal;
+use crate::machine;
+
+/// Period of the software PWM used to coast down engines.
+const COAST_PERIOD_MS: u64 = 100;
+
+struct Movement {
+    at: time::Instant,
+    state: MachineState,
+}
+
+/// Applies client states to the machine and takes over when the client is lost.
+pub struct Failsafe {
+    policy: LossPolicy,
+    machine: sync::Arc<sync::Mutex<machine::Machine>>,
+    history: sync::Mutex<VecDeque<Movement>>,
+    interrupted: atomic::AtomicBool,
+    /// Generation of the newest session. Only its heartbeat loss engages the policy.
+    session: atomic::AtomicUsize,
+    /// Token of the autonomous mode which may drive the machine, 0 when there is none.
+    autonomy: atomic::AtomicUsize,
+    /// Last token given to an autonomous mode.
+    last_autonomy: atomic::AtomicUsize,
+    /// Lamp set by an autonomous mode instead of the client.
+    autonomous_lamp: sync::Mutex<Option<bool>>,
+    journal: sync::Arc<journal::Journal>,
+}
+
+impl Failsafe {
+    pub fn new(
+        policy: LossPolicy,
+        machine: sync::Arc<sync::Mutex<machine::Machine>>,
+        journal: sync::Arc<journal::Journal>,
+    ) -> Self {
+        Failsafe {
+            policy: policy,
+            machine: machine,
+            history: sync::Mutex::new(VecDeque::new()),
+            interrupted: atomic::AtomicBool::new(false),
+            session: atomic::AtomicUsize::new(0),
+            autonomy: atomic::AtomicUsize::new(0),
+            last_autonomy: atomic::AtomicUsize::new(0),
+            autonomous_lamp: sync::Mutex::new(None),
+            journal: journal,
+        }
+    }
+
+    pub fn policy(&self) -> LossPolicy {
+        self.policy
+    }
+
+    /// Make a new session the owner of the heartbeat policy and return its generation.
+    pub fn open_session(&self) -> usize {
+        self.session.fetch_add(1, atomic::Ordering::SeqCst) + 1
+    }
+
+    /// Whether `session` is still the newest session.
+    pub fn is_current_session(&self, session: usize) -> bool {
+        self.session.load(atomic::Ordering::SeqCst) == session
+    }
+
+    /// Last state applied from the client.
+    pub fn last_state(&self) -> MachineState {
+        match self.history.lock().expect("Failed to lock history").back() {
+            Some(movement) => movement.state,
+            None => MachineState::new(),
+        }
+    }
+
+    /// Apply a state received from the client and remember it for the return home policy.
+    ///
+    /// Any running policy and autonomous mode is interrupted.
+    pub fn apply(&self, state: &MachineState) {
+        let mut machine = self.machine.lock().expect("Failed to lock GPIO");
+        self.interrupted.store(true, atomic::Ordering::SeqCst);
+        if self.autonomy.swap(0, atomic::Ordering::SeqCst) != 0 {
+            info!("Autonomous mode is cancelled by manual input.");
+        }
+        machine.update(state);
+        drop(machine);
+        self.journal.record(msg::JournalEvent::Output(*state));
+
+        let now = time::Instant::now();
+        let mut history = self.history.lock().expect("Failed to lock history");
+        history.push_back(Movement {
+            at: now,
+            state: *state,
+        });
+        if let LossPolicy::ReturnHome(window_ms) = self.policy {
+            let window = time::Duration::from_millis(window_ms as u64);
+            // Keep the movement that was active at the beginning of the window.
+            while history.len() > 1 && now.duration_since(history[1].at) > window {
+                history.pop_front();
+            }
+        } else {
+            while history.len() > 1 {
+                history.pop_front();
+            }
+        }
+    }
+
+    /// Switch the lamp on behalf of the server, e.g. by the sentry.
+    ///
+    /// Unlike `apply`, it doesn't interrupt a running policy or autonomous mode and isn't
+    /// retraced by the return home policy. The next client state sets the lamp again.
+    pub fn set_lamp(&self, enabled: bool) {
+        let mut machine = self.machine.lock().expect("Failed to lock GPIO");
+        let state = MachineState {
+            lamp_enabled: enabled,
+            ..self.last_state()
+        };
+        machine.update(&state);
+        drop(machine);
+        self.journal.record(msg::JournalEvent::Output(state));
+    }
+
+    /// Let an autonomous mode drive the machine until manual input, a heartbeat loss,
+    /// another mode or `stop_autonomy`.
+    ///
+    /// Returns the token the mode drives with. Tokens of replaced modes are rejected.
+    pub fn start_autonomy(&self) -> usize {
+        let mut machine = self.machine.lock().expect("Failed to lock GPIO");
+        let token = self.last_autonomy.fetch_add(1, atomic::Ordering::SeqCst) + 1;
+        *self.autonomous_lamp.lock().expect("Failed to lock lamp") = None;
+        if self.autonomy.swap(token, atomic::Ordering::SeqCst) != 0 {
+            info!("Autonomous mode is replaced by another one.");
+            let state = self.last_state().halted();
+            machine.update(&state);
+            drop(machine);
+            self.journal.record(msg::JournalEvent::Output(state));
+        }
+        token
+    }
+
+    /// Whether any autonomous mode drives the machine.
+    pub fn is_autonomous(&self) -> bool {
+        self.autonomy.load(atomic::Ordering::SeqCst) != 0
+    }
+
+    /// Whether the mode started with `token` still drives the machine.
+    pub fn owns_autonomy(&self, token: usize) -> bool {
+        self.autonomy.load(atomic::Ordering::SeqCst) == token
+    }
+
+    /// Apply a state of the autonomous mode started with `token`. The lamp stays as
+    /// set by the client unless the mode has set it with `set_autonomous_lamp`.
+    ///
+    /// Returns `false` when the mode has been cancelled and the state is not applied.
+    pub fn apply_autonomous(&self, token: usize, state: &MachineState) -> bool {
+        let mut machine = self.machine.lock().expect("Failed to lock GPIO");
+        if !self.owns_autonomy(token) {
+            return false;
+        }
+        let lamp = self.autonomous_lamp.lock().expect("Failed to lock lamp");
+        let state = MachineState {
+            lamp_enabled: lamp.unwrap_or(self.last_state().lamp_enabled),
+            ..*state
+        };
+        drop(lamp);
+        machine.update(&state);
+        drop(machine);
+        self.journal.record(msg::JournalEvent::Output(state));
+        true
+    }
+
+    /// Set the lamp for the next states of the autonomous mode started with `token`.
+    /// The lamp is returned to the client state when the mode stops.
+    pub fn set_autonomous_lamp(&self, token: usize, enabled: bool) {
+        let _machine = self.machine.lock().expect("Failed to lock GPIO");
+        if self.owns_autonomy(token) {
+            *self.autonomous_lamp.lock().expect("Failed to lock lamp") = Some(enabled);
+        }
+    }
+
+    /// Stop the autonomous mode started with `token` and the engines. Other modes
+    /// keep driving.
+    pub fn stop_autonomy(&self, token: usize) {
+        let mut machine = self.machine.lock().expect("Failed to lock GPIO");
+        if self
+            .autonomy
+            .compare_exchange(token, 0, atomic::Ordering::SeqCst, atomic::Ordering::SeqCst)
+            .is_ok()
+        {
+            let state = self.last_state().halted();
+            machine.update(&state);
+            drop(machine);
+            self.journal.record(msg::JournalEvent::Output(state));
+        }
+    }
+
+    /// Run the configured policy after the heartbeat of `session` is lost.
+    ///
+    /// `last_good` is the moment when the signal was last considered good.
+    /// Blocks until the policy is done or interrupted by a new state. A loss of
+    /// a session replaced by a newer one is ignored.
+    pub fn engage(&self, session: usize, last_good: time::Instant) {
+        if !self.is_current_session(session) {
+            info!("Heartbeat loss of a replaced session is ignored.");
+            return;
+        }
+        self.interrupted.store(false, atomic::Ordering::SeqCst);
+        if self.autonomy.swap(0, atomic::Ordering::SeqCst) != 0 {
+            warn!("Autonomous mode is cancelled by the heartbeat loss.");
+        }
+        let last = self.last_state();
+
+        warn!("Heartbeat lost. Engaging {} policy...", self.policy);
+        self.journal.record(msg::JournalEvent::Session(format!(
+            "Engaged {} policy",
+            self.policy
+        )));
+        match self.policy {
+            LossPolicy::Stop => {}
+            LossPolicy::Coast(timeout_ms) => self.coast(&last, timeout_ms as u64),
+            LossPolicy::ReturnHome(window_ms) => self.return_home(last_good, window_ms as u64),
+        }
+        if !self.set_state(&last.halted()) {
+            info!("Failsafe policy is interrupted by a new state.");
+            return;
+        }
+        info!("Failsafe policy is done. Engines stopped.");
+    }
+
+    /// Set the machine state unless the policy has been interrupted.
+    fn set_state(&self, state: &MachineState) -> bool {
+        let mut machine = self.machine.lock().expect("Failed to lock GPIO");
+        if self.interrupted.load(atomic::Ordering::SeqCst) {
+            return false;
+        }
+        machine.update(state);
+        drop(machine);
+        self.journal.record(msg::JournalEvent::Output(*state));
+        true
+    }
+
+    fn coast(&self, state: &MachineState, timeout_ms: u64) {
+        let started = time::Instant::now();
+        let timeout = time::Duration::from_millis(timeout_ms);
+
+        while started.elapsed() < timeout {
+            let duty = 1.0 - started.elapsed().as_millis() as f64 / timeout_ms as f64;
+            let on_ms = (COAST_PERIOD_MS as f64 * duty) as u64;
+
+            if !self.set_state(state) {
+                return;
+            }
+            thread::sleep(time::Duration::from_millis(on_ms));
+            if !self.set_state(&state.halted()) {
+                return;
+            }
+            thread::sleep(time::Duration::from_millis(COAST_PERIOD_MS - on_ms));
+        }
+    }
+
+    fn return_home(&self, last_good: time::Instant, window_ms: u64) {
+        let now = time::Instant::now();
+        let start = match now.checked_sub(time::Duration::from_millis(window_ms)) {
+            Some(window_start) if window_start > last_good => window_start,
+            _ => last_good,
+        };
+        let mut steps: Vec<(MachineState, time::Duration)> = vec![];
+        {
+            let history = self.history.lock().expect("Failed to lock history");
+            for (i, movement) in history.iter().enumerate() {
+                let until = match history.get(i + 1) {
+                    Some(next) => next.at,
+                    None => now,
+                };
+                let since = if movement.at < start {
+                    start
+                } else {
+                    movement.at
+                };
+                if until > since {
+                    steps.push((movement.state.inverted(), until - since));
+                }
+            }
+        }
+
+        info!("Retracing {} movements...", steps.len());
+        for (state, duration) in steps.iter().rev() {
+            if !self.set_state(state) {
+                return;
+            }
+            thread::sleep(*duration);
+        }
+    }
+}
@@ -0,0 +1,965 @@
+use chrono;
+use simple_error::SimpleError as Error;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync;
+use std::sync::atomic;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use crate::camera;
+use crate::common::messages as msg;
+use crate::common::settings;
+use crate::common::types;
+use crate::conn;
+use crate::detect;
+use crate::failsafe;
+use crate::gps;
+use crate::journal;
+use crate::localization;
+use crate::mission;
+use crate::mount;
+use crate::recorder;
+use crate::scripts;
+use crate::snapshot;
+use crate::timelapse;
+
+/// Frames captured after the camera is reopened for a snapshot, so exposure can settle.
+const SNAPSHOT_WARMUP_FRAMES: u32 = 5;
+/// Consecutive capture errors after which the camera is reopened.
+const CAPTURE_FAILURES_BEFORE_RECOVERY: u32 = 5;
+const RECOVERY_MIN_BACKOFF_MS: u64 = 500;
+const RECOVERY_MAX_BACKOFF_MS: u64 = 10000;
+/// Longest time commands wait while the camera is reconnecting in milliseconds.
+const RECOVERY_POLL_MS: u64 = 100;
+/// Shortest time between sentry snapshots and lamp triggers in milliseconds.
+const SENTRY_COOLDOWN_MS: u64 = 10000;
+/// How long a sentry recording continues after the last motion in seconds.
+const SENTRY_RECORDING_SECS: u64 = 30;
+
+/// Server resources used by the camera feeds.
+#[derive(Clone)]
+pub struct Context {
+    pub gps: Option<sync::Arc<gps::Gps>>,
+    pub mount: Option<sync::Arc<mount::Mount>>,
+    pub snapshot_dir: String,
+    pub recording_dir: String,
+    pub recording_limit: u64,
+    pub timelapse: sync::Arc<timelapse::Timelapse>,
+    pub localization: sync::Arc<localization::Localization>,
+    pub missions: sync::Arc<mission::Missions>,
+    pub scripts: sync::Arc<scripts::Scripts>,
+    pub failsafe: sync::Arc<failsafe::Failsafe>,
+    pub journal: sync::Arc<journal::Journal>,
+}
+
+/// Frame captured by a feed.
+pub struct Frame {
+    /// Captured buffer shared by the subscribers without a copy.
+    pub data: sync::Arc<camera::Frame>,
+    pub resolution: (u32, u32),
+    /// Resolution selected by the clients. Frames are larger while a region is zoomed.
+    pub selected_resolution: (u32, u32),
+    pub timestamp_ms: i64,
+}
+
+/// Change of a feed reported to its subscribers.
+pub enum Event {
+    /// Message to forward to the client.
+    Video(Box<msg::Video>),
+    /// Motion with a score of at least the threshold of the detection.
+    Motion(types::Motion),
+}
+
+enum Command {
+    Subscribe(Subscriber, mpsc::Sender<Result<types::Orientation, String>>),
+    Unsubscribe(usize),
+    /// Whether a subscriber crops a region of the frames.
+    Zoom(usize, bool),
+    Camera(types::CameraCommand),
+}
+
+struct Subscriber {
+    id: usize,
+    frames: mpsc::SyncSender<Frame>,
+    events: mpsc::Sender<Event>,
+}
+
+/// Frames and events of a feed received by a video stream until it is dropped.
+pub struct Subscription {
+    /// Transform left for the client.
+    pub orientation: types::Orientation,
+    /// Latest frame. Frames are dropped while the subscriber is busy.
+    pub frames: mpsc::Receiver<Frame>,
+    pub events: mpsc::Receiver<Event>,
+    id: usize,
+    commands: mpsc::Sender<Command>,
+}
+
+impl Subscription {
+    /// Pass a command to the camera of the feed.
+    pub fn send(&self, command: types::CameraCommand) {
+        let _ = self.commands.send(Command::Camera(command));
+    }
+
+    /// Have the camera captured at its largest resolution while a region is cropped,
+    /// so the region keeps its detail.
+    pub fn set_zoomed(&self, zoomed: bool) {
+        let _ = self.commands.send(Command::Zoom(self.id, zoomed));
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Unsubscribe(self.id));
+    }
+}
+
+/// Cameras captured by the server.
+///
+/// A camera is captured in its own thread while it's streamed to a client, recorded or
+/// guarded by the sentry, so these don't depend on the client link.
+pub struct Feeds {
+    context: Context,
+    feeds: sync::Arc<sync::Mutex<HashMap<String, mpsc::Sender<Command>>>>,
+    last_id: atomic::AtomicUsize,
+}
+
+impl Feeds {
+    pub fn new(context: Context) -> Self {
+        Feeds {
+            context: context,
+            feeds: sync::Arc::new(sync::Mutex::new(HashMap::new())),
+            last_id: atomic::AtomicUsize::new(0),
+        }
+    }
+
+    /// Receive frames of a camera, opening it with `stream_config` unless it's
+    /// already captured.
+    pub fn subscribe(
+        &self,
+        camera: &camera::CameraConfig,
+        stream_config: camera::StreamConfig,
+    ) -> Result<Subscription, Error> {
+        let (frame_sender, frames) = mpsc::sync_channel(1);
+        let (event_sender, events) = mpsc::channel();
+        let (reply_sender, reply) = mpsc::channel();
+        let id = self.last_id.fetch_add(1, atomic::Ordering::SeqCst) + 1;
+        let subscriber = Subscriber {
+            id: id,
+            frames: frame_sender,
+            events: event_sender,
+        };
+
+        let mut feeds = lock(&self.feeds);
+        let commands = match feeds.get(&camera.name) {
+            Some(res) => res.clone(),
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                let feed_camera = camera.clone();
+                let context = self.context.clone();
+                let feed_feeds = self.feeds.clone();
+                thread::spawn(move || {
+                    Feed::run(feed_camera, stream_config, context, receiver, feed_feeds)
+                });
+                feeds.insert(camera.name.clone(), sender.clone());
+                sender
+            }
+        };
+        // Commands are sent under the lock, so a feed doesn't miss them when it stops.
+        if commands
+            .send(Command::Subscribe(subscriber, reply_sender))
+            .is_err()
+        {
+            return Err(Error::new("Camera feed is stopped"));
+        }
+        drop(feeds);
+
+        match reply.recv() {
+            Ok(Ok(orientation)) => Ok(Subscription {
+                orientation: orientation,
+                frames: frames,
+                events: events,
+                id: id,
+                commands: commands,
+            }),
+            Ok(Err(e)) => Err(Error::new(e)),
+            Err(_) => Err(Error::new("Camera feed is stopped")),
+        }
+    }
+}
+
+struct Recovery {
+    attempt: u32,
+    backoff_ms: u64,
+    at: time::Instant,
+    reason: String,
+}
+
+/// Capture of a camera owned by the server.
+struct Feed {
+    camera: camera::CameraConfig,
+    context: Context,
+    source: Option<Box<dyn camera::FrameSource>>,
+    stream_config: camera::StreamConfig,
+    capabilities: types::CameraCapabilities,
+    /// Resolution selected by the clients, the camera runs at the largest one while zoomed.
+    selected_resolution: (u32, u32),
+    /// Subscribers which crop a region of the frames.
+    zoomed: HashSet<usize>,
+    /// Transform left for the client.
+    orientation: types::Orientation,
+    subscribers: Vec<Subscriber>,
+    recorder: Option<recorder::Recorder>,
+    motion: Option<(types::MotionDetection, detect::Worker<types::Motion>)>,
+    last_sentry_trigger: Option<time::Instant>,
+    /// Set while a recording started by the sentry goes on.
+    sentry_recording_until: Option<time::Instant>,
+    failures: u32,
+    /// Set while the camera is reconnecting.
+    recovery: Option<Recovery>,
+}
+
+impl Feed {
+    fn run(
+        camera: camera::CameraConfig,
+        stream_config: camera::StreamConfig,
+        context: Context,
+        commands: mpsc::Receiver<Command>,
+        feeds: sync::Arc<sync::Mutex<HashMap<String, mpsc::Sender<Command>>>>,
+    ) {
+        // A timelapse of the camera takes frames from the feed while it runs.
+        let _timelapse_guard = context.timelapse.acquire(&camera.name);
+        let mut feed = match Feed::open(camera.clone(), stream_config, context) {
+            Ok(res) => res,
+            Err(e) => {
+                let mut feeds = lock(&feeds);
+                feeds.remove(&camera.name);
+                while let Ok(command) = commands.try_recv() {
+                    if let Command::Subscribe(_, reply) = command {
+                        let _ = reply.send(Err(format!("{}", e)));
+                    }
+                }
+                return;
+            }
+        };
+        info!("Camera {:?} feed is started", camera.name);
+
+        loop {
+            while let Ok(command) = commands.try_recv() {
+                feed.handle_command(command);
+            }
+            if feed.is_idle() {
+                let mut feeds = lock(&feeds);
+                // A subscriber may have come after the commands were handled.
+                match commands.try_recv() {
+                    Ok(command) => {
+                        drop(feeds);
+                        feed.handle_command(command);
+                        continue;
+                    }
+                    Err(_) => {
+                        feeds.remove(&camera.name);
+                        break;
+                    }
+                }
+            }
+
+            if feed.source.is_some() {
+                feed.capture();
+            } else {
+                feed.recover();
+            }
+        }
+
+        if let Some(recorder) = feed.recorder.take() {
+            if let Err(e) = recorder.stop() {
+                error!("Failed to finish recording: {}", e);
+            }
+        }
+        info!("Camera {:?} feed is stopped", camera.name);
+    }
+
+    fn open(
+        camera: camera::CameraConfig,
+        stream_config: camera::StreamConfig,
+        context: Context,
+    ) -> Result<Self, Error> {
+        let mut source = camera::open(&stream_config)?;
+        let orientation = camera::apply_orientation(&mut source, camera.orientation);
+        debug!(
+            "Camera {:?} orientation: {:?}, left for the client: {:?}",
+            camera.name, camera.orientation, orientation
+        );
+        let capabilities = camera::capabilities(&source, &camera, &stream_config);
+        Ok(Feed {
+            camera: camera,
+            context: context,
+            source: Some(source),
+            selected_resolution: stream_config.resolution,
+            stream_config: stream_config,
+            capabilities: capabilities,
+            zoomed: HashSet::new(),
+            orientation: orientation,
+            subscribers: vec![],
+            recorder: None,
+            motion: None,
+            last_sentry_trigger: None,
+            sentry_recording_until: None,
+            failures: 0,
+            recovery: None,
+        })
+    }
+
+    /// Whether nothing needs the camera anymore.
+    fn is_idle(&self) -> bool {
+        self.subscribers.is_empty() && self.recorder.is_none() && !self.is_guarded()
+    }
+
+    /// Whether the sentry watches the camera, which goes on without subscribers.
+    fn is_guarded(&self) -> bool {
+        self.motion
+            .as_ref()
+            .map_or(false, |(detection, _)| detection.sentry.is_some())
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Subscribe(subscriber, reply) => {
+                if reply.send(Ok(self.orientation)).is_err() {
+                    return;
+                }
+                let mut messages = vec![msg::Video::Capabilities(self.capabilities.clone())];
+                if let Some(recorder) = &self.recorder {
+                    messages.push(msg::Video::Recording(Ok(Some(recorder.clip().clone()))));
+                }
+                if let Some(recovery) = &self.recovery {
+                    messages.push(msg::Video::Status(Some(self.status(recovery))));
+                }
+                for message in messages {
+                    let _ = subscriber.events.send(Event::Video(Box::new(message)));
+                }
+                self.subscribers.push(subscriber);
+            }
+            Command::Unsubscribe(id) => {
+                self.subscribers.retain(|subscriber| subscriber.id != id);
+                if self.zoomed.remove(&id) {
+                    self.update_zoom();
+                }
+                if self.subscribers.is_empty() && self.motion.is_some() {
+                    if self.is_guarded() {
+                        info!("Camera {:?} stays guarded by the sentry", self.camera.name);
+                    } else {
+                        info!("Camera {:?} motion detection is stopped", self.camera.name);
+                        self.motion = None;
+                    }
+                }
+            }
+            Command::Zoom(id, zoomed) => {
+                let changed = if zoomed {
+                    self.zoomed.insert(id)
+                } else {
+                    self.zoomed.remove(&id)
+                };
+                if changed {
+                    self.update_zoom();
+                }
+            }
+            Command::Camera(command) => self.handle_camera_command(command),
+        }
+    }
+
+    /// Capture at the largest allowed resolution while a region is zoomed and at the
+    /// selected one otherwise.
+    fn update_zoom(&mut self) {
+        let resolution = if self.zoomed.is_empty() {
+            self.selected_resolution
+        } else {
+            self.capabilities
+                .resolutions
+                .iter()
+                .max_by_key(|(width, height)| width * height)
+                .cloned()
+                .unwrap_or(self.selected_resolution)
+        };
+        if resolution != self.stream_config.resolution {
+            self.reconfigure(types::CameraCommand::SetResolution(resolution));
+        }
+    }
+
+    /// Reopen the camera with a new resolution or framerate, or set a control.
+    fn reconfigure(&mut self, command: types::CameraCommand) {
+        match apply_camera_command(
+            &mut self.source,
+            &self.camera,
+            &mut self.stream_config,
+            &self.capabilities,
+            command,
+        ) {
+            Ok(_) => self.on_camera_changed(),
+            Err(e) => {
+                warn!("Camera {:?} command failed: {}", self.camera.name, e);
+                // Neither the new nor the previous stream could be opened.
+                if self.source.is_none() && self.recovery.is_none() {
+                    self.start_recovery(format!("{}", e));
+                }
+            }
+        }
+    }
+
+    fn handle_camera_command(&mut self, command: types::CameraCommand) {
+        match command {
+            types::CameraCommand::Snapshot => {
+                let reply = self.snapshot();
+                self.publish(msg::Video::Snapshot(reply));
+            }
+            types::CameraCommand::StartRecording => {
+                // A recording started by hand isn't stopped by the sentry.
+                self.sentry_recording_until = None;
+                let reply = match self.recorder {
+                    Some(ref recorder) => Ok(Some(recorder.clip().clone())),
+                    None => self.start_recording(),
+                };
+                self.publish(msg::Video::Recording(reply));
+            }
+            types::CameraCommand::StopRecording => {
+                self.sentry_recording_until = None;
+                let reply = match self.recorder.take() {
+                    Some(recorder) => match recorder.stop() {
+                        Ok(_) => Ok(None),
+                        Err(e) => {
+                            error!("Failed to finish recording: {}", e);
+                            Err(format!("Failed to finish recording: {}", e))
+                        }
+                    },
+                    None => Ok(None),
+                };
+                self.publish(msg::Video::Recording(reply));
+            }
+            types::CameraCommand::SetMotionDetection(res) => {
+                info!("Camera {:?} motion detection: {:?}", self.camera.name, res);
+                let (camera, orientation) = (self.camera.name.clone(), self.orientation);
+                self.motion =
+                    res.map(|detection| (detection, detect::motion_worker(camera, orientation)));
+            }
+            types::CameraCommand::SetResolution(resolution) if !self.zoomed.is_empty() => {
+                // The camera keeps running at the largest resolution until the zoom ends.
+                if self.capabilities.resolutions.contains(&resolution) {
+                    self.selected_resolution = resolution;
+                    self.on_camera_changed();
+                } else {
+                    warn!(
+                        "Camera {:?} resolution {}x{} is not supported",
+                        self.camera.name, resolution.0, resolution.1
+                    );
+                }
+            }
+            types::CameraCommand::SetResolution(_)
+            | types::CameraCommand::SetFramerate(_)
+            | types::CameraCommand::SetControl(_, _) => self.reconfigure(command),
+            types::CameraCommand::SetRegion(_)
+            | types::CameraCommand::SetBlobTracking(_)
+            | types::CameraCommand::SetLineFollowing(_)
+            | types::CameraCommand::SetMarkerDetection(_) => {
+                warn!("Camera {:?} feed ignores {:?}", self.camera.name, command)
+            }
+        }
+    }
+
+    /// Send a message to all subscribers.
+    fn publish(&mut self, message: msg::Video) {
+        for subscriber in &self.subscribers {
+            let _ = subscriber
+                .events
+                .send(Event::Video(Box::new(message.clone())));
+        }
+    }
+
+    /// Take a snapshot and save it on the server.
+    fn snapshot(&mut self) -> Result<msg::Snapshot, String> {
+        let result = take_snapshot(
+            &mut self.source,
+            &self.camera,
+            &self.stream_config,
+            &self.capabilities,
+        );
+        if let Err(ref e) = result {
+            // The stream couldn't be restored after the snapshot.
+            if self.source.is_none() && self.recovery.is_none() {
+                self.start_recovery(format!("{}", e));
+            }
+        }
+        match result {
+            Ok((data, resolution)) => {
+                let snapshot = self.make_snapshot(data, resolution);
+                match snapshot::save(&self.context.snapshot_dir, &snapshot) {
+                    Ok(path) => info!("Saved snapshot to {:?}", path),
+                    Err(e) => error!("Failed to save snapshot: {}", e),
+                }
+                Ok(snapshot)
+            }
+            Err(e) => {
+                error!("Failed to take snapshot: {}", e);
+                Err(format!("{}", e))
+            }
+        }
+    }
+
+    fn make_snapshot(&self, data: Vec<u8>, resolution: (u32, u32)) -> msg::Snapshot {
+        msg::Snapshot {
+            camera: self.camera.name.clone(),
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            resolution: resolution,
+            orientation: self.orientation,
+            telemetry: conn::collect_telemetry(
+                &self.context.gps,
+                &self.context.mount,
+                &self.context.timelapse,
+                &self.context.localization,
+                &self.context.missions,
+                &self.context.scripts,
+            ),
+            data: data,
+        }
+    }
+
+    fn start_recording(&mut self) -> Result<Option<String>, String> {
+        match recorder::Recorder::start(
+            &self.context.recording_dir,
+            &self.camera.name,
+            self.stream_config.resolution,
+            self.context.recording_limit,
+        ) {
+            Ok(res) => {
+                let clip = res.clip().clone();
+                self.recorder = Some(res);
+                Ok(Some(clip))
+            }
+            Err(e) => {
+                error!("Failed to start recording: {}", e);
+                Err(format!("Failed to start recording: {}", e))
+            }
+        }
+    }
+
+    /// Report the parameters of a reopened camera to the subscribers.
+    fn on_camera_changed(&mut self) {
+        if let Some(ref source) = self.source {
+            self.capabilities = camera::capabilities(source, &self.camera, &self.stream_config);
+        }
+        if self.zoomed.is_empty() {
+            self.selected_resolution = self.stream_config.resolution;
+        }
+        // Clients see the resolution they selected, frames are scaled to it.
+        self.capabilities.resolution = self.selected_resolution;
+        let resolution = self.stream_config.resolution;
+        if let Some(status) = update_recording(&mut self.recorder, |r| r.set_resolution(resolution))
+        {
+            self.publish(msg::Video::Recording(status));
+        }
+        self.publish(msg::Video::Capabilities(self.capabilities.clone()));
+    }
+
+    fn capture(&mut self) {
+        let result = match self.source {
+            Some(ref mut source) => source.capture(),
+            None => return,
+        };
+        match result {
+            Ok(frame) => self.on_frame(frame),
+            Err(e) => self.on_capture_error(e),
+        }
+    }
+
+    /// Record a captured frame and pass it to the subscribers.
+    fn on_frame(&mut self, frame: camera::Frame) {
+        self.failures = 0;
+        let timestamp_ms = chrono::Utc::now().timestamp_millis();
+        // Every captured frame is recorded regardless of the client link.
+        if let Some(status) =
+            update_recording(&mut self.recorder, |r| r.write(&frame, timestamp_ms))
+        {
+            self.publish(msg::Video::Recording(status));
+        }
+        self.context.timelapse.offer(
+            &self.camera.name,
+            &frame,
+            self.stream_config.resolution,
+            self.orientation,
+        );
+        self.detect_motion(&frame);
+        if self
+            .sentry_recording_until
+            .map_or(false, |t| time::Instant::now() >= t)
+        {
+            self.sentry_recording_until = None;
+            if let Some(recorder) = self.recorder.take() {
+                info!("Motion is over, stopping the sentry recording");
+                let status = match recorder.stop() {
+                    Ok(_) => Ok(None),
+                    Err(e) => Err(format!("Failed to finish recording: {}", e)),
+                };
+                self.publish(msg::Video::Recording(status));
+            }
+        }
+
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let data = sync::Arc::new(frame);
+        let resolution = self.stream_config.resolution;
+        let selected_resolution = self.selected_resolution;
+        // A busy subscriber gets the next frame instead.
+        self.subscribers.retain(|subscriber| {
+            let frame = Frame {
+                data: data.clone(),
+                resolution: resolution,
+                selected_resolution: selected_resolution,
+                timestamp_ms: timestamp_ms,
+            };
+            !matches!(
+                subscriber.frames.try_send(frame),
+                Err(mpsc::TrySendError::Disconnected(_))
+            )
+        });
+    }
+
+    /// Pass a frame to the motion detection and take the sentry actions on motion.
+    fn detect_motion(&mut self, frame: &[u8]) {
+        let (detection, result) = match &mut self.motion {
+            Some((detection, worker)) => {
+                worker.offer(frame);
+                match worker.result() {
+                    Some(result) => (*detection, result),
+                    None => return,
+                }
+            }
+            None => return,
+        };
+        // Changes below the threshold aren't motion.
+        if result.score < detection.min_score {
+            return;
+        }
+        if let Some(actions) = detection.sentry {
+            let now = time::Instant::now();
+            let cooldown = time::Duration::from_millis(SENTRY_COOLDOWN_MS);
+            let state = self.context.failsafe.last_state();
+            if state == state.halted()
+                && !self.context.failsafe.is_autonomous()
+                && self
+                    .last_sentry_trigger
+                    .map_or(true, |t| t.elapsed() >= cooldown)
+            {
+                self.last_sentry_trigger = Some(now);
+                let started = self.recorder.is_none();
+                if let Some(status) = self.trigger_sentry(actions, frame) {
+                    self.publish(msg::Video::Recording(status));
+                }
+                if started && self.recorder.is_some() {
+                    self.sentry_recording_until = Some(now);
+                }
+            }
+            // Every motion prolongs a sentry recording.
+            if self.sentry_recording_until.is_some() {
+                self.sentry_recording_until =
+                    Some(now + time::Duration::from_secs(SENTRY_RECORDING_SECS));
+            }
+        }
+        for subscriber in &self.subscribers {
+            let _ = subscriber.events.send(Event::Motion(result.clone()));
+        }
+    }
+
+    /// Take the sentry actions when motion appears while the machine is parked.
+    ///
+    /// Returns the recording status for the client when a recording is started.
+    fn trigger_sentry(
+        &mut self,
+        actions: types::SentryActions,
+        frame: &[u8],
+    ) -> Option<Result<Option<String>, String>> {
+        warn!("Sentry: motion on camera {:?}", self.camera.name);
+        self.context
+            .journal
+            .record(msg::JournalEvent::Session(format!(
+                "Sentry: motion on camera {}",
+                self.camera.name
+            )));
+
+        if actions.snapshot {
+            let snapshot = self.make_snapshot(frame.to_vec(), self.stream_config.resolution);
+            match snapshot::save(&self.context.snapshot_dir, &snapshot) {
+                Ok(path) => info!("Saved sentry snapshot to {:?}", path),
+                Err(e) => error!("Failed to save sentry snapshot: {}", e),
+            }
+        }
+        if actions.lamp {
+            self.context.failsafe.set_lamp(true);
+        }
+        if actions.record && self.recorder.is_none() {
+            return Some(self.start_recording());
+        }
+        None
+    }
+
+    /// Back off after a failed capture and reopen the camera when it keeps failing.
+    fn on_capture_error(&mut self, error: io::Error) {
+        error!("Unable to take picture: {:?}", error);
+        // Frames are paced by the blocking capture, so back off on errors.
+        thread::sleep(time::Duration::from_millis(10));
+        self.failures += 1;
+        if self.failures >= CAPTURE_FAILURES_BEFORE_RECOVERY {
+            self.start_recovery(format!("{}", error));
+        }
+    }
+
+    /// Release the camera and reopen it with an exponential backoff.
+    fn start_recovery(&mut self, reason: String) {
+        self.source = None;
+        self.failures = 0;
+        let recovery = Recovery {
+            attempt: 1,
+            backoff_ms: RECOVERY_MIN_BACKOFF_MS,
+            at: time::Instant::now() + time::Duration::from_millis(RECOVERY_MIN_BACKOFF_MS),
+            reason: reason,
+        };
+        self.report_recovery(&recovery);
+        self.recovery = Some(recovery);
+    }
+
+    /// Reopen the camera once the backoff is over.
+    fn recover(&mut self) {
+        let mut recovery = match self.recovery.take() {
+            Some(res) => res,
+            None => return,
+        };
+        let now = time::Instant::now();
+        if now < recovery.at {
+            // Commands are handled in between.
+            let poll = time::Duration::from_millis(RECOVERY_POLL_MS);
+            thread::sleep((recovery.at - now).min(poll));
+            self.recovery = Some(recovery);
+            return;
+        }
+
+        match reopen_camera(&self.camera, &mut self.stream_config) {
+            Ok(source) => {
+                info!("Camera {:?} is reconnected", self.camera.name);
+                self.source = Some(source);
+                self.publish(msg::Video::Status(None));
+                self.on_camera_changed();
+            }
+            Err(e) => {
+                recovery.reason = format!("{}", e);
+                recovery.attempt += 1;
+                recovery.backoff_ms = (recovery.backoff_ms * 2).min(RECOVERY_MAX_BACKOFF_MS);
+                recovery.at = now + time::Duration::from_millis(recovery.backoff_ms);
+                self.report_recovery(&recovery);
+                self.recovery = Some(recovery);
+            }
+        }
+    }
+
+    fn report_recovery(&mut self, recovery: &Recovery) {
+        let status = self.status(recovery);
+        warn!("{}", status);
+        self.publish(msg::Video::Status(Some(status)));
+    }
+
+    fn status(&self, recovery: &Recovery) -> String {
+        format!(
+            "Camera {} reconnecting (attempt {}): {}",
+            self.camera.name, recovery.attempt, recovery.reason
+        )
+    }
+}
+
+/// Apply a runtime camera command to an open stream.
+///
+/// Resolution and framerate changes are checked against the camera allowlist and
+/// reopen the source. The previous stream is restored when the new one fails to start.
+/// The source is left closed when the previous stream fails to start as well.
+fn apply_camera_command(
+    source: &mut Option<Box<dyn camera::FrameSource>>,
+    camera: &camera::CameraConfig,
+    stream_config: &mut camera::StreamConfig,
+    capabilities: &types::CameraCapabilities,
+    command: types::CameraCommand,
+) -> Result<(), Error> {
+    let mut request = settings::Video {
+        camera: camera.name.clone(),
+        cameras: vec![],
+        resolution: stream_config.resolution,
+        max_framerate: stream_config.framerate,
+        max_latency_ms: 0,
+        profile: None,
+    };
+    let current = match source {
+        Some(res) => res,
+        None => return Err(Error::new("The camera is reconnecting")),
+    };
+    match command {
+        types::CameraCommand::SetControl(id, value) => return current.set_control(id, value),
+        types::CameraCommand::SetResolution(resolution) => {
+            if !capabilities.resolutions.contains(&resolution) {
+                return Err(Error::new(format!(
+                    "Resolution {}x{} is not supported",
+                    resolution.0, resolution.1
+                )));
+            }
+            request.resolution = resolution;
+        }
+        types::CameraCommand::SetFramerate(framerate) => request.max_framerate = framerate.max(1),
+        types::CameraCommand::SetRegion(_)
+        | types::CameraCommand::Snapshot
+        | types::CameraCommand::StartRecording
+        | types::CameraCommand::StopRecording
+        | types::CameraCommand::SetMotionDetection(_)
+        | types::CameraCommand::SetBlobTracking(_)
+        | types::CameraCommand::SetLineFollowing(_)
+        | types::CameraCommand::SetMarkerDetection(_) => {
+            return Err(Error::new("The command is handled by the stream"))
+        }
+    }
+
+    let new_config = camera::negotiate(&vec![camera.clone()], &request)?;
+    info!(
+        "Restarting camera {:?} at {}x{} {} fps...",
+        camera.name, new_config.resolution.0, new_config.resolution.1, new_config.framerate
+    );
+    // The device must be released before it can be opened with new parameters.
+    *source = None;
+    match camera::open(&new_config) {
+        Ok(mut res) => {
+            camera::apply_orientation(&mut res, camera.orientation);
+            *source = Some(res);
+            *stream_config = new_config;
+            Ok(())
+        }
+        Err(e) => {
+            let mut res = camera::open(stream_config)?;
+            camera::apply_orientation(&mut res, camera.orientation);
+            *source = Some(res);
+            Err(e)
+        }
+    }
+}
+
+/// Run an operation on the recording, stopping the recording when it fails.
+///
+/// Returns the recording status to report to the client when it changes.
+fn update_recording<F>(
+    recorder: &mut Option<recorder::Recorder>,
+    operation: F,
+) -> Option<Result<Option<String>, String>>
+where
+    F: FnOnce(&mut recorder::Recorder) -> Result<Option<String>, io::Error>,
+{
+    let result = match recorder {
+        Some(ref mut res) => operation(res),
+        None => return None,
+    };
+    match result {
+        Ok(None) => None,
+        Ok(Some(clip)) => Some(Ok(Some(clip))),
+        Err(e) => {
+            error!("Recording is stopped due to an error: {}", e);
+            *recorder = None;
+            Some(Err(format!("Recording is stopped due to an error: {}", e)))
+        }
+    }
+}
+
+/// Capture a frame at the largest resolution allowed for the camera.
+///
+/// When the stream runs at a lower resolution, the camera is reopened for the snapshot
+/// and the stream is restored afterwards. The source is left closed when the stream
+/// fails to restart.
+fn take_snapshot(
+    source: &mut Option<Box<dyn camera::FrameSource>>,
+    camera: &camera::CameraConfig,
+    stream_config: &camera::StreamConfig,
+    capabilities: &types::CameraCapabilities,
+) -> Result<(Vec<u8>, (u32, u32)), Error> {
+    let resolution = capabilities
+        .resolutions
+        .iter()
+        .max_by_key(|(width, height)| width * height)
+        .cloned()
+        .unwrap_or(stream_config.resolution);
+    let current = match source {
+        Some(res) => res,
+        None => return Err(Error::new("The camera is reconnecting")),
+    };
+    if resolution == stream_config.resolution {
+        return match current.capture() {
+            Ok(frame) => Ok((frame.to_vec(), resolution)),
+            Err(e) => Err(Error::new(format!("Unable to take picture: {}", e))),
+        };
+    }
+
+    info!(
+        "Reopening camera {:?} at {}x{} for a snapshot...",
+        camera.name, resolution.0, resolution.1
+    );
+    let snapshot_config = camera::StreamConfig {
+        resolution: resolution,
+        ..stream_config.clone()
+    };
+    // The device must be released before it can be opened with new parameters.
+    *source = None;
+    let data = camera::open(&snapshot_config).and_then(|mut snapshot_source| {
+        camera::apply_orientation(&mut snapshot_source, camera.orientation);
+        let mut frame = snapshot_source.capture();
+        for _ in 1..SNAPSHOT_WARMUP_FRAMES {
+            frame = snapshot_source.capture();
+        }
+        match frame {
+            Ok(frame) => Ok(frame.to_vec()),
+            Err(e) => Err(Error::new(format!("Unable to take picture: {}", e))),
+        }
+    });
+    let mut restored = camera::open(stream_config)?;
+    camera::apply_orientation(&mut restored, camera.orientation);
+    *source = Some(restored);
+    Ok((data?, resolution))
+}
+
+/// Open the camera again, falling back to another allowed resolution when the device
+/// doesn't support the current one anymore.
+fn reopen_camera(
+    camera: &camera::CameraConfig,
+    stream_config: &mut camera::StreamConfig,
+) -> Result<Box<dyn camera::FrameSource>, Error> {
+    let mut source = camera::open(stream_config)?;
+    if !source.supports_resolution(stream_config.resolution) {
+        let resolution = match camera
+            .resolutions
+            .iter()
+            .find(|res| source.supports_resolution(**res))
+        {
+            Some(res) => *res,
+            None => {
+                return Err(Error::new(
+                    "The device supports none of the allowed resolutions",
+                ))
+            }
+        };
+        warn!(
+            "Camera {:?} falls back to {}x{}",
+            camera.name, resolution.0, resolution.1
+        );
+        drop(source);
+        stream_config.resolution = resolution;
+        source = camera::open(stream_config)?;
+    }
+    camera::apply_orientation(&mut source, camera.orientation);
+    Ok(source)
+}
+
+fn lock(
+    feeds: &sync::Arc<sync::Mutex<HashMap<String, mpsc::Sender<Command>>>>,
+) -> sync::MutexGuard<'_, HashMap<String, mpsc::Sender<Command>>> {
+    feeds.lock().expect("Failed to lock camera feeds")
+}
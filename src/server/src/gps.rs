@@ -0,0 +1,251 @@
+use chrono;
+use simple_error::SimpleError as Error;
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::sync;
+use std::thread;
+use std::time;
+
+use crate::common::types::GpsFix;
+
+/// NMEA 0183 GPS receiver connected to a serial port.
+pub struct Gps {
+    fix: sync::Arc<sync::Mutex<Option<GpsFix>>>,
+}
+
+impl Gps {
+    /// Start reading NMEA sentences from the serial `device` in a background thread.
+    ///
+    /// The port must be configured beforehand (e.g. `stty -F /dev/ttyS0 9600 raw`).
+    /// A row per epoch with a fix is appended to the `track` CSV file when it is set.
+    pub fn start(device: String, track: Option<String>) -> Self {
+        let fix = sync::Arc::new(sync::Mutex::new(None));
+        let reader_fix = fix.clone();
+
+        thread::spawn(move || {
+            let mut track_file = match track {
+                Some(path) => match fs::OpenOptions::new().create(true).append(true).open(&path) {
+                    Ok(file) => Some(file),
+                    Err(e) => {
+                        error!("Failed to open GPS track file {}: {}", path, e);
+                        None
+                    }
+                },
+                None => None,
+            };
+
+            loop {
+                match Gps::read(&device, &reader_fix, &mut track_file) {
+                    Ok(_) => warn!("GPS device {} is closed. Reopening...", device),
+                    Err(e) => error!("Failed to read GPS device {}: {}", device, e),
+                }
+                *reader_fix.lock().expect("Failed to lock GPS fix") = None;
+                thread::sleep(time::Duration::from_secs(1));
+            }
+        });
+
+        Gps { fix: fix }
+    }
+
+    /// Last known position or `None` when the receiver is not available.
+    pub fn fix(&self) -> Option<GpsFix> {
+        *self.fix.lock().expect("Failed to lock GPS fix")
+    }
+
+    fn read(
+        device: &String,
+        fix: &sync::Arc<sync::Mutex<Option<GpsFix>>>,
+        track_file: &mut Option<fs::File>,
+    ) -> Result<(), io::Error> {
+        let reader = io::BufReader::new(fs::File::open(device)?);
+        info!("Reading GPS sentences from {}...", device);
+
+        // UTC time of the epoch the last sentences belong to.
+        let mut epoch: Option<String> = None;
+        for line in reader.lines() {
+            let line = line?;
+            let previous = fix
+                .lock()
+                .expect("Failed to lock GPS fix")
+                .unwrap_or_default();
+            let mut current = previous;
+            match parse_sentence(line.trim(), &mut current) {
+                Ok(Some(time)) => {
+                    // GGA and RMC sentences of an epoch complete each other, so the
+                    // track row is written once the next epoch starts.
+                    if epoch.as_ref().map_or(false, |e| *e != time) {
+                        if let Some(file) = track_file {
+                            write_track(file, &previous)?;
+                        }
+                    }
+                    epoch = Some(time);
+                    *fix.lock().expect("Failed to lock GPS fix") = Some(current);
+                }
+                Ok(None) => {}
+                Err(e) => debug!("Skipping NMEA sentence {:?}: {}", line, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_track(file: &mut fs::File, fix: &GpsFix) -> Result<(), io::Error> {
+    if !fix.has_fix {
+        return Ok(());
+    }
+    writeln!(
+        file,
+        "{},{:.7},{:.7},{:.1},{:.2},{}",
+        chrono::Utc::now().to_rfc3339(),
+        fix.latitude,
+        fix.longitude,
+        fix.altitude,
+        fix.speed_kmh,
+        fix.satellites,
+    )
+}
+
+/// Update `fix` from a single NMEA sentence.
+///
+/// Returns the UTC time of the sentence when it was recognized. Only GGA and RMC
+/// sentences are used.
+pub fn parse_sentence(sentence: &str, fix: &mut GpsFix) -> Result<Option<String>, Error> {
+    if !sentence.starts_with('$') {
+        return Err(Error::new("Missing start delimiter"));
+    }
+    let body = match sentence[1..].find('*') {
+        Some(pos) => {
+            let (body, checksum) = sentence[1..].split_at(pos);
+            let expected = match u8::from_str_radix(&checksum[1..], 16) {
+                Ok(res) => res,
+                Err(_) => return Err(Error::new("Invalid checksum")),
+            };
+            if body.bytes().fold(0, |acc, b| acc ^ b) != expected {
+                return Err(Error::new("Checksum mismatch"));
+            }
+            body
+        }
+        None => &sentence[1..],
+    };
+
+    let fields: Vec<&str> = body.split(',').collect();
+    let sentence_type = match fields[0].get(2..) {
+        Some(res) if res.len() == 3 => res,
+        _ => return Err(Error::new("Invalid sentence type")),
+    };
+    match sentence_type {
+        "GGA" => {
+            if fields.len() < 10 {
+                return Err(Error::new("Too few GGA fields"));
+            }
+            let quality = parse_field::<u8>(fields[6])?.unwrap_or(0);
+            fix.has_fix = quality > 0;
+            fix.satellites = parse_field::<u8>(fields[7])?.unwrap_or(0);
+            if fix.has_fix {
+                fix.latitude = parse_coordinate(fields[2], fields[3])?;
+                fix.longitude = parse_coordinate(fields[4], fields[5])?;
+                fix.altitude = parse_field::<f32>(fields[9])?.unwrap_or(0.0);
+            }
+            Ok(Some(fields[1].to_string()))
+        }
+        "RMC" => {
+            if fields.len() < 8 {
+                return Err(Error::new("Too few RMC fields"));
+            }
+            fix.has_fix = fields[2] == "A";
+            if fix.has_fix {
+                fix.latitude = parse_coordinate(fields[3], fields[4])?;
+                fix.longitude = parse_coordinate(fields[5], fields[6])?;
+                // Speed over ground is reported in knots.
+                fix.speed_kmh = parse_field::<f32>(fields[7])?.unwrap_or(0.0) * 1.852;
+            }
+            Ok(Some(fields[1].to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(value: &str) -> Result<Option<T>, Error> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.parse::<T>() {
+        Ok(res) => Ok(Some(res)),
+        Err(_) => Err(Error::new(format!("Invalid field value {:?}", value))),
+    }
+}
+
+/// Convert a `(d)ddmm.mmmm` coordinate with a hemisphere into signed decimal degrees.
+fn parse_coordinate(value: &str, hemisphere: &str) -> Result<f64, Error> {
+    let raw = match parse_field::<f64>(value)? {
+        Some(res) => res,
+        None => return Err(Error::new("Missing coordinate")),
+    };
+    let degrees = (raw / 100.0).trunc();
+    let decimal = degrees + (raw - degrees * 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Ok(decimal),
+        "S" | "W" => Ok(-decimal),
+        _ => Err(Error::new(format!("Invalid hemisphere {:?}", hemisphere))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+    const RMC: &str = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+
+    #[test]
+    fn parses_gga() {
+        let mut fix = GpsFix::default();
+        assert_eq!(
+            parse_sentence(GGA, &mut fix).unwrap(),
+            Some("123519".to_string())
+        );
+        assert!(fix.has_fix);
+        assert_eq!(fix.satellites, 8);
+        assert!((fix.latitude - 48.1173).abs() < 1e-6);
+        assert!((fix.longitude - 11.516_666_7).abs() < 1e-6);
+        assert!((fix.altitude - 545.4).abs() < 1e-3);
+    }
+
+    #[test]
+    fn parses_rmc() {
+        let mut fix = GpsFix::default();
+        assert_eq!(
+            parse_sentence(RMC, &mut fix).unwrap(),
+            Some("123519".to_string())
+        );
+        assert!(fix.has_fix);
+        assert!((fix.latitude - 48.1173).abs() < 1e-6);
+        assert!((fix.longitude - 11.516_666_7).abs() < 1e-6);
+        assert!((fix.speed_kmh - 22.4 * 1.852).abs() < 1e-3);
+    }
+
+    #[test]
+    fn keeps_position_without_fix() {
+        let mut fix = GpsFix::default();
+        parse_sentence(GGA, &mut fix).unwrap();
+        parse_sentence("$GPGGA,123520,,,,,0,00,99.99,,,,,,*4F", &mut fix).unwrap();
+        assert!(!fix.has_fix);
+        assert_eq!(fix.satellites, 0);
+        assert!((fix.latitude - 48.1173).abs() < 1e-6);
+    }
+
+    #[test]
+    fn skips_other_sentences() {
+        let mut fix = GpsFix::default();
+        assert_eq!(parse_sentence("$GPGSV,1,1,00*79", &mut fix).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_sentences() {
+        let mut fix = GpsFix::default();
+        assert!(parse_sentence(&GGA.replace("*47", "*48"), &mut fix).is_err());
+        assert!(parse_sentence(&GGA[1..], &mut fix).is_err());
+        assert!(parse_sentence("$GPGGA,123519*77", &mut fix).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use chrono;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync;
+
+use crate::common::conn::MessageStream;
+use crate::common::messages as msg;
+
+/// Rotating black-box journal of commands, outputs, session events and telemetry.
+///
+/// Records are written in the same length-prefixed frames as network messages
+/// into `journal-<timestamp>.bin` files.
+pub struct Journal {
+    dir: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+    file: sync::Mutex<Option<(fs::File, u64)>>,
+}
+
+impl Journal {
+    pub fn new(dir: String, max_file_size: u64, max_files: usize) -> Result<Self, io::Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(Journal {
+            dir: PathBuf::from(dir),
+            max_file_size: max_file_size,
+            max_files: max_files.max(1),
+            file: sync::Mutex::new(None),
+        })
+    }
+
+    pub fn record(&self, event: msg::JournalEvent) {
+        let record = msg::JournalRecord {
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            event: event,
+        };
+
+        let mut file = self.file.lock().expect("Failed to lock journal");
+        let rotate = match &*file {
+            Some((_, size)) => *size >= self.max_file_size,
+            None => true,
+        };
+        if rotate {
+            *file = match self.rotate(record.timestamp_ms) {
+                Ok(res) => Some((res, 0)),
+                Err(e) => {
+                    error!("Failed to rotate journal: {}", e);
+                    return;
+                }
+            };
+        }
+
+        if let Some((journal_file, size)) = &mut *file {
+            match journal_file.write_msg(&record) {
+                Ok(written) => *size += written as u64,
+                Err(e) => error!("Failed to write journal record: {}", e),
+            }
+        }
+    }
+
+    /// Read all records starting from `since_ms` timestamp.
+    pub fn read_since(&self, since_ms: i64) -> Result<Vec<msg::JournalRecord>, io::Error> {
+        // Sizes are taken under the lock to not read a partially written record,
+        // the files are read without it to not block recording.
+        let file = self.file.lock().expect("Failed to lock journal");
+        let paths = self.files()?;
+        let mut files = vec![];
+        for (index, path) in paths.iter().enumerate() {
+            // A file ends where the next one starts, so older files are skipped.
+            let ended_before = paths
+                .get(index + 1)
+                .and_then(|next| started_ms(next))
+                .is_some_and(|next| next <= since_ms);
+            if ended_before {
+                continue;
+            }
+            let size = fs::metadata(path)?.len();
+            files.push((path.clone(), size));
+        }
+        drop(file);
+
+        let mut records = vec![];
+        for (path, size) in files {
+            let file = match fs::File::open(&path) {
+                Ok(res) => res,
+                // The file has been rotated out in the meantime.
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mut data = vec![];
+            file.take(size).read_to_end(&mut data)?;
+            let mut data = io::Cursor::new(data);
+            // Reading stops at the end of file or at a truncated record.
+            while let Ok(record) = data.read_msg::<msg::JournalRecord>(&mut vec![]) {
+                if record.timestamp_ms >= since_ms {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    fn rotate(&self, timestamp_ms: i64) -> Result<fs::File, io::Error> {
+        let mut files = self.files()?;
+        while files.len() >= self.max_files {
+            let oldest = files.remove(0);
+            debug!("Removing journal file {:?}...", oldest);
+            fs::remove_file(oldest)?;
+        }
+
+        let path = self.dir.join(format!("journal-{:013}.bin", timestamp_ms));
+        info!("Opening journal file {:?}...", path);
+        fs::OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Journal files sorted from the oldest to the newest.
+    fn files(&self) -> Result<Vec<PathBuf>, io::Error> {
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if name.starts_with("journal-") && name.ends_with(".bin") => {
+                    files.push(path)
+                }
+                _ => {}
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// Timestamp of the first record of a journal file, from its name.
+fn started_ms(path: &Path) -> Option<i64> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.strip_prefix("journal-"))
+        .and_then(|timestamp| timestamp.parse().ok())
+}
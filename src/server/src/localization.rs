@@ -0,0 +1,69 @@
+use chrono;
+use serde::Deserialize;
+use std::sync;
+
+use crate::common::types::{Marker, MarkerFix};
+
+/// Marker with a known position, e.g. a QR code on a wall.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MarkerConfig {
+    /// Content of the marker.
+    pub payload: String,
+    /// Position in metres of a local map.
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Keeps the position of the machine known from the markers it sees.
+///
+/// The machine has no odometry, so the position is the one of the last seen marker
+/// with a configured position and isn't tracked between markers.
+pub struct Localization {
+    markers: Vec<MarkerConfig>,
+    fix: sync::Mutex<Option<MarkerFix>>,
+}
+
+impl Localization {
+    pub fn new(markers: Vec<MarkerConfig>) -> Self {
+        Localization {
+            markers: markers,
+            fix: sync::Mutex::new(None),
+        }
+    }
+
+    /// Update the position from markers found in a frame. The largest, so the closest,
+    /// configured marker is used.
+    ///
+    /// Returns the new position if any of the markers is configured.
+    pub fn update(&self, found: &[Marker]) -> Option<MarkerFix> {
+        let (marker, config) = found
+            .iter()
+            .filter_map(|marker| {
+                self.markers
+                    .iter()
+                    .find(|config| config.payload == marker.payload)
+                    .map(|config| (marker, config))
+            })
+            .max_by(|(a, _), (b, _)| {
+                a.region
+                    .height
+                    .partial_cmp(&b.region.height)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })?;
+
+        let fix = MarkerFix {
+            payload: marker.payload.clone(),
+            x: config.x,
+            y: config.y,
+            size: marker.region.height,
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+        };
+        *self.fix.lock().expect("Failed to lock marker fix") = Some(fix.clone());
+        Some(fix)
+    }
+
+    /// Last known position.
+    pub fn fix(&self) -> Option<MarkerFix> {
+        self.fix.lock().expect("Failed to lock marker fix").clone()
+    }
+}
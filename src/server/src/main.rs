@@ -1,5 +1,23 @@
+pub mod analysis;
+pub mod annotations;
+pub mod autopilot;
+pub mod avi;
+pub mod camera;
 pub mod conn;
+pub mod detect;
+pub mod failsafe;
+pub mod feed;
+pub mod gps;
+pub mod journal;
+pub mod localization;
 pub mod machine;
+pub mod mission;
+pub mod mount;
+pub mod quality;
+pub mod recorder;
+pub mod scripts;
+pub mod snapshot;
+pub mod timelapse;
 pub mod utils;
 
 #[macro_use]
@@ -7,9 +25,11 @@ extern crate log;
 extern crate bincode;
 extern crate chrono;
 extern crate common;
+extern crate config;
 extern crate log4rs;
 extern crate log_panics;
 extern crate rand;
+extern crate serde;
 extern crate signal_hook;
 extern crate simple_error;
 extern crate sysfs_gpio;
@@ -18,6 +38,9 @@ use std::sync;
 use std::thread;
 use std::time;
 
+/// How often telemetry is written to the journal in milliseconds.
+const JOURNAL_TELEMETRY_INTERVAL: u64 = 1000;
+
 fn main() {
     println!("Starting...");
     let signals = signal_hook::iterator::Signals::new(&[signal_hook::SIGINT, signal_hook::SIGTERM])
@@ -41,13 +64,69 @@ fn main() {
         }
     };
 
+    info!("Initializing journal in {}...", config.journal_dir);
+    let journal = match journal::Journal::new(
+        config.journal_dir.clone(),
+        utils::JOURNAL_FILE_SIZE,
+        utils::JOURNAL_FILES,
+    ) {
+        Ok(res) => sync::Arc::new(res),
+        Err(e) => {
+            error!("Unable to initialize journal: {}", e);
+            error!("Exiting...");
+            std::process::exit(4);
+        }
+    };
+    journal.record(common::messages::JournalEvent::Session(
+        "Server started".to_string(),
+    ));
+
     info!("Initializing GPIO...");
     let mut machine = machine::Machine::new();
     machine.export();
     let machine_mutex = sync::Arc::new(sync::Mutex::new(machine));
 
+    let gps = match config.gps_device.clone() {
+        Some(device) => {
+            info!("Initializing GPS on {}...", device);
+            Some(sync::Arc::new(gps::Gps::start(device, config.gps_track.clone())))
+        }
+        None => None,
+    };
+
+    let mount = match config.mount.clone() {
+        Some(mount_config) => {
+            info!("Initializing camera mount on {}...", mount_config.pwm_chip);
+            match mount::Mount::new(mount_config) {
+                Ok(res) => Some(sync::Arc::new(res)),
+                Err(e) => {
+                    error!("Unable to initialize camera mount: {}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    let timelapse = sync::Arc::new(timelapse::Timelapse::new(
+        config.timelapse_dir.clone(),
+        config.cameras.clone(),
+    ));
+
+    let localization = sync::Arc::new(localization::Localization::new(config.markers.clone()));
+
     info!("Initializing session pool on {} port...", config.port);
-    let mut session_pool = conn::SessionPool::new(config, machine_mutex.clone());
+    let mut session_pool = conn::SessionPool::new(
+        config,
+        machine_mutex.clone(),
+        gps.clone(),
+        mount.clone(),
+        timelapse.clone(),
+        localization.clone(),
+        journal.clone(),
+    );
+    let missions = session_pool.missions();
+    let scripts = session_pool.scripts();
 
     thread::spawn(move || {
         match session_pool.listen() {
@@ -64,11 +143,33 @@ fn main() {
     });
 
     info!("Starting event loop...");
+    let mut last_telemetry = time::Instant::now();
     loop {
+        if last_telemetry.elapsed() >= time::Duration::from_millis(JOURNAL_TELEMETRY_INTERVAL) {
+            journal.record(common::messages::JournalEvent::Telemetry(
+                conn::collect_telemetry(
+                    &gps,
+                    &mount,
+                    &timelapse,
+                    &localization,
+                    &missions,
+                    &scripts,
+                ),
+            ));
+            last_telemetry = time::Instant::now();
+        }
         for sig in signals.pending() {
             info!("Received signal {:?}, exiting...", sig);
+            journal.record(common::messages::JournalEvent::Session(format!(
+                "Server stopped by signal {}",
+                sig
+            )));
             let mut machine = machine_mutex.try_lock().expect("Failed to lock GPIO");
             machine.unexport();
+            if let Some(mount) = &mount {
+                mount.disable();
+            }
+            timelapse.stop_all();
             std::process::exit(sig);
         }
         thread::sleep(time::Duration::from_millis(200));
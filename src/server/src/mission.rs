@@ -0,0 +1,300 @@
+use simple_error::SimpleError as Error;
+use std::collections::HashMap;
+use std::sync;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::common::types::{
+    CameraCommand, MachineState, Mission, MissionProgress, MissionStatus, MissionStep,
+};
+use crate::failsafe::Failsafe;
+
+/// Period of the software PWM which makes speeds below the full one.
+const PULSE_PERIOD_MS: u64 = 100;
+/// How often a running step checks whether it's paused or aborted.
+const TICK_MS: u64 = 10;
+
+/// Command channels of the cameras streamed to a client.
+pub type CameraCommands = sync::Arc<sync::Mutex<HashMap<String, mpsc::Sender<CameraCommand>>>>;
+
+struct State {
+    progress: Option<MissionProgress>,
+    paused: bool,
+    aborted: bool,
+}
+
+/// Executes missions uploaded by clients, one at a time.
+///
+/// A mission drives the machine as an autonomous mode, so it's aborted by manual input
+/// and by a heartbeat loss like any other mode.
+pub struct Missions {
+    failsafe: sync::Arc<Failsafe>,
+    /// Degrees per second the machine turns in place.
+    turn_rate: f32,
+    state: sync::Arc<sync::Mutex<State>>,
+}
+
+impl Missions {
+    pub fn new(failsafe: sync::Arc<Failsafe>, turn_rate: f32) -> Self {
+        Missions {
+            failsafe: failsafe,
+            turn_rate: turn_rate,
+            state: sync::Arc::new(sync::Mutex::new(State {
+                progress: None,
+                paused: false,
+                aborted: false,
+            })),
+        }
+    }
+
+    /// Start a mission in a background thread. Snapshots are taken by the streams
+    /// of `cameras`.
+    pub fn start(&self, mission: Mission, cameras: CameraCommands) -> Result<(), Error> {
+        let mut state = lock(&self.state);
+        if let Some(progress) = state.progress.as_ref().filter(|p| p.is_active()) {
+            return Err(Error::new(format!(
+                "Mission {:?} is already running",
+                progress.name
+            )));
+        }
+        if mission.steps.is_empty() {
+            return Err(Error::new(format!(
+                "Mission {:?} has no steps",
+                mission.name
+            )));
+        }
+        if self.failsafe.is_autonomous() {
+            return Err(Error::new("Another autonomous mode is driving the machine"));
+        }
+        state.progress = Some(MissionProgress {
+            name: mission.name.clone(),
+            step: 0,
+            steps: mission.steps.len(),
+            status: MissionStatus::Running,
+        });
+        state.paused = false;
+        state.aborted = false;
+        drop(state);
+
+        info!(
+            "Starting mission {:?} of {} steps",
+            mission.name,
+            mission.steps.len()
+        );
+        let runner = Runner {
+            autonomy: self.failsafe.start_autonomy(),
+            failsafe: self.failsafe.clone(),
+            turn_rate: self.turn_rate,
+            state: self.state.clone(),
+            cameras: cameras,
+        };
+        thread::spawn(move || runner.run(mission));
+        Ok(())
+    }
+
+    /// Stop the engines and the mission clock until `resume`.
+    pub fn pause(&self) -> Result<(), Error> {
+        self.set_paused(true)
+    }
+
+    pub fn resume(&self) -> Result<(), Error> {
+        self.set_paused(false)
+    }
+
+    /// Stop the running mission and the engines.
+    pub fn abort(&self) -> Result<(), Error> {
+        let mut state = lock(&self.state);
+        match state.progress.as_ref().filter(|p| p.is_active()) {
+            Some(_) => {
+                state.aborted = true;
+                Ok(())
+            }
+            None => Err(Error::new("No mission is running")),
+        }
+    }
+
+    /// Progress of the last started mission.
+    pub fn progress(&self) -> Option<MissionProgress> {
+        lock(&self.state).progress.clone()
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<(), Error> {
+        let mut guard = lock(&self.state);
+        let state = &mut *guard;
+        match state.progress.as_mut().filter(|p| p.is_active()) {
+            Some(progress) => {
+                progress.status = if paused {
+                    MissionStatus::Paused
+                } else {
+                    MissionStatus::Running
+                };
+                state.paused = paused;
+                Ok(())
+            }
+            None => Err(Error::new("No mission is running")),
+        }
+    }
+}
+
+/// Executes the steps of a mission.
+struct Runner {
+    /// Token the mission drives with.
+    autonomy: usize,
+    failsafe: sync::Arc<Failsafe>,
+    turn_rate: f32,
+    state: sync::Arc<sync::Mutex<State>>,
+    cameras: CameraCommands,
+}
+
+impl Runner {
+    fn run(self, mission: Mission) {
+        let mut result = Ok(());
+        for (i, step) in mission.steps.iter().enumerate() {
+            if let Some(progress) = lock(&self.state).progress.as_mut() {
+                progress.step = i;
+            }
+            debug!("Mission {:?} step {}: {:?}", mission.name, i + 1, step);
+            result = self.execute(step);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.failsafe.stop_autonomy(self.autonomy);
+
+        let mut state = lock(&self.state);
+        if let Some(progress) = state.progress.as_mut() {
+            match result {
+                Ok(_) => {
+                    info!("Mission {:?} is finished", mission.name);
+                    progress.step = progress.steps;
+                    progress.status = MissionStatus::Finished;
+                }
+                Err(e) => {
+                    warn!("Mission {:?} is aborted: {}", mission.name, e);
+                    progress.status = MissionStatus::Aborted(e.as_str().to_string());
+                }
+            }
+        }
+    }
+
+    fn execute(&self, step: &MissionStep) -> Result<(), Error> {
+        match step {
+            MissionStep::Drive { speed, duration_ms } => {
+                let speed = speed.max(-1.0).min(1.0);
+                let movement = MachineState {
+                    forward: speed > 0.0,
+                    backward: speed < 0.0,
+                    ..MachineState::new()
+                };
+                self.hold(
+                    movement,
+                    speed.abs(),
+                    Duration::from_millis(*duration_ms as u64),
+                )
+            }
+            MissionStep::Turn { angle } => {
+                let movement = MachineState {
+                    left: *angle < 0.0,
+                    right: *angle > 0.0,
+                    ..MachineState::new()
+                };
+                let duration_ms = angle.abs() / self.turn_rate * 1000.0;
+                self.hold(movement, 1.0, Duration::from_millis(duration_ms as u64))
+            }
+            MissionStep::Lamp { enabled } => {
+                self.failsafe.set_autonomous_lamp(self.autonomy, *enabled);
+                self.apply(&MachineState::new())
+            }
+            MissionStep::Snapshot { camera } => {
+                // A still machine makes a sharp picture.
+                self.apply(&MachineState::new())?;
+                match lock_cameras(&self.cameras).get(camera) {
+                    Some(sender) => {
+                        let _ = sender.send(CameraCommand::Snapshot);
+                    }
+                    None => warn!("Camera {:?} is not streaming. Snapshot is skipped", camera),
+                }
+                Ok(())
+            }
+            MissionStep::Wait { duration_ms } => self.hold(
+                MachineState::new(),
+                0.0,
+                Duration::from_millis(*duration_ms as u64),
+            ),
+        }
+    }
+
+    /// Keep a movement for a duration of the mission clock, running the engines for
+    /// a share `duty` of each pulse period. Cancellation is checked on every tick.
+    fn hold(&self, movement: MachineState, duty: f32, duration: Duration) -> Result<(), Error> {
+        let tick = Duration::from_millis(TICK_MS);
+        let mut elapsed = Duration::from_millis(0);
+        let mut engines_on = None;
+        while elapsed < duration {
+            if self.wait_while_paused()? {
+                engines_on = None;
+            }
+            if !self.failsafe.owns_autonomy(self.autonomy) {
+                return Err(cancelled());
+            }
+            let phase = (elapsed.as_millis() as u64 % PULSE_PERIOD_MS) as f32;
+            let on = phase < duty * PULSE_PERIOD_MS as f32;
+            if engines_on != Some(on) {
+                self.apply(&if on { movement } else { movement.halted() })?;
+                engines_on = Some(on);
+            }
+            let step = tick.min(duration - elapsed);
+            thread::sleep(step);
+            elapsed += step;
+        }
+        Ok(())
+    }
+
+    /// Block while the mission is paused with the engines stopped.
+    ///
+    /// Returns whether it was paused, or an error when the mission is aborted.
+    fn wait_while_paused(&self) -> Result<bool, Error> {
+        let mut paused = false;
+        loop {
+            let state = lock(&self.state);
+            if state.aborted {
+                return Err(Error::new("Aborted by the client"));
+            }
+            if !state.paused {
+                return Ok(paused);
+            }
+            drop(state);
+            if !self.failsafe.owns_autonomy(self.autonomy) {
+                return Err(cancelled());
+            }
+            if !paused {
+                self.apply(&MachineState::new())?;
+                paused = true;
+            }
+            thread::sleep(Duration::from_millis(TICK_MS));
+        }
+    }
+
+    fn apply(&self, state: &MachineState) -> Result<(), Error> {
+        if self.failsafe.apply_autonomous(self.autonomy, state) {
+            Ok(())
+        } else {
+            Err(cancelled())
+        }
+    }
+}
+
+fn cancelled() -> Error {
+    Error::new("Cancelled by manual input or a heartbeat loss")
+}
+
+fn lock(state: &sync::Arc<sync::Mutex<State>>) -> sync::MutexGuard<'_, State> {
+    state.lock().expect("Failed to lock mission state")
+}
+
+fn lock_cameras(
+    cameras: &CameraCommands,
+) -> sync::MutexGuard<'_, HashMap<String, mpsc::Sender<CameraCommand>>> {
+    cameras.lock().expect("Failed to lock camera commands")
+}
@@ -0,0 +1,142 @@
+use serde::Deserialize;
+use simple_error::SimpleError as Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync;
+
+use crate::common::types::{MountCommand, MountPosition};
+
+/// Servo PWM period in nanoseconds (50 Hz).
+const SERVO_PERIOD_NS: u32 = 20_000_000;
+/// Pulse width at -90 degrees in nanoseconds.
+const SERVO_MIN_PULSE_NS: u32 = 500_000;
+/// Pulse width at +90 degrees in nanoseconds.
+const SERVO_MAX_PULSE_NS: u32 = 2_500_000;
+
+/// Pan/tilt camera mount settings.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MountConfig {
+    /// sysfs PWM chip, e.g. `/sys/class/pwm/pwmchip0`.
+    pub pwm_chip: String,
+    pub pan: ServoConfig,
+    pub tilt: ServoConfig,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ServoConfig {
+    /// PWM channel of the chip.
+    pub channel: u32,
+    /// Servo angle of the center position in degrees from -90 to 90.
+    pub center: f32,
+    /// Limits in degrees relative to the center.
+    pub min_angle: f32,
+    pub max_angle: f32,
+}
+
+/// Hobby servo driven by a sysfs PWM channel.
+struct Servo {
+    path: PathBuf,
+    config: ServoConfig,
+}
+
+impl Servo {
+    fn new(chip: &String, config: ServoConfig) -> Result<Self, io::Error> {
+        let chip = PathBuf::from(chip);
+        let path = chip.join(format!("pwm{}", config.channel));
+        if !path.exists() {
+            fs::write(chip.join("export"), format!("{}", config.channel))?;
+        }
+        fs::write(path.join("period"), format!("{}", SERVO_PERIOD_NS))?;
+        let servo = Servo {
+            path: path,
+            config: config,
+        };
+        servo.set_angle(0.0)?;
+        fs::write(servo.path.join("enable"), "1")?;
+        Ok(servo)
+    }
+
+    /// Clamp an angle relative to the center to the servo limits.
+    fn clamp(&self, angle: f32) -> f32 {
+        angle.max(self.config.min_angle).min(self.config.max_angle)
+    }
+
+    fn set_angle(&self, angle: f32) -> Result<(), io::Error> {
+        let servo_angle = (self.config.center + angle).max(-90.0).min(90.0);
+        let pulse = SERVO_MIN_PULSE_NS as f32
+            + (servo_angle + 90.0) / 180.0 * (SERVO_MAX_PULSE_NS - SERVO_MIN_PULSE_NS) as f32;
+        fs::write(self.path.join("duty_cycle"), format!("{}", pulse as u32))
+    }
+
+    fn disable(&self) -> Result<(), io::Error> {
+        fs::write(self.path.join("enable"), "0")
+    }
+}
+
+/// Two-servo pan/tilt camera mount.
+pub struct Mount {
+    pan: Servo,
+    tilt: Servo,
+    position: sync::Mutex<MountPosition>,
+}
+
+impl Mount {
+    /// Export PWM channels and move the mount to the center.
+    pub fn new(config: MountConfig) -> Result<Self, Error> {
+        let pan = match Servo::new(&config.pwm_chip, config.pan) {
+            Ok(res) => res,
+            Err(e) => return Err(Error::new(format!("Failed to initialize pan servo: {}", e))),
+        };
+        let tilt = match Servo::new(&config.pwm_chip, config.tilt) {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(Error::new(format!(
+                    "Failed to initialize tilt servo: {}",
+                    e
+                )))
+            }
+        };
+        Ok(Mount {
+            pan: pan,
+            tilt: tilt,
+            position: sync::Mutex::new(MountPosition::default()),
+        })
+    }
+
+    pub fn position(&self) -> MountPosition {
+        *self.position.lock().expect("Failed to lock mount position")
+    }
+
+    /// Move the mount. Angles out of the limits are clamped.
+    pub fn apply(&self, command: &MountCommand) -> Result<MountPosition, Error> {
+        let mut position = self.position.lock().expect("Failed to lock mount position");
+        let (pan, tilt) = match *command {
+            MountCommand::Absolute { pan, tilt } => (pan, tilt),
+            MountCommand::Relative { pan, tilt } => (position.pan + pan, position.tilt + tilt),
+            MountCommand::Center => (0.0, 0.0),
+        };
+        let target = MountPosition {
+            pan: self.pan.clamp(pan),
+            tilt: self.tilt.clamp(tilt),
+        };
+
+        if let Err(e) = self.pan.set_angle(target.pan) {
+            return Err(Error::new(format!("Failed to move pan servo: {}", e)));
+        }
+        if let Err(e) = self.tilt.set_angle(target.tilt) {
+            return Err(Error::new(format!("Failed to move tilt servo: {}", e)));
+        }
+        *position = target;
+        Ok(target)
+    }
+
+    /// Stop driving the servos.
+    pub fn disable(&self) {
+        for servo in [&self.pan, &self.tilt].iter() {
+            if let Err(e) = servo.disable() {
+                error!("Failed to disable servo {:?}: {}", servo.path, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::time;
+
+use crate::common::messages as msg;
+use crate::common::settings;
+
+/// Step of the quality ladder. Higher steps trade image quality for latency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    /// Send every n-th captured frame.
+    pub frame_divider: u64,
+    /// JPEG quality of re-encoded frames, `None` to forward camera frames as is.
+    pub quality: Option<u8>,
+    /// Downscale factor of re-encoded frames.
+    pub scale: f32,
+}
+
+impl Level {
+    /// Bounds and JPEG quality of re-encoded frames for a stream of the given resolution,
+    /// or `None` when camera frames can be sent as is.
+    pub fn encoding(
+        &self,
+        profile: &Option<settings::VideoProfile>,
+        resolution: (u32, u32),
+    ) -> Option<((u32, u32), u8)> {
+        let (bounds, quality) = match (profile, self.quality) {
+            (None, None) => return None,
+            (None, Some(quality)) => (resolution, quality),
+            (Some(profile), None) => (profile.resolution, profile.quality),
+            (Some(profile), Some(quality)) => (profile.resolution, profile.quality.min(quality)),
+        };
+        Some((
+            (
+                (bounds.0 as f32 * self.scale) as u32,
+                (bounds.1 as f32 * self.scale) as u32,
+            ),
+            quality,
+        ))
+    }
+}
+
+const LEVELS: [Level; 5] = [
+    Level {
+        frame_divider: 1,
+        quality: None,
+        scale: 1.0,
+    },
+    Level {
+        frame_divider: 2,
+        quality: None,
+        scale: 1.0,
+    },
+    Level {
+        frame_divider: 2,
+        quality: Some(60),
+        scale: 1.0,
+    },
+    Level {
+        frame_divider: 3,
+        quality: Some(50),
+        scale: 0.5,
+    },
+    Level {
+        frame_divider: 4,
+        quality: Some(35),
+        scale: 0.5,
+    },
+];
+
+/// Time given to a new level before it is changed again, so its effect can be measured.
+const LEVEL_CHANGE_INTERVAL_MS: u64 = 2000;
+/// How long latency must stay well under the target before quality is raised.
+const UPGRADE_INTERVAL_MS: u64 = 5000;
+/// How long frames in flight are limited after the last client report. Without reports,
+/// e.g. from an old client, the count of received frames is unknown.
+const FEEDBACK_TIMEOUT_MS: u64 = 3000;
+
+pub enum Decision {
+    Skip,
+    Send(Level),
+}
+
+/// Keeps a video stream under the latency target using receiver reports.
+///
+/// Frames are dropped while too many of them are in flight and the client keeps
+/// reporting, and the quality level goes down while reported latency is above the
+/// target and up when it recovers.
+pub struct QualityController {
+    max_latency_ms: u32,
+    framerate: u8,
+    level: usize,
+    frames_captured: u64,
+    frames_sent: u64,
+    frames_received: u64,
+    last_feedback: Option<time::Instant>,
+    last_change: time::Instant,
+    good_since: Option<time::Instant>,
+}
+
+impl QualityController {
+    pub fn new(max_latency_ms: u32, framerate: u8) -> Self {
+        QualityController {
+            max_latency_ms: max_latency_ms,
+            framerate: framerate.max(1),
+            level: 0,
+            frames_captured: 0,
+            frames_sent: 0,
+            frames_received: 0,
+            last_feedback: None,
+            last_change: time::Instant::now(),
+            good_since: None,
+        }
+    }
+
+    pub fn set_framerate(&mut self, framerate: u8) {
+        self.framerate = framerate.max(1);
+    }
+
+    /// Update from a client report. Returns `true` when the level is changed.
+    pub fn feedback(&mut self, feedback: &msg::VideoFeedback, now_ms: i64) -> bool {
+        self.frames_received = feedback.frames_received;
+        self.last_feedback = Some(time::Instant::now());
+        if self.max_latency_ms == 0 || feedback.frames_received == 0 {
+            return false;
+        }
+
+        let latency_ms = (now_ms - feedback.last_timestamp_ms).max(0) as u32;
+        let frame_interval_ms = 1000 / self.framerate as u32;
+        let congested = latency_ms > self.max_latency_ms
+            || feedback.decode_ms > frame_interval_ms
+            || feedback.queue_depth > 2;
+        let can_change =
+            self.last_change.elapsed() >= time::Duration::from_millis(LEVEL_CHANGE_INTERVAL_MS);
+
+        if congested {
+            self.good_since = None;
+            if can_change && self.level + 1 < LEVELS.len() {
+                self.level += 1;
+                self.last_change = time::Instant::now();
+                info!(
+                    "Video latency {} ms is over {} ms. Lowering quality to {:?}",
+                    latency_ms,
+                    self.max_latency_ms,
+                    self.level()
+                );
+                return true;
+            }
+        } else if latency_ms < self.max_latency_ms / 2 {
+            let good_since = *self.good_since.get_or_insert(time::Instant::now());
+            if can_change
+                && self.level > 0
+                && good_since.elapsed() >= time::Duration::from_millis(UPGRADE_INTERVAL_MS)
+            {
+                self.level -= 1;
+                self.last_change = time::Instant::now();
+                self.good_since = None;
+                info!(
+                    "Video latency is {} ms. Raising quality to {:?}",
+                    latency_ms,
+                    self.level()
+                );
+                return true;
+            }
+        } else {
+            self.good_since = None;
+        }
+        false
+    }
+
+    pub fn level(&self) -> Level {
+        LEVELS[self.level]
+    }
+
+    /// Decide whether a captured frame is sent and how.
+    pub fn next_frame(&mut self) -> Decision {
+        self.frames_captured += 1;
+        let level = self.level();
+        if self.max_latency_ms == 0 {
+            self.frames_sent += 1;
+            return Decision::Send(level);
+        }
+
+        // Frames the client can process within the latency target.
+        let max_in_flight = (self.framerate as u64 * self.max_latency_ms as u64 / 1000).max(2);
+        let has_feedback = self
+            .last_feedback
+            .is_some_and(|last| last.elapsed() < time::Duration::from_millis(FEEDBACK_TIMEOUT_MS));
+        if has_feedback && self.frames_sent.saturating_sub(self.frames_received) >= max_in_flight {
+            return Decision::Skip;
+        }
+        if self.frames_captured % level.frame_divider != 0 {
+            return Decision::Skip;
+        }
+        self.frames_sent += 1;
+        Decision::Send(level)
+    }
+}
@@ -0,0 +1,190 @@
+use chrono;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::common::messages as msg;
+use avi::AviWriter;
+use utils;
+
+/// Records camera frames into a sequence of AVI clips.
+///
+/// A new clip is started when the current one reaches its maximum length or size.
+/// The oldest clips of the directory are removed to keep it within the size limit.
+pub struct Recorder {
+    dir: PathBuf,
+    camera: String,
+    resolution: (u32, u32),
+    limit: u64,
+    writer: AviWriter,
+    clip: String,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn start(
+        dir: &String,
+        camera: &String,
+        resolution: (u32, u32),
+        limit: u64,
+    ) -> Result<Recorder, io::Error> {
+        fs::create_dir_all(dir)?;
+        let dir = PathBuf::from(dir);
+        let (writer, clip) = open_clip(&dir, camera, resolution, limit)?;
+        Ok(Recorder {
+            dir: dir,
+            camera: camera.clone(),
+            resolution: resolution,
+            limit: limit,
+            writer: writer,
+            clip: clip,
+            started: Instant::now(),
+        })
+    }
+
+    /// Name of the clip being written.
+    pub fn clip(&self) -> &String {
+        &self.clip
+    }
+
+    /// Append a JPEG frame. Returns the name of a new clip when the recording is rotated.
+    pub fn write(&mut self, frame: &[u8], timestamp_ms: i64) -> Result<Option<String>, io::Error> {
+        let rotated = if self.writer.frames() > 0
+            && (self.started.elapsed() >= Duration::from_secs(utils::RECORDING_SEGMENT_SECS)
+                || self.writer.size() + frame.len() as u64 > segment_size(self.limit))
+        {
+            self.rotate()?;
+            Some(self.clip.clone())
+        } else {
+            None
+        };
+        self.writer.write_frame(frame, timestamp_ms)?;
+        Ok(rotated)
+    }
+
+    /// Continue in a new clip when the stream resolution changes.
+    /// Returns the name of the new clip.
+    pub fn set_resolution(&mut self, resolution: (u32, u32)) -> Result<Option<String>, io::Error> {
+        if resolution == self.resolution {
+            return Ok(None);
+        }
+        self.resolution = resolution;
+        self.rotate()?;
+        Ok(Some(self.clip.clone()))
+    }
+
+    /// Finish the current clip and continue in a new one.
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        let (writer, clip) = open_clip(&self.dir, &self.camera, self.resolution, self.limit)?;
+        let finished = std::mem::replace(&mut self.writer, writer);
+        let finished_clip = std::mem::replace(&mut self.clip, clip);
+        self.started = Instant::now();
+        finish(finished, &finished_clip)
+    }
+
+    pub fn stop(self) -> Result<(), io::Error> {
+        finish(self.writer, &self.clip)
+    }
+}
+
+fn finish(writer: AviWriter, clip: &String) -> Result<(), io::Error> {
+    info!(
+        "Finished recording {} ({} frames, {} bytes)",
+        clip,
+        writer.frames(),
+        writer.size()
+    );
+    writer.finish()
+}
+
+fn segment_size(limit: u64) -> u64 {
+    utils::RECORDING_SEGMENT_SIZE.min(limit)
+}
+
+fn open_clip(
+    dir: &PathBuf,
+    camera: &String,
+    resolution: (u32, u32),
+    limit: u64,
+) -> Result<(AviWriter, String), io::Error> {
+    free_space(dir, limit.saturating_sub(segment_size(limit)))?;
+
+    let name = format!(
+        "clip-{}-{}.avi",
+        camera,
+        chrono::Utc::now().format("%Y%m%d-%H%M%S%.3f")
+    );
+    let writer = AviWriter::create(dir.join(&name), resolution)?;
+    info!("Recording camera {:?} to {}", camera, name);
+    Ok((writer, name))
+}
+
+/// Remove the oldest clips until the recordings take at most `size` bytes.
+///
+/// Clips which are still being written are kept.
+fn free_space(dir: &PathBuf, size: u64) -> Result<(), io::Error> {
+    let mut clips = list(dir)?;
+    clips.sort_by_key(|c| c.timestamp_ms);
+    let mut total: u64 = clips.iter().map(|c| c.size).sum();
+
+    let active_since = to_millis(SystemTime::now()) - utils::RECORDING_ACTIVE_MS;
+    for clip in clips {
+        if total <= size || clip.timestamp_ms > active_since {
+            break;
+        }
+        fs::remove_file(dir.join(&clip.name))?;
+        info!("Removed clip {} to free disk space", clip.name);
+        total -= clip.size;
+    }
+    Ok(())
+}
+
+/// List the clips of a recording directory.
+pub fn list<P: Into<PathBuf>>(dir: P) -> Result<Vec<msg::Clip>, io::Error> {
+    let dir = dir.into();
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut clips = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !is_clip_name(&name) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        clips.push(msg::Clip {
+            name: name,
+            size: metadata.len(),
+            timestamp_ms: to_millis(metadata.modified()?),
+        });
+    }
+    clips.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(clips)
+}
+
+/// Open a clip of a recording directory for reading.
+pub fn open<P: Into<PathBuf>>(dir: P, name: &String) -> Result<fs::File, io::Error> {
+    if !is_clip_name(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid clip name: {:?}", name),
+        ));
+    }
+    fs::File::open(dir.into().join(name))
+}
+
+fn is_clip_name(name: &String) -> bool {
+    name.starts_with("clip-") && name.ends_with(".avi") && !name.contains(&['/', '\\'][..])
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,347 @@
+extern crate rhai;
+
+use self::rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Map, FLOAT, INT};
+use simple_error::SimpleError as Error;
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::types::{CameraCommand, MachineState, ScriptRun};
+use crate::failsafe::Failsafe;
+use crate::gps;
+use crate::localization;
+use crate::mission::CameraCommands;
+use crate::mount;
+
+/// Extension of script files.
+const SCRIPT_EXTENSION: &str = "rhai";
+/// How often a sleeping script checks whether it's stopped.
+const TICK_MS: u64 = 10;
+/// Limits which keep a script from exhausting the server memory and stack.
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_COLLECTION_SIZE: usize = 10000;
+
+struct State {
+    run: Option<ScriptRun>,
+    stop: bool,
+}
+
+/// Runs Rhai scripts of a directory as custom behaviors, one at a time.
+///
+/// Scripts only reach the machine through the functions registered here: they can't
+/// touch files, load modules or use the network. A script drives as an autonomous mode,
+/// so manual input and a heartbeat loss stop it like any other mode.
+pub struct Scripts {
+    dir: String,
+    failsafe: sync::Arc<Failsafe>,
+    gps: Option<sync::Arc<gps::Gps>>,
+    mount: Option<sync::Arc<mount::Mount>>,
+    localization: sync::Arc<localization::Localization>,
+    state: sync::Arc<sync::Mutex<State>>,
+}
+
+impl Scripts {
+    pub fn new(
+        dir: String,
+        failsafe: sync::Arc<Failsafe>,
+        gps: Option<sync::Arc<gps::Gps>>,
+        mount: Option<sync::Arc<mount::Mount>>,
+        localization: sync::Arc<localization::Localization>,
+    ) -> Self {
+        Scripts {
+            dir: dir,
+            failsafe: failsafe,
+            gps: gps,
+            mount: mount,
+            localization: localization,
+            state: sync::Arc::new(sync::Mutex::new(State {
+                run: None,
+                stop: false,
+            })),
+        }
+    }
+
+    /// Names of the scripts in the directory without the extension.
+    pub fn list(&self) -> Result<Vec<String>, io::Error> {
+        let dir = PathBuf::from(&self.dir);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut names = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !path.is_file()
+                || path.extension().and_then(|e| e.to_str()) != Some(SCRIPT_EXTENSION)
+            {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Start a script in a background thread. Snapshots are taken by the streams
+    /// of `cameras`.
+    pub fn start(&self, name: &String, cameras: CameraCommands) -> Result<(), Error> {
+        // Only listed names are accepted, so a name can't point outside of the directory.
+        let listed = match self.list() {
+            Ok(names) => names.contains(name),
+            Err(e) => return Err(Error::new(format!("Failed to list scripts: {}", e))),
+        };
+        if !listed {
+            return Err(Error::new(format!("Unknown script {:?}", name)));
+        }
+        let path = PathBuf::from(&self.dir).join(format!("{}.{}", name, SCRIPT_EXTENSION));
+        let source = match fs::read_to_string(&path) {
+            Ok(res) => res,
+            Err(e) => return Err(Error::new(format!("Failed to read {:?}: {}", path, e))),
+        };
+
+        let mut state = lock(&self.state);
+        if let Some(run) = state.run.as_ref().filter(|r| r.running) {
+            return Err(Error::new(format!(
+                "Script {:?} is already running",
+                run.name
+            )));
+        }
+        if self.failsafe.is_autonomous() {
+            return Err(Error::new("Another autonomous mode is driving the machine"));
+        }
+        state.run = Some(ScriptRun {
+            name: name.clone(),
+            running: true,
+            error: None,
+        });
+        state.stop = false;
+        drop(state);
+
+        info!("Starting script {:?}", name);
+        let runner = Runner {
+            autonomy: self.failsafe.start_autonomy(),
+            failsafe: self.failsafe.clone(),
+            gps: self.gps.clone(),
+            mount: self.mount.clone(),
+            localization: self.localization.clone(),
+            state: self.state.clone(),
+            cameras: cameras,
+        };
+        let name = name.clone();
+        thread::spawn(move || runner.run(name, source));
+        Ok(())
+    }
+
+    /// Stop the running script and the engines.
+    pub fn stop(&self) -> Result<(), Error> {
+        let mut state = lock(&self.state);
+        match state.run.as_ref().filter(|r| r.running) {
+            Some(_) => {
+                state.stop = true;
+                Ok(())
+            }
+            None => Err(Error::new("No script is running")),
+        }
+    }
+
+    /// State of the last started script.
+    pub fn status(&self) -> Option<ScriptRun> {
+        lock(&self.state).run.clone()
+    }
+}
+
+/// Resources of a running script.
+#[derive(Clone)]
+struct Runner {
+    /// Token the script drives with.
+    autonomy: usize,
+    failsafe: sync::Arc<Failsafe>,
+    gps: Option<sync::Arc<gps::Gps>>,
+    mount: Option<sync::Arc<mount::Mount>>,
+    localization: sync::Arc<localization::Localization>,
+    state: sync::Arc<sync::Mutex<State>>,
+    cameras: CameraCommands,
+}
+
+impl Runner {
+    fn run(self, name: String, source: String) {
+        let engine = self.engine(&name);
+        let result = engine.run(&source);
+        self.failsafe.stop_autonomy(self.autonomy);
+
+        let error = match result {
+            Ok(_) => {
+                info!("Script {:?} is finished", name);
+                None
+            }
+            Err(e) => {
+                warn!("Script {:?} is stopped: {}", name, e);
+                Some(format!("{}", e))
+            }
+        };
+        if let Some(run) = lock(&self.state).run.as_mut() {
+            run.running = false;
+            run.error = error;
+        }
+    }
+
+    /// Whether a script has to stop, by the client or by a safety stop.
+    fn stopped(&self) -> Option<&'static str> {
+        if lock(&self.state).stop {
+            Some("Stopped by the client")
+        } else if !self.failsafe.owns_autonomy(self.autonomy) {
+            Some("Cancelled by manual input or a heartbeat loss")
+        } else {
+            None
+        }
+    }
+
+    /// Engine with the machine API of scripts:
+    ///
+    /// - `drive(forward, turn)` moves the machine until the next call, `forward` is 1
+    ///   to drive forward, -1 backward and 0 to stand, `turn` is 1 right, -1 left, 0 straight;
+    /// - `stop()` stops the engines;
+    /// - `lamp(enabled)` switches the lamp;
+    /// - `gps()`, `mount()` and `marker()` return the last readings as maps, or `()`;
+    /// - `millis()` returns the time since the script start and `sleep(ms)` waits;
+    /// - `snapshot(camera)` takes a snapshot with a streamed camera;
+    /// - `print(text)` writes to the server log.
+    fn engine(&self, name: &String) -> Engine {
+        let mut engine = Engine::new();
+        engine.set_max_modules(0);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_COLLECTION_SIZE);
+        engine.set_max_map_size(MAX_COLLECTION_SIZE);
+
+        let runner = self.clone();
+        engine.on_progress(move |_| {
+            runner
+                .stopped()
+                .map(|reason| Dynamic::from(reason.to_string()))
+        });
+        let log_name = name.clone();
+        engine.on_print(move |text| info!("Script {:?}: {}", log_name, text));
+
+        // Movement of the script, which is applied again when the lamp changes.
+        let movement = Rc::new(Cell::new(MachineState::new()));
+
+        let (runner, current) = (self.clone(), movement.clone());
+        engine.register_fn("drive", move |forward: INT, turn: INT| {
+            let state = MachineState {
+                forward: forward > 0,
+                backward: forward < 0,
+                left: turn < 0,
+                right: turn > 0,
+                ..MachineState::new()
+            };
+            current.set(state);
+            runner.apply(&state)
+        });
+        let (runner, current) = (self.clone(), movement.clone());
+        engine.register_fn("stop", move || {
+            current.set(MachineState::new());
+            runner.apply(&MachineState::new())
+        });
+        let (runner, current) = (self.clone(), movement.clone());
+        engine.register_fn("lamp", move |enabled: bool| {
+            runner
+                .failsafe
+                .set_autonomous_lamp(runner.autonomy, enabled);
+            runner.apply(&current.get())
+        });
+
+        let gps = self.gps.clone();
+        engine.register_fn("gps", move || {
+            match gps.as_ref().and_then(|gps| gps.fix()) {
+                Some(fix) => {
+                    let mut map = Map::new();
+                    map.insert("has_fix".into(), Dynamic::from(fix.has_fix));
+                    map.insert("latitude".into(), Dynamic::from(fix.latitude as FLOAT));
+                    map.insert("longitude".into(), Dynamic::from(fix.longitude as FLOAT));
+                    map.insert("altitude".into(), Dynamic::from(fix.altitude as FLOAT));
+                    map.insert("speed_kmh".into(), Dynamic::from(fix.speed_kmh as FLOAT));
+                    map.insert("satellites".into(), Dynamic::from(fix.satellites as INT));
+                    Dynamic::from(map)
+                }
+                None => Dynamic::from(()),
+            }
+        });
+        let mount = self.mount.clone();
+        engine.register_fn("mount", move || match &mount {
+            Some(mount) => {
+                let position = mount.position();
+                let mut map = Map::new();
+                map.insert("pan".into(), Dynamic::from(position.pan as FLOAT));
+                map.insert("tilt".into(), Dynamic::from(position.tilt as FLOAT));
+                Dynamic::from(map)
+            }
+            None => Dynamic::from(()),
+        });
+        let localization = self.localization.clone();
+        engine.register_fn("marker", move || match localization.fix() {
+            Some(fix) => {
+                let mut map = Map::new();
+                map.insert("payload".into(), Dynamic::from(fix.payload));
+                map.insert("x".into(), Dynamic::from(fix.x as FLOAT));
+                map.insert("y".into(), Dynamic::from(fix.y as FLOAT));
+                map.insert("size".into(), Dynamic::from(fix.size as FLOAT));
+                Dynamic::from(map)
+            }
+            None => Dynamic::from(()),
+        });
+
+        let started = Instant::now();
+        engine.register_fn("millis", move || started.elapsed().as_millis() as INT);
+        let runner = self.clone();
+        engine.register_fn(
+            "sleep",
+            move |ms: INT| -> Result<Dynamic, Box<EvalAltResult>> {
+                let until = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+                while Instant::now() < until {
+                    if let Some(reason) = runner.stopped() {
+                        return Err(reason.into());
+                    }
+                    thread::sleep(Duration::from_millis(TICK_MS));
+                }
+                Ok(Dynamic::from(()))
+            },
+        );
+        let cameras = self.cameras.clone();
+        engine.register_fn("snapshot", move |camera: ImmutableString| {
+            let cameras = cameras.lock().expect("Failed to lock camera commands");
+            match cameras.get(camera.as_str()) {
+                Some(sender) => sender.send(CameraCommand::Snapshot).is_ok(),
+                None => {
+                    warn!("Camera {:?} is not streaming. Snapshot is skipped", camera);
+                    false
+                }
+            }
+        });
+        engine
+    }
+
+    fn apply(&self, state: &MachineState) -> Result<Dynamic, Box<EvalAltResult>> {
+        if let Some(reason) = self.stopped() {
+            return Err(reason.into());
+        }
+        if !self.failsafe.apply_autonomous(self.autonomy, state) {
+            return Err("Cancelled by manual input or a heartbeat loss".into());
+        }
+        Ok(Dynamic::from(()))
+    }
+}
+
+fn lock(state: &sync::Arc<sync::Mutex<State>>) -> sync::MutexGuard<'_, State> {
+    state.lock().expect("Failed to lock script state")
+}
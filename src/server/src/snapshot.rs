@@ -0,0 +1,28 @@
+use chrono;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::common::messages as msg;
+
+/// Store a snapshot in `dir` as a JPEG file with a text file of its metadata next to it.
+///
+/// Returns the path of the image.
+pub fn save(dir: &String, snapshot: &msg::Snapshot) -> Result<PathBuf, io::Error> {
+    fs::create_dir_all(dir)?;
+
+    let duration =
+        std::time::UNIX_EPOCH + std::time::Duration::from_millis(snapshot.timestamp_ms as u64);
+    let datetime = chrono::DateTime::<chrono::Utc>::from(duration);
+    let name = format!(
+        "snapshot-{}-{}",
+        snapshot.camera,
+        datetime.format("%Y%m%d-%H%M%S%.3f")
+    );
+
+    let dir = PathBuf::from(dir);
+    let path = dir.join(format!("{}.jpg", name));
+    fs::write(&path, &snapshot.data)?;
+    fs::write(dir.join(format!("{}.txt", name)), snapshot.metadata())?;
+    Ok(path)
+}
@@ -0,0 +1,390 @@
+use chrono;
+use simple_error::SimpleError as Error;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::types::{Orientation, TimelapseFormat};
+use avi::AviWriter;
+use camera;
+
+/// Frames captured after the camera is opened, so exposure can settle.
+const WARMUP_FRAMES: u32 = 5;
+/// Frame rate of timelapse videos.
+const PLAYBACK_FPS: i64 = 25;
+/// Shortest allowed capture interval in seconds.
+pub const MIN_INTERVAL_S: u32 = 1;
+/// How often a stream opening a camera checks whether its capture is finished.
+const CAPTURE_POLL_MS: u64 = 10;
+
+enum Output {
+    /// Directory of numbered JPEG files.
+    Images(PathBuf),
+    Mjpeg(AviWriter),
+}
+
+struct Job {
+    interval: Duration,
+    /// Resolution the camera is captured at.
+    resolution: (u32, u32),
+    /// Size of the upright frames written to the output.
+    size: (u32, u32),
+    last_capture: Option<Instant>,
+    frames: u32,
+    output: Output,
+    name: String,
+}
+
+impl Job {
+    fn is_due(&self) -> bool {
+        match self.last_capture {
+            Some(time) => time.elapsed() >= self.interval,
+            None => true,
+        }
+    }
+
+    /// Write a frame captured at `resolution`, which still needs the `orientation`
+    /// transform to be upright.
+    fn write(
+        &mut self,
+        frame: &[u8],
+        resolution: (u32, u32),
+        orientation: Orientation,
+    ) -> Result<(), io::Error> {
+        // Frames of a video stream may come at another resolution than the job has,
+        // and all frames of a video must have its size.
+        let transformed;
+        let data = if resolution != self.resolution || orientation != Orientation::default() {
+            match camera::reorient(frame, orientation, self.size, camera::REGION_QUALITY) {
+                Ok(res) => {
+                    transformed = res;
+                    &transformed[..]
+                }
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("{}", e))),
+            }
+        } else {
+            frame
+        };
+        self.last_capture = Some(Instant::now());
+        self.frames += 1;
+        match &mut self.output {
+            Output::Images(dir) => {
+                fs::write(dir.join(format!("frame-{:06}.jpg", self.frames)), data)
+            }
+            // Timestamps of the playback rate make the file play as a timelapse.
+            Output::Mjpeg(writer) => {
+                writer.write_frame(data, self.frames as i64 * 1000 / PLAYBACK_FPS)
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), io::Error> {
+        info!("Finished timelapse {} ({} frames)", self.name, self.frames);
+        match self.output {
+            Output::Images(_) => Ok(()),
+            Output::Mjpeg(writer) => writer.finish(),
+        }
+    }
+}
+
+struct State {
+    jobs: HashMap<String, Job>,
+    /// Cameras opened by video streams.
+    streaming: HashSet<String>,
+    /// Cameras opened by the timelapse thread.
+    capturing: HashSet<String>,
+}
+
+/// Captures frames of cameras at fixed intervals, independently of connected clients.
+///
+/// While a camera is streamed, frames are taken from the stream. Otherwise the camera
+/// is opened for every capture, so it's free for a stream to open it in between.
+pub struct Timelapse {
+    dir: String,
+    cameras: Vec<camera::CameraConfig>,
+    state: sync::Arc<sync::Mutex<State>>,
+}
+
+/// Marks a camera as opened by a video stream until it is dropped.
+pub struct StreamGuard {
+    camera: String,
+    state: sync::Arc<sync::Mutex<State>>,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        lock(&self.state).streaming.remove(&self.camera);
+    }
+}
+
+impl Timelapse {
+    /// Start a background thread which captures frames of cameras without a stream.
+    pub fn new(dir: String, cameras: Vec<camera::CameraConfig>) -> Self {
+        let state = sync::Arc::new(sync::Mutex::new(State {
+            jobs: HashMap::new(),
+            streaming: HashSet::new(),
+            capturing: HashSet::new(),
+        }));
+
+        let worker_state = state.clone();
+        let worker_cameras = cameras.clone();
+        thread::spawn(move || loop {
+            Timelapse::capture_due(&worker_state, &worker_cameras);
+            thread::sleep(Duration::from_millis(100));
+        });
+
+        Timelapse {
+            dir: dir,
+            cameras: cameras,
+            state: state,
+        }
+    }
+
+    /// Start a timelapse of a camera and return the name of its output.
+    pub fn start(
+        &self,
+        camera: &String,
+        interval_s: u32,
+        format: TimelapseFormat,
+    ) -> Result<String, Error> {
+        let config = match self.cameras.iter().find(|c| &c.name == camera) {
+            Some(res) => res,
+            None => return Err(Error::new(format!("Unknown camera {:?}", camera))),
+        };
+        if interval_s < MIN_INTERVAL_S {
+            return Err(Error::new(format!(
+                "Timelapse interval must be at least {} s",
+                MIN_INTERVAL_S
+            )));
+        }
+
+        let mut state = lock(&self.state);
+        if let Some(job) = state.jobs.get(camera) {
+            return Err(Error::new(format!(
+                "Timelapse {} is already running",
+                job.name
+            )));
+        }
+
+        let resolution = largest_resolution(config);
+        // The camera itself only flips, so rotated frames have the other aspect.
+        let size = match config.orientation.rotate {
+            90 | 270 => (resolution.1, resolution.0),
+            _ => resolution,
+        };
+        let name = format!(
+            "timelapse-{}-{}",
+            camera,
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        );
+        let dir = PathBuf::from(&self.dir);
+        let created = fs::create_dir_all(&dir).and_then(|_| match format {
+            TimelapseFormat::Images => {
+                let path = dir.join(&name);
+                fs::create_dir_all(&path)?;
+                Ok((Output::Images(path), name.clone()))
+            }
+            TimelapseFormat::Mjpeg => {
+                let file_name = format!("{}.avi", name);
+                let writer = AviWriter::create(dir.join(&file_name), size)?;
+                Ok((Output::Mjpeg(writer), file_name))
+            }
+        });
+        let (output, name) = match created {
+            Ok(res) => res,
+            Err(e) => return Err(Error::new(format!("Failed to create timelapse: {}", e))),
+        };
+
+        info!(
+            "Starting timelapse {} of camera {:?} every {} s",
+            name, camera, interval_s
+        );
+        state.jobs.insert(
+            camera.clone(),
+            Job {
+                interval: Duration::from_secs(interval_s as u64),
+                resolution: resolution,
+                size: size,
+                last_capture: None,
+                frames: 0,
+                output: output,
+                name: name.clone(),
+            },
+        );
+        Ok(name)
+    }
+
+    pub fn stop(&self, camera: &String) -> Result<(), Error> {
+        let job = lock(&self.state).jobs.remove(camera);
+        match job {
+            Some(job) => job
+                .finish()
+                .map_err(|e| Error::new(format!("Failed to finish timelapse: {}", e))),
+            None => Err(Error::new(format!(
+                "No timelapse of camera {:?} is running",
+                camera
+            ))),
+        }
+    }
+
+    /// Finish all timelapses, e.g. when the server stops.
+    pub fn stop_all(&self) {
+        let jobs: Vec<Job> = lock(&self.state).jobs.drain().map(|(_, job)| job).collect();
+        for job in jobs {
+            if let Err(e) = job.finish() {
+                error!("Failed to finish timelapse: {}", e);
+            }
+        }
+    }
+
+    /// Cameras with a running timelapse.
+    pub fn cameras(&self) -> Vec<String> {
+        let mut cameras: Vec<String> = lock(&self.state).jobs.keys().cloned().collect();
+        cameras.sort();
+        cameras
+    }
+
+    /// Mark a camera as opened by a video stream, so the timelapse takes its frames
+    /// from the stream instead of opening the camera.
+    ///
+    /// Waits until a capture of the camera in progress is finished.
+    pub fn acquire(&self, camera: &String) -> StreamGuard {
+        loop {
+            let mut state = lock(&self.state);
+            if !state.capturing.contains(camera) {
+                state.streaming.insert(camera.clone());
+                break;
+            }
+            drop(state);
+            thread::sleep(Duration::from_millis(CAPTURE_POLL_MS));
+        }
+        StreamGuard {
+            camera: camera.clone(),
+            state: self.state.clone(),
+        }
+    }
+
+    /// Offer a frame of a video stream to the timelapse of its camera.
+    ///
+    /// Doesn't wait while another camera is captured; the next frame is used instead.
+    ///
+    /// `orientation` is the transform left after the camera flipped the frame.
+    pub fn offer(
+        &self,
+        camera: &String,
+        frame: &[u8],
+        resolution: (u32, u32),
+        orientation: Orientation,
+    ) {
+        let mut state = match self.state.try_lock() {
+            Ok(res) => res,
+            Err(_) => return,
+        };
+        let failed = match state.jobs.get_mut(camera) {
+            Some(job) if job.is_due() => job.write(frame, resolution, orientation).err(),
+            _ => None,
+        };
+        if let Some(e) = failed {
+            error!("Timelapse of camera {:?} is stopped: {}", camera, e);
+            if let Some(job) = state.jobs.remove(camera) {
+                let _ = job.finish();
+            }
+        }
+    }
+
+    /// Capture the due jobs of cameras without a stream.
+    ///
+    /// The state isn't locked while a camera is captured, so streams and status
+    /// requests don't wait for it.
+    fn capture_due(state: &sync::Arc<sync::Mutex<State>>, cameras: &Vec<camera::CameraConfig>) {
+        let due: Vec<String> = lock(state)
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.is_due())
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in due {
+            let config = match cameras.iter().find(|c| c.name == name) {
+                Some(res) => res,
+                None => continue,
+            };
+            let resolution = {
+                let mut state = lock(state);
+                if state.streaming.contains(&name) {
+                    continue;
+                }
+                let resolution = match state.jobs.get(&name) {
+                    Some(job) => job.resolution,
+                    None => continue,
+                };
+                state.capturing.insert(name.clone());
+                resolution
+            };
+
+            let result = capture(config, resolution);
+
+            let mut state = lock(state);
+            state.capturing.remove(&name);
+            // The job may be stopped or replaced during the capture.
+            let failed = match (state.jobs.get_mut(&name), result) {
+                (Some(job), Ok((frame, orientation))) => {
+                    job.write(&frame, resolution, orientation).err()
+                }
+                (Some(job), Err(e)) => {
+                    // Try again after the interval.
+                    warn!("Timelapse of camera {:?} failed to capture: {}", name, e);
+                    job.last_capture = Some(Instant::now());
+                    None
+                }
+                (None, _) => None,
+            };
+            if let Some(e) = failed {
+                error!("Timelapse of camera {:?} is stopped: {}", name, e);
+                if let Some(job) = state.jobs.remove(&name) {
+                    let _ = job.finish();
+                }
+            }
+        }
+    }
+}
+
+/// Open the camera, take a frame and release the camera.
+///
+/// Returns the frame with the transform left after the camera flipped it.
+fn capture(
+    config: &camera::CameraConfig,
+    resolution: (u32, u32),
+) -> Result<(Vec<u8>, Orientation), Error> {
+    let mut source = camera::open(&camera::StreamConfig {
+        device: config.device.clone(),
+        resolution: resolution,
+        framerate: config.max_framerate,
+    })?;
+    let orientation = camera::apply_orientation(&mut source, config.orientation);
+    let mut frame = source.capture();
+    for _ in 1..WARMUP_FRAMES {
+        frame = source.capture();
+    }
+    match frame {
+        Ok(res) => Ok((res.to_vec(), orientation)),
+        Err(e) => Err(Error::new(format!("Unable to take picture: {}", e))),
+    }
+}
+
+fn largest_resolution(config: &camera::CameraConfig) -> (u32, u32) {
+    config
+        .resolutions
+        .iter()
+        .max_by_key(|(width, height)| width * height)
+        .cloned()
+        .unwrap_or((640, 480))
+}
+
+fn lock(state: &sync::Arc<sync::Mutex<State>>) -> sync::MutexGuard<'_, State> {
+    state.lock().expect("Failed to lock timelapse state")
+}
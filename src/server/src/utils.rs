@@ -4,8 +4,14 @@ extern crate log4rs;
 extern crate log_panics;
 extern crate simple_error;
 
+use camera::CameraConfig;
+use common::types::LossPolicy;
+use config::{Config as ConfigLoader, File as ConfigFile};
+use localization::MarkerConfig;
 use log;
 use log4rs::{append, config, encode};
+use mount::MountConfig;
+use serde::Deserialize;
 use simple_error::SimpleError as Error;
 use std::env;
 use std::error;
@@ -35,10 +41,59 @@ pub fn init_logger() -> Result<(), Box<dyn error::Error>> {
 }
 
 const DEFAULT_PORT: u16 = 20301;
+const DEFAULT_COAST_TIMEOUT: u32 = 1000;
+const DEFAULT_RETURN_TIMEOUT: u32 = 10000;
+const DEFAULT_JOURNAL_DIR: &str = "/var/log/rc.journal";
+const DEFAULT_SNAPSHOT_DIR: &str = "/var/lib/rc.snapshots";
+const DEFAULT_RECORDING_DIR: &str = "/var/lib/rc.recordings";
+const DEFAULT_RECORDING_LIMIT_MB: u64 = 4096;
+const DEFAULT_TIMELAPSE_DIR: &str = "/var/lib/rc.timelapse";
+const DEFAULT_SCRIPT_DIR: &str = "/var/lib/rc.scripts";
+const DEFAULT_TURN_RATE: f32 = 90.0;
+const DEFAULT_CONFIG_PATH: &str = "Server.toml";
+
+/// Maximum size of a single journal file in bytes.
+pub const JOURNAL_FILE_SIZE: u64 = 4 * 1024 * 1024;
+/// How many journal files are kept.
+pub const JOURNAL_FILES: usize = 8;
+/// Maximum length of a recorded clip in seconds.
+pub const RECORDING_SEGMENT_SECS: u64 = 300;
+/// Maximum size of a recorded clip in bytes.
+pub const RECORDING_SEGMENT_SIZE: u64 = 512 * 1024 * 1024;
+/// Clips modified within this time in milliseconds are considered being recorded.
+pub const RECORDING_ACTIVE_MS: i64 = 10000;
 
 pub struct Config {
     token: String,
     pub port: u16,
+    pub loss_policy: LossPolicy,
+    pub gps_device: Option<String>,
+    pub gps_track: Option<String>,
+    pub journal_dir: String,
+    pub snapshot_dir: String,
+    pub recording_dir: String,
+    /// Maximum size of all recorded clips in bytes.
+    pub recording_limit: u64,
+    pub timelapse_dir: String,
+    /// Directory of the scripts clients may run.
+    pub script_dir: String,
+    /// Degrees per second the machine turns in place, used to turn by an angle.
+    pub turn_rate: f32,
+    pub cameras: Vec<CameraConfig>,
+    pub mount: Option<MountConfig>,
+    /// Markers with a known position.
+    pub markers: Vec<MarkerConfig>,
+}
+
+/// Settings of the server configuration file.
+#[derive(Deserialize, Default)]
+struct FileConfig {
+    #[serde(default)]
+    cameras: Vec<CameraConfig>,
+    #[serde(default)]
+    mount: Option<MountConfig>,
+    #[serde(default)]
+    markers: Vec<MarkerConfig>,
 }
 
 impl Config {
@@ -74,12 +129,132 @@ impl Config {
             }
         };
 
+        let loss_policy = Config::loss_policy_from_env()?;
+        info!("Connection loss policy: {}", loss_policy);
+
+        let gps_device = env::var("RC_GPS_DEVICE").ok();
+        if gps_device.is_none() {
+            debug!("RC_GPS_DEVICE environment variable missing. GPS is disabled.");
+        }
+
+        let recording_limit_mb: u64 = match env::var("RC_RECORDING_LIMIT") {
+            Ok(value) => match value.parse::<u64>() {
+                Ok(res) => res,
+                Err(_) => {
+                    return Err(Error::new(
+                        "Invalid integer value for RC_RECORDING_LIMIT environment variable.",
+                    ))
+                }
+            },
+            Err(_) => DEFAULT_RECORDING_LIMIT_MB,
+        };
+
+        let turn_rate: f32 = match env::var("RC_TURN_RATE") {
+            Ok(value) => match value.parse::<f32>() {
+                Ok(res) if res > 0.0 => res,
+                _ => {
+                    return Err(Error::new(
+                        "Invalid positive number for RC_TURN_RATE environment variable.",
+                    ))
+                }
+            },
+            Err(_) => DEFAULT_TURN_RATE,
+        };
+
+        let config_path = env::var("RC_CONFIG").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+        let file_config = Config::load_file(&config_path)?;
+        if file_config.cameras.is_empty() {
+            warn!(
+                "No cameras configured in {}. Video is disabled.",
+                config_path
+            );
+        }
+
         Ok(Config {
             token: token,
             port: port,
+            loss_policy: loss_policy,
+            gps_device: gps_device,
+            gps_track: env::var("RC_GPS_TRACK").ok(),
+            journal_dir: env::var("RC_JOURNAL_DIR").unwrap_or(DEFAULT_JOURNAL_DIR.to_string()),
+            snapshot_dir: env::var("RC_SNAPSHOT_DIR").unwrap_or(DEFAULT_SNAPSHOT_DIR.to_string()),
+            recording_dir: env::var("RC_RECORDING_DIR")
+                .unwrap_or(DEFAULT_RECORDING_DIR.to_string()),
+            recording_limit: recording_limit_mb * 1024 * 1024,
+            timelapse_dir: env::var("RC_TIMELAPSE_DIR")
+                .unwrap_or(DEFAULT_TIMELAPSE_DIR.to_string()),
+            script_dir: env::var("RC_SCRIPT_DIR").unwrap_or(DEFAULT_SCRIPT_DIR.to_string()),
+            turn_rate: turn_rate,
+            cameras: file_config.cameras,
+            mount: file_config.mount,
+            markers: file_config.markers,
         })
     }
 
+    fn load_file(path: &String) -> Result<FileConfig, Error> {
+        let mut loader = ConfigLoader::new();
+        match loader.merge(ConfigFile::with_name(path).required(false)) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::new(format!("Failed to read {}: {}", path, e))),
+        }
+        match loader.try_into::<FileConfig>() {
+            Ok(res) => {
+                for camera in res.cameras.iter() {
+                    if !camera.orientation.is_valid() {
+                        return Err(Error::new(format!(
+                            "Invalid rotation {} of camera {:?} in {}. Use 0, 90, 180 or 270.",
+                            camera.orientation.rotate, camera.name, path
+                        )));
+                    }
+                    if camera.max_framerate == 0 {
+                        return Err(Error::new(format!(
+                            "Invalid max_framerate 0 of camera {:?} in {}",
+                            camera.name, path
+                        )));
+                    }
+                }
+                Ok(res)
+            }
+            Err(e) => Err(Error::new(format!(
+                "Invalid configuration in {}: {}",
+                path, e
+            ))),
+        }
+    }
+
+    fn loss_policy_from_env() -> Result<LossPolicy, Error> {
+        let timeout: Option<u32> = match env::var("RC_LOSS_TIMEOUT") {
+            Ok(value) => match value.parse::<u32>() {
+                Ok(res) => Some(res),
+                Err(_) => {
+                    return Err(Error::new(
+                        "Invalid integer value for RC_LOSS_TIMEOUT environment variable.",
+                    ))
+                }
+            },
+            Err(_) => None,
+        };
+
+        match env::var("RC_LOSS_POLICY") {
+            Ok(value) => match value.as_str() {
+                "stop" => Ok(LossPolicy::Stop),
+                "coast" => Ok(LossPolicy::Coast(
+                    timeout.unwrap_or(DEFAULT_COAST_TIMEOUT),
+                )),
+                "return" => Ok(LossPolicy::ReturnHome(
+                    timeout.unwrap_or(DEFAULT_RETURN_TIMEOUT),
+                )),
+                _ => Err(Error::new(
+                    "Invalid RC_LOSS_POLICY environment variable. Expected one of: stop, coast, return.",
+                )),
+            },
+            Err(_) => {
+                debug!("RC_LOSS_POLICY environment variable missing. Use default stop policy.");
+                Ok(LossPolicy::Stop)
+            }
+        }
+    }
+
     pub fn is_valid_token(&self, token: String) -> bool {
         self.token == token
     }